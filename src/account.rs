@@ -1,143 +1,634 @@
 use crate::{amount::Amount, error::Errors};
 use anyhow::Result;
-use rust_decimal::Decimal;
-use serde::ser::SerializeStruct;
-use serde::{Serialize, Serializer};
+use rust_decimal::{Decimal, RoundingStrategy};
+use serde::ser::{Error, SerializeStruct};
+use serde::{Deserialize, Serialize, Serializer};
 
-#[derive(Debug, PartialEq, Serialize)]
+// a locked account is frozen for the client: deposits and withdrawals are rejected, while the
+// dispute family operations (dispute, resolve, chargeback) are still applied, since they settle
+// the transactions made before the lock. A lock (by a chargeback) is permanent, while a frozen
+// account (by an admin) behaves the same, until it gets unfrozen; both are output as locked
+#[derive(Debug, Default, PartialEq, Serialize)]
 enum AccountState {
     #[serde(rename = "true")]
     Locked,
+    #[serde(rename = "true")]
+    Frozen,
     #[serde(rename = "false")]
+    #[default]
     Unlocked,
 }
 
-impl Default for AccountState {
-    fn default() -> Self {
-        AccountState::Unlocked
-    }
-}
-
 #[derive(Debug)]
 pub struct Account {
     client_id: u16,
+    // the asset the funds are in, `None` for an input without the `currency` column
+    currency: Option<String>,
     available: Decimal,
     held: Decimal,
     locked: AccountState,
+    // how far below zero a withdrawal might take the available funds
+    credit_limit: Decimal,
+    // the cap of the total funds, checked by the operations increasing it
+    max_balance: Option<Decimal>,
+    // applied disputes and chargebacks of the account's transactions, for fraud scoring
+    dispute_count: u32,
+    chargeback_count: u32,
+    // the highest held funds ever reached, for liquidity planning
+    peak_held: Decimal,
+    overflow_policy: OverflowPolicy,
 }
 
-impl Serialize for Account {
+// optional knobs of the account output, the defaults produce the plain five columns output
+#[derive(Debug, Default, Clone, Copy)]
+pub struct OutputOptions {
+    // adds the `currency` column, for the accounts of a multi-asset input
+    pub currency_column: bool,
+    pub held_exceeds_available: bool,
+    // adds the `dispute_count` and `chargeback_count` columns
+    pub fraud_columns: bool,
+    // adds the `peak_held` column
+    pub peak_held: bool,
+    pub client_width: Option<usize>,
+    // decimal places of the amounts, `DEFAULT_PRECISION` when not set
+    pub precision: Option<u32>,
+    pub rounding: Rounding,
+}
+
+const DEFAULT_PRECISION: u32 = 4;
+
+impl OutputOptions {
+    // the header row of the CSV output, in the order `AccountOutput` writes the fields; written
+    // separately, so even an output without any account has one
+    pub fn columns(&self) -> Vec<&'static str> {
+        let mut columns = vec!["client"];
+        if self.currency_column {
+            columns.push("currency");
+        }
+        columns.extend(["available", "held", "total", "locked"]);
+        if self.held_exceeds_available {
+            columns.push("held_exceeds_available");
+        }
+        if self.fraud_columns {
+            columns.extend(["dispute_count", "chargeback_count"]);
+        }
+        if self.peak_held {
+            columns.push("peak_held");
+        }
+        columns
+    }
+}
+
+/// What happens when the funds of an account don't fit into a Decimal
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum OverflowPolicy {
+    /// the operation is rejected with `FundsOverflow`, the account stays untouched
+    #[default]
+    Error,
+    /// the funds are clamped to `Decimal::MAX` (or `Decimal::MIN`), the part of the amount beyond it
+    /// is lost, so the balances (and the total) no longer add up to the applied transactions
+    Saturate,
+}
+
+impl std::str::FromStr for OverflowPolicy {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "error" => Ok(OverflowPolicy::Error),
+            "saturate" => Ok(OverflowPolicy::Saturate),
+            _ => Err("the overflow policy has to be either `error` or `saturate`".to_string()),
+        }
+    }
+}
+
+/// How the output amounts are rounded to the precision
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum Rounding {
+    /// to the nearest, a midpoint towards the even neighbour (banker's rounding)
+    #[default]
+    HalfEven,
+    /// to the nearest, a midpoint away from zero
+    HalfUp,
+    /// towards zero, the extra decimal places are just dropped
+    Truncate,
+}
+
+impl std::str::FromStr for Rounding {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "half-even" => Ok(Rounding::HalfEven),
+            "half-up" => Ok(Rounding::HalfUp),
+            "truncate" => Ok(Rounding::Truncate),
+            _ => Err(
+                "the rounding has to be one of `half-even`, `half-up`, or `truncate`".to_string(),
+            ),
+        }
+    }
+}
+
+// rounds with the chosen rounding, and pads the amount with zeros up to the precision
+fn format_amount(amount: Decimal, options: &OutputOptions) -> String {
+    let precision = options.precision.unwrap_or(DEFAULT_PRECISION);
+    let strategy = match options.rounding {
+        Rounding::HalfEven => RoundingStrategy::MidpointNearestEven,
+        Rounding::HalfUp => RoundingStrategy::MidpointAwayFromZero,
+        Rounding::Truncate => RoundingStrategy::ToZero,
+    };
+    // padded by hand, as rust_decimal can't format the largest amounts with a precision
+    let rounded = amount.round_dp_with_strategy(precision, strategy);
+    let mut formatted = rounded.to_string();
+    if rounded.scale() < precision {
+        if rounded.scale() == 0 {
+            formatted.push('.');
+        }
+        formatted.extend(std::iter::repeat_n(
+            '0',
+            (precision - rounded.scale()) as usize,
+        ));
+    }
+    formatted
+}
+
+// an account serialized with the given output options
+pub struct AccountOutput<'a> {
+    account: &'a Account,
+    options: OutputOptions,
+}
+
+impl Serialize for AccountOutput<'_> {
     fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
     where
         S: Serializer,
     {
-        let mut state = serializer.serialize_struct("Account", 5)?;
-        state.serialize_field("client", &self.client_id)?;
-        state.serialize_field("available", &format!("{:.04}", self.available.round_dp(4)))?;
-        state.serialize_field("held", &format!("{:.04}", self.held.round_dp(4)))?;
-        state.serialize_field(
-            "total",
-            &format!("{:.04}", (self.available + self.held).round_dp(4)),
-        )?;
-        state.serialize_field("locked", &self.locked)?;
+        let account = self.account;
+        //checked before anything gets serialized, so an overflowing account produces no partial row
+        let total = account
+            .total()
+            .ok_or_else(|| S::Error::custom(Errors::FundsOverflow(account.client_id)))?;
+        let fields = 5
+            + usize::from(self.options.currency_column)
+            + usize::from(self.options.held_exceeds_available)
+            + 2 * usize::from(self.options.fraud_columns)
+            + usize::from(self.options.peak_held);
+        let mut state = serializer.serialize_struct("Account", fields)?;
+        match self.options.client_width {
+            Some(width) => state.serialize_field(
+                "client",
+                &format!("{:0width$}", account.client_id, width = width),
+            )?,
+            None => state.serialize_field("client", &account.client_id)?,
+        }
+        if self.options.currency_column {
+            state.serialize_field("currency", &account.currency)?;
+        }
+        let options = &self.options;
+        state.serialize_field("available", &format_amount(account.available, options))?;
+        state.serialize_field("held", &format_amount(account.held, options))?;
+        state.serialize_field("total", &format_amount(total, options))?;
+        state.serialize_field("locked", &account.locked)?;
+        if self.options.held_exceeds_available {
+            state.serialize_field(
+                "held_exceeds_available",
+                &(account.held > account.available),
+            )?;
+        }
+        if self.options.fraud_columns {
+            state.serialize_field("dispute_count", &account.dispute_count)?;
+            state.serialize_field("chargeback_count", &account.chargeback_count)?;
+        }
+        if self.options.peak_held {
+            state.serialize_field("peak_held", &format_amount(account.peak_held, options))?;
+        }
         state.end()
     }
 }
 
+// an account serialized for the JSON output, `locked` being a real boolean there;
+// out of the output options only the currency column, the precision, and the rounding apply to it
+pub struct AccountJson<'a> {
+    account: &'a Account,
+    options: OutputOptions,
+}
+
+impl Serialize for AccountJson<'_> {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        let account = self.account;
+        let total = account
+            .total()
+            .ok_or_else(|| S::Error::custom(Errors::FundsOverflow(account.client_id)))?;
+        let fields = 5 + usize::from(self.options.currency_column);
+        let mut state = serializer.serialize_struct("Account", fields)?;
+        //a padded client is a string, just as it's text in the CSV output
+        match self.options.client_width {
+            Some(width) => state.serialize_field(
+                "client",
+                &format!("{:0width$}", account.client_id, width = width),
+            )?,
+            None => state.serialize_field("client", &account.client_id)?,
+        }
+        if self.options.currency_column {
+            state.serialize_field("currency", &account.currency)?;
+        }
+        //decimals are kept as strings, so no float rounding happens on the consumer side
+        let options = &self.options;
+        state.serialize_field("available", &format_amount(account.available, options))?;
+        state.serialize_field("held", &format_amount(account.held, options))?;
+        state.serialize_field("total", &format_amount(total, options))?;
+        state.serialize_field("locked", &(account.is_locked() || account.is_frozen()))?;
+        state.end()
+    }
+}
+
+// a single line for eyeballing, e.g. `client=1 available=12.3400 held=0.0000 total=12.3400 locked=false`;
+// the amounts are rounded to the default precision, a frozen account is shown as locked
+impl std::fmt::Display for Account {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let options = OutputOptions::default();
+        write!(f, "client={}", self.client_id)?;
+        if let Some(currency) = &self.currency {
+            write!(f, " currency={}", currency)?;
+        }
+        write!(
+            f,
+            " available={} held={} total={} locked={}",
+            format_amount(self.available, &options),
+            format_amount(self.held, &options),
+            self.total()
+                .map_or("overflow".to_string(), |total| format_amount(
+                    total, &options
+                )),
+            self.is_locked() || self.is_frozen()
+        )
+    }
+}
+
+impl Serialize for Account {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        self.output(Default::default()).serialize(serializer)
+    }
+}
+
+// the state of an account in a manager snapshot, unlike the output it keeps the exact funds and
+// tells a lock from a freeze; the limits are configuration, restored from the manager
+#[derive(Debug, Serialize, Deserialize)]
+pub(crate) struct AccountSnapshot {
+    client: u16,
+    #[serde(default)]
+    currency: Option<String>,
+    available: Decimal,
+    held: Decimal,
+    locked: bool,
+    frozen: bool,
+    dispute_count: u32,
+    chargeback_count: u32,
+    #[serde(default)]
+    peak_held: Decimal,
+}
+
+impl From<&Account> for AccountSnapshot {
+    fn from(account: &Account) -> Self {
+        Self {
+            client: account.client_id,
+            currency: account.currency.clone(),
+            available: account.available,
+            held: account.held,
+            locked: account.is_locked(),
+            frozen: account.is_frozen(),
+            dispute_count: account.dispute_count,
+            chargeback_count: account.chargeback_count,
+            peak_held: account.peak_held,
+        }
+    }
+}
+
+impl From<AccountSnapshot> for Account {
+    fn from(snapshot: AccountSnapshot) -> Self {
+        let locked = match (snapshot.locked, snapshot.frozen) {
+            (true, _) => AccountState::Locked,
+            (false, true) => AccountState::Frozen,
+            (false, false) => AccountState::Unlocked,
+        };
+        Self {
+            available: snapshot.available,
+            held: snapshot.held,
+            locked,
+            dispute_count: snapshot.dispute_count,
+            chargeback_count: snapshot.chargeback_count,
+            peak_held: snapshot.peak_held,
+            ..Account::new(snapshot.client).with_currency(snapshot.currency)
+        }
+    }
+}
+
 impl Account {
     pub fn new(client_id: u16) -> Self {
         Self {
             client_id,
+            currency: None,
             available: Default::default(),
             held: Default::default(),
             locked: Default::default(),
+            credit_limit: Decimal::ZERO,
+            max_balance: None,
+            dispute_count: 0,
+            chargeback_count: 0,
+            peak_held: Decimal::ZERO,
+            overflow_policy: OverflowPolicy::Error,
+        }
+    }
+
+    pub fn with_currency(mut self, currency: Option<String>) -> Self {
+        self.currency = currency;
+        self
+    }
+
+    pub fn set_credit_limit(&mut self, limit: Decimal) -> Result<(), Errors> {
+        non_negative(limit)?;
+        self.credit_limit = limit;
+        Ok(())
+    }
+
+    pub fn set_max_balance(&mut self, limit: Option<Decimal>) -> Result<(), Errors> {
+        if let Some(limit) = limit {
+            non_negative(limit)?;
+        }
+        self.max_balance = limit;
+        Ok(())
+    }
+
+    pub fn set_overflow_policy(&mut self, policy: OverflowPolicy) {
+        self.overflow_policy = policy;
+    }
+
+    // the funds arithmetic, an overflow is either an error or clamped, as the policy says
+    fn add(&self, funds: Decimal, amount: Decimal) -> Result<Decimal, Errors> {
+        match (funds.checked_add(amount), self.overflow_policy) {
+            (Some(funds), _) => Ok(funds),
+            (None, OverflowPolicy::Saturate) => Ok(funds.saturating_add(amount)),
+            (None, OverflowPolicy::Error) => Err(Errors::FundsOverflow(self.client_id)),
+        }
+    }
+
+    fn sub(&self, funds: Decimal, amount: Decimal) -> Result<Decimal, Errors> {
+        match (funds.checked_sub(amount), self.overflow_policy) {
+            (Some(funds), _) => Ok(funds),
+            (None, OverflowPolicy::Saturate) => Ok(funds.saturating_sub(amount)),
+            (None, OverflowPolicy::Error) => Err(Errors::FundsOverflow(self.client_id)),
+        }
+    }
+
+    // a total reaching exactly the cap is still fine
+    fn check_max_balance(&self, increase: Decimal) -> Result<(), Errors> {
+        let Some(max_balance) = self.max_balance else {
+            return Ok(());
+        };
+        let total = self
+            .total()
+            .ok_or(Errors::FundsOverflow(self.client_id))
+            .and_then(|total| self.add(total, increase))?;
+        if total > max_balance {
+            return Err(Errors::BalanceCapExceeded(self.client_id));
+        }
+        Ok(())
+    }
+
+    pub fn output(&self, options: OutputOptions) -> AccountOutput<'_> {
+        AccountOutput {
+            account: self,
+            options,
+        }
+    }
+
+    pub fn json(&self, options: OutputOptions) -> AccountJson<'_> {
+        AccountJson {
+            account: self,
+            options,
         }
     }
 
     pub fn deposit(&mut self, amount: Amount) -> Result<(), Errors> {
+        non_negative(*amount)?;
         match self.locked {
             AccountState::Locked => Err(Errors::AccountLocked(self.client_id)),
+            AccountState::Frozen => Err(Errors::AccountFrozen(self.client_id)),
             AccountState::Unlocked => {
-                self.available = self
-                    .available
-                    .checked_add(*amount)
-                    .ok_or(Errors::FundsOverflow(self.client_id))?;
+                self.check_max_balance(*amount)?;
+                self.available = self.add(self.available, *amount)?;
                 Ok(())
             }
         }
     }
 
     pub fn withdrawal(&mut self, amount: Amount) -> Result<(), Errors> {
+        self.withdrawal_with_fee(amount, Decimal::ZERO)
+    }
+
+    // the fee is deducted from available together with the withdrawn amount, if both can't be covered
+    // (down to the credit limit) then nothing is withdrawn
+    pub fn withdrawal_with_fee(&mut self, amount: Amount, fee: Decimal) -> Result<(), Errors> {
+        //a negative amount (or fee) would pass the funds check, and then increase the funds
+        non_negative(*amount)?;
+        non_negative(fee)?;
         match self.locked {
             AccountState::Locked => Err(Errors::AccountLocked(self.client_id)),
+            AccountState::Frozen => Err(Errors::AccountFrozen(self.client_id)),
             AccountState::Unlocked => {
-                if self.available >= *amount {
-                    self.available = self
-                        .available
-                        .checked_sub(*amount)
-                        .ok_or(Errors::FundsOverflow(self.client_id))?;
-                    Ok(())
-                } else {
-                    Err(Errors::InsuficientFunds(self.client_id))
+                let charged = self.add(*amount, fee)?;
+                match self.available.checked_sub(charged) {
+                    Some(available) if available >= -self.credit_limit => {
+                        self.available = available;
+                        Ok(())
+                    }
+                    _ => Err(Errors::InsuficientFunds(self.client_id)),
                 }
             }
         }
     }
 
+    // the disputed amount is held even if it has been withdrawn in the meantime, driving available
+    // below zero: the client owes it back, and the total stays the same
     pub fn dispute(&mut self, amount: Amount) -> Result<(), Errors> {
-        if self.available >= *amount {
-            self.available = self
-                .available
-                .checked_sub(*amount)
-                .ok_or(Errors::InsuficientFunds(self.client_id))?;
-            self.held = self
-                .held
-                .checked_add(*amount)
-                .ok_or(Errors::FundsOverflow(self.client_id))?;
-            Ok(())
-        } else {
-            Err(Errors::InsuficientFunds(self.client_id))
-        }
+        let available = self.sub(self.available, *amount)?;
+        let held = self.add(self.held, *amount)?;
+        self.available = available;
+        self.held = held;
+        self.peak_held = self.peak_held.max(held);
+        Ok(())
     }
 
+    // both balances are computed before any of them is updated, so a failed resolve leaves the
+    // account untouched
     pub fn resolve(&mut self, amount: Amount) -> Result<(), Errors> {
-        self.available = self
-            .available
-            .checked_add(*amount)
-            .ok_or(Errors::FundsOverflow(self.client_id))?;
+        if *amount > self.held {
+            return Err(Errors::HeldUnderflow(self.client_id));
+        }
+        let available = self.add(self.available, *amount)?;
+        let held = self.sub(self.held, *amount)?;
+        self.available = available;
+        self.held = held;
+        Ok(())
+    }
+
+    pub fn chargeback(&mut self, amount: Amount) -> Result<(), Errors> {
+        self.held = self.sub(self.held, *amount)?;
+        self.locked = AccountState::Locked;
+        Ok(())
+    }
+
+    // holds a disputed withdrawal: the withdrawn amount is credited to held, since the client might
+    // get it back, while available stays as it is
+    pub fn dispute_withdrawal(&mut self, amount: Amount) -> Result<(), Errors> {
+        self.check_max_balance(*amount)?;
+        self.held = self.add(self.held, *amount)?;
+        self.peak_held = self.peak_held.max(self.held);
+        Ok(())
+    }
+
+    // the withdrawal stands, the amount held by `dispute_withdrawal` is just dropped
+    pub fn resolve_withdrawal(&mut self, amount: Amount) -> Result<(), Errors> {
+        if *amount > self.held {
+            return Err(Errors::HeldUnderflow(self.client_id));
+        }
         self.held = self
             .held
             .checked_sub(*amount)
-            .ok_or(Errors::FundsOverflow(self.client_id))?;
+            .ok_or(Errors::HeldUnderflow(self.client_id))?;
         Ok(())
     }
 
-    pub fn chargeback(&mut self, amount: Amount) -> Result<(), Errors> {
-        self.held = self
+    // reverses a disputed withdrawal: the withdrawn funds are credited back to available; the
+    // account is locked only on request (e.g. pending a fraud review), since it's the client who
+    // gets the money back
+    pub fn chargeback_withdrawal(&mut self, amount: Amount, lock: bool) -> Result<(), Errors> {
+        self.check_max_balance(*amount)?;
+        self.available = self.add(self.available, *amount)?;
+        if lock {
+            self.locked = AccountState::Locked;
+        }
+        Ok(())
+    }
+
+    // reverses a withdrawal held by `dispute_withdrawal`: the held amount moves to available, so the
+    // total stays the same and the balance cap isn't checked; both balances are computed before any
+    // of them is updated, so a failed chargeback leaves the account untouched
+    pub fn chargeback_held_withdrawal(&mut self, amount: Amount, lock: bool) -> Result<(), Errors> {
+        if *amount > self.held {
+            return Err(Errors::HeldUnderflow(self.client_id));
+        }
+        let available = self.add(self.available, *amount)?;
+        let held = self
             .held
             .checked_sub(*amount)
-            .ok_or(Errors::FundsOverflow(self.client_id))?;
-        self.locked = AccountState::Locked;
+            .ok_or(Errors::HeldUnderflow(self.client_id))?;
+        self.available = available;
+        self.held = held;
+        if lock {
+            self.locked = AccountState::Locked;
+        }
         Ok(())
     }
 
-    #[cfg(test)]
+    // a locked account can't be frozen (nor unfrozen), its lock is permanent
+    pub fn freeze(&mut self) -> Result<(), Errors> {
+        match self.locked {
+            AccountState::Locked => Err(Errors::AccountLocked(self.client_id)),
+            _ => {
+                self.locked = AccountState::Frozen;
+                Ok(())
+            }
+        }
+    }
+
+    pub fn unfreeze(&mut self) -> Result<(), Errors> {
+        match self.locked {
+            AccountState::Locked => Err(Errors::AccountLocked(self.client_id)),
+            _ => {
+                self.locked = AccountState::Unlocked;
+                Ok(())
+            }
+        }
+    }
+
+    // an unlocked account holding less than the given balance in total, locked accounts are never treated as dust
+    pub fn is_dust(&self, min_balance: Decimal) -> bool {
+        self.locked == AccountState::Unlocked
+            && self.total().is_some_and(|total| total < min_balance)
+    }
+
+    pub fn get_client_id(&self) -> u16 {
+        self.client_id
+    }
+
+    pub fn currency(&self) -> Option<&str> {
+        self.currency.as_deref()
+    }
+
+    // zeroes the available funds for a close-out, whatever the state of the account
+    pub(crate) fn close_out(&mut self) {
+        self.available = Decimal::ZERO;
+    }
+
     pub fn available(&self) -> Decimal {
         self.available
     }
 
-    #[cfg(test)]
     pub fn held(&self) -> Decimal {
         self.held
     }
 
-    #[cfg(test)]
+    // the very same total that gets serialized (before rounding), `None` if it doesn't fit into a
+    // Decimal, unless the overflow policy clamps it
+    pub fn total(&self) -> Option<Decimal> {
+        self.add(self.available, self.held).ok()
+    }
+
+    pub fn peak_held(&self) -> Decimal {
+        self.peak_held
+    }
+
     pub fn is_locked(&self) -> bool {
         match self.locked {
             AccountState::Locked => true,
-            AccountState::Unlocked => false,
+            AccountState::Frozen | AccountState::Unlocked => false,
         }
     }
+
+    pub fn dispute_count(&self) -> u32 {
+        self.dispute_count
+    }
+
+    pub fn chargeback_count(&self) -> u32 {
+        self.chargeback_count
+    }
+
+    // counted by the manager, as a dispute of a withdrawal might not touch the funds at all
+    pub(crate) fn count_dispute(&mut self) {
+        self.dispute_count = self.dispute_count.saturating_add(1);
+    }
+
+    pub(crate) fn count_chargeback(&mut self) {
+        self.chargeback_count = self.chargeback_count.saturating_add(1);
+    }
+
+    pub fn is_frozen(&self) -> bool {
+        self.locked == AccountState::Frozen
+    }
+}
+
+fn non_negative(amount: Decimal) -> Result<(), Errors> {
+    if amount < Decimal::ZERO {
+        return Err(Errors::NegativeAmount(amount));
+    }
+    Ok(())
 }
 
 #[cfg(test)]
@@ -190,6 +681,56 @@ mod tests {
         assert_eq!(account.available, dec!(0.0));
     }
 
+    #[test]
+    fn test_withdrawal_within_credit_limit_overdraws_account() {
+        let mut account = Account::new(1);
+        assert!(account.set_credit_limit(dec!(50)).is_ok());
+        assert!(account.deposit(dec!(10).into()).is_ok());
+        assert!(account.withdrawal(dec!(60).into()).is_ok());
+        assert_eq!(account.available, dec!(-50));
+        assert!(matches!(
+            account.withdrawal(dec!(0.0001).into()),
+            Err(Errors::InsuficientFunds(1))
+        ));
+        assert_eq!(account.available, dec!(-50));
+    }
+
+    #[test]
+    fn test_deposit_up_to_max_balance_is_accepted() {
+        let mut account = Account::new(1);
+        assert!(account.set_max_balance(Some(dec!(100))).is_ok());
+        assert!(account.deposit(dec!(60).into()).is_ok());
+        assert!(account.dispute(dec!(20).into()).is_ok());
+
+        //the held funds count towards the cap as well
+        assert!(matches!(
+            account.deposit(dec!(40.0001).into()),
+            Err(Errors::BalanceCapExceeded(1))
+        ));
+        assert!(account.deposit(dec!(39.9999).into()).is_ok());
+        assert!(account.deposit(dec!(0.0001).into()).is_ok());
+        assert_eq!(account.total(), Some(dec!(100)));
+        assert!(matches!(
+            account.deposit(dec!(0.0001).into()),
+            Err(Errors::BalanceCapExceeded(1))
+        ));
+        assert!(matches!(
+            account.chargeback_withdrawal(dec!(1).into(), false),
+            Err(Errors::BalanceCapExceeded(1))
+        ));
+        assert_eq!(account.total(), Some(dec!(100)));
+    }
+
+    #[test]
+    fn test_negative_credit_limit_is_rejected() {
+        let mut account = Account::new(1);
+        assert!(matches!(
+            account.set_credit_limit(dec!(-1)),
+            Err(Errors::NegativeAmount(_))
+        ));
+        assert_eq!(account.credit_limit, Decimal::ZERO);
+    }
+
     #[test]
     fn test_dispute_to_account() {
         let mut account = Account::new(1);
@@ -200,13 +741,14 @@ mod tests {
     }
 
     #[test]
-    fn test_dispute_to_account_with_not_enough_funds() {
+    fn test_dispute_of_withdrawn_deposit_drives_available_negative() {
         let mut account = Account::new(1);
-        assert!(matches!(
-            account.dispute(dec!(10.0).into()),
-            Err(Errors::InsuficientFunds(1))
-        ));
-        assert_eq!(account.held, dec!(0.0));
+        assert!(account.deposit(dec!(100).into()).is_ok());
+        assert!(account.withdrawal(dec!(100).into()).is_ok());
+        assert!(account.dispute(dec!(100).into()).is_ok());
+        assert_eq!(account.available, dec!(-100));
+        assert_eq!(account.held, dec!(100));
+        assert_eq!(account.total(), Some(dec!(0)));
     }
 
     #[test]
@@ -236,6 +778,34 @@ mod tests {
         assert_eq!(account.available, dec!(10.0));
     }
 
+    #[test]
+    fn test_resolve_of_more_than_held_leaves_account_untouched() {
+        let mut account = Account::new(1);
+        assert!(account.deposit(dec!(10.0).into()).is_ok());
+        assert!(account.dispute(dec!(5.0).into()).is_ok());
+
+        assert!(matches!(
+            account.resolve(dec!(5.5).into()),
+            Err(Errors::HeldUnderflow(1))
+        ));
+        assert_eq!(account.available(), dec!(5.0));
+        assert_eq!(account.held(), dec!(5.0));
+    }
+
+    #[test]
+    fn test_resolve_overflowing_available_leaves_held_untouched() {
+        let mut account = Account::new(1);
+        account.available = Decimal::MAX;
+        account.held = dec!(5.0);
+
+        assert!(matches!(
+            account.resolve(dec!(5.0).into()),
+            Err(Errors::FundsOverflow(1))
+        ));
+        assert_eq!(account.available(), Decimal::MAX);
+        assert_eq!(account.held(), dec!(5.0));
+    }
+
     #[test]
     fn test_deposit_on_locked_account() {
         let mut account = Account::new(1);
@@ -248,6 +818,51 @@ mod tests {
         ));
     }
 
+    #[test]
+    fn test_frozen_account_rejects_deposits_and_withdrawals_until_unfrozen() {
+        let mut account = Account::new(1);
+        assert!(account.deposit(dec!(10).into()).is_ok());
+        assert!(account.freeze().is_ok());
+        assert!(account.is_frozen());
+        assert!(!account.is_locked());
+
+        assert!(matches!(
+            account.deposit(dec!(5).into()),
+            Err(Errors::AccountFrozen(1))
+        ));
+        assert!(matches!(
+            account.withdrawal(dec!(5).into()),
+            Err(Errors::AccountFrozen(1))
+        ));
+        let mut writer = csv::Writer::from_writer(Vec::new());
+        assert!(writer.serialize(account.output(Default::default())).is_ok());
+        assert_eq!(
+            String::from_utf8(writer.into_inner().unwrap()).unwrap(),
+            "client,available,held,total,locked\n1,10.0000,0.0000,10.0000,true\n"
+        );
+
+        assert!(account.unfreeze().is_ok());
+        assert!(!account.is_frozen());
+        assert!(account.withdrawal(dec!(5).into()).is_ok());
+        assert_eq!(account.available(), dec!(5));
+    }
+
+    #[test]
+    fn test_lock_is_permanent_unlike_freeze() {
+        let mut account = Account::new(1);
+        assert!(account.deposit(dec!(10).into()).is_ok());
+        assert!(account.freeze().is_ok());
+        //a chargeback turns the freeze into a lock
+        assert!(account.dispute(dec!(10).into()).is_ok());
+        assert!(account.chargeback(dec!(10).into()).is_ok());
+        assert!(account.is_locked());
+        assert!(!account.is_frozen());
+
+        assert!(matches!(account.unfreeze(), Err(Errors::AccountLocked(1))));
+        assert!(matches!(account.freeze(), Err(Errors::AccountLocked(1))));
+        assert!(account.is_locked());
+    }
+
     #[test]
     fn test_deposit_fails_due_overflow() {
         let mut account = Account::new(1);
@@ -261,15 +876,16 @@ mod tests {
     }
 
     #[test]
-    fn test_withdrawal_fails_due_overflow() {
+    fn test_withdrawal_of_decimal_min_is_rejected_as_negative() {
         let mut account = Account::new(1);
         account.held = Decimal::MAX;
         account.available = Decimal::MAX;
 
         assert!(matches!(
             account.withdrawal(Decimal::MIN.into()),
-            Err(Errors::FundsOverflow(1))
+            Err(Errors::NegativeAmount(_))
         ));
+        assert_eq!(account.available(), Decimal::MAX);
     }
 
     #[test]
@@ -308,4 +924,432 @@ mod tests {
             Err(Errors::FundsOverflow(1))
         ));
     }
+
+    #[test]
+    fn test_failed_held_withdrawal_chargeback_leaves_the_account_untouched() {
+        let mut account = Account::new(1);
+        account.available = Decimal::MAX;
+        assert!(account.dispute_withdrawal(dec!(4).into()).is_ok());
+
+        assert!(matches!(
+            account.chargeback_held_withdrawal(dec!(4).into(), true),
+            Err(Errors::FundsOverflow(1))
+        ));
+        assert_eq!(account.available(), Decimal::MAX);
+        assert_eq!(account.held(), dec!(4));
+        assert!(!account.is_locked());
+
+        assert!(matches!(
+            account.chargeback_held_withdrawal(dec!(5).into(), false),
+            Err(Errors::HeldUnderflow(1))
+        ));
+    }
+
+    #[test]
+    fn test_overflow_policy_error_leaves_the_account_untouched() {
+        let mut account = Account::new(1);
+        assert!(account.deposit(Decimal::MAX.into()).is_ok());
+        assert!(account.dispute(dec!(1).into()).is_ok());
+
+        assert!(matches!(
+            account.deposit(dec!(2).into()),
+            Err(Errors::FundsOverflow(1))
+        ));
+        assert_eq!(account.available(), Decimal::MAX - dec!(1));
+        assert_eq!(account.total(), Some(Decimal::MAX));
+    }
+
+    #[test]
+    fn test_overflow_policy_saturate_clamps_the_funds() {
+        let mut account = Account::new(1);
+        account.set_overflow_policy(OverflowPolicy::Saturate);
+        assert!(account.deposit(Decimal::MAX.into()).is_ok());
+        assert!(account.deposit(dec!(2).into()).is_ok());
+        assert_eq!(account.available(), Decimal::MAX);
+
+        //the held funds don't fit either, while available can still go down
+        account.held = Decimal::MAX;
+        assert!(account.dispute(dec!(5).into()).is_ok());
+        assert_eq!(account.available(), Decimal::MAX - dec!(5));
+        assert_eq!(account.held(), Decimal::MAX);
+        assert_eq!(account.total(), Some(Decimal::MAX));
+
+        account.held = Decimal::MIN;
+        assert!(account.chargeback(Decimal::MAX.into()).is_ok());
+        assert_eq!(account.held(), Decimal::MIN);
+        assert!(account.is_locked());
+    }
+
+    #[test]
+    fn test_saturated_account_is_written_out() {
+        let mut account = Account::new(1);
+        account.set_overflow_policy(OverflowPolicy::Saturate);
+        assert!(account.deposit(Decimal::MAX.into()).is_ok());
+        assert!(account.deposit(dec!(1).into()).is_ok());
+
+        let mut writer = csv::Writer::from_writer(Vec::new());
+        assert!(writer.serialize(account.output(Default::default())).is_ok());
+        assert_eq!(
+            String::from_utf8(writer.into_inner().unwrap()).unwrap(),
+            "client,available,held,total,locked\n1,79228162514264337593543950335.0000,0.0000,79228162514264337593543950335.0000,false\n"
+        );
+    }
+
+    #[test]
+    fn test_unlocked_account_below_min_balance_is_dust() {
+        let mut account = Account::new(1);
+        account.available = dec!(0.0003);
+        account.held = dec!(0.0002);
+
+        assert!(account.is_dust(dec!(0.001)));
+        assert!(!account.is_dust(dec!(0.0005)));
+    }
+
+    #[test]
+    fn test_locked_account_below_min_balance_is_not_dust() {
+        let mut account = Account::new(1);
+        account.available = dec!(0.0005);
+        account.locked = AccountState::Locked;
+
+        assert!(!account.is_dust(dec!(0.001)));
+    }
+
+    #[test]
+    fn test_account_with_overflowing_total_is_not_dust() {
+        let mut account = Account::new(1);
+        account.available = Decimal::MAX;
+        account.held = Decimal::MAX;
+
+        assert!(!account.is_dust(dec!(0.001)));
+    }
+
+    fn to_csv<T: Serialize>(rows: &[T]) -> String {
+        let mut writer = csv::Writer::from_writer(Vec::new());
+        for row in rows {
+            writer.serialize(row).unwrap();
+        }
+        String::from_utf8(writer.into_inner().unwrap()).unwrap()
+    }
+
+    #[test]
+    fn test_default_output_has_five_columns() {
+        let mut account = Account::new(1);
+        assert!(account.deposit(dec!(1.5).into()).is_ok());
+
+        assert_eq!(
+            to_csv(&[&account]),
+            "client,available,held,total,locked\n1,1.5000,0.0000,1.5000,false\n"
+        );
+    }
+
+    #[test]
+    fn test_output_flags_accounts_with_held_exceeding_available() {
+        let mut disputed_withdrawal = Account::new(1);
+        assert!(disputed_withdrawal.deposit(dec!(10).into()).is_ok());
+        assert!(disputed_withdrawal.withdrawal(dec!(5).into()).is_ok());
+        assert!(disputed_withdrawal.dispute(dec!(5).into()).is_ok());
+        let mut normal = Account::new(2);
+        assert!(normal.deposit(dec!(10).into()).is_ok());
+
+        let options = OutputOptions {
+            held_exceeds_available: true,
+            ..Default::default()
+        };
+        assert_eq!(
+            to_csv(&[disputed_withdrawal.output(options), normal.output(options)]),
+            "client,available,held,total,locked,held_exceeds_available\n\
+             1,0.0000,5.0000,5.0000,false,true\n\
+             2,10.0000,0.0000,10.0000,false,false\n"
+        );
+    }
+
+    #[test]
+    fn test_display_prints_the_account_on_a_single_line() {
+        let mut account = Account::new(1);
+        assert!(account.deposit(dec!(12.34).into()).is_ok());
+        assert_eq!(
+            account.to_string(),
+            "client=1 available=12.3400 held=0.0000 total=12.3400 locked=false"
+        );
+
+        let mut frozen = Account::new(2).with_currency(Some("BTC".to_string()));
+        assert!(frozen.deposit(dec!(0.5).into()).is_ok());
+        assert!(frozen.dispute(dec!(0.5).into()).is_ok());
+        assert!(frozen.freeze().is_ok());
+        assert_eq!(
+            frozen.to_string(),
+            "client=2 currency=BTC available=0.0000 held=0.5000 total=0.5000 locked=true"
+        );
+    }
+
+    #[test]
+    fn test_peak_held_is_retained_after_the_disputes_are_resolved() {
+        let mut account = Account::new(1);
+        assert!(account.deposit(dec!(10).into()).is_ok());
+        assert!(account.deposit(dec!(5).into()).is_ok());
+        assert!(account.dispute(dec!(10).into()).is_ok());
+        assert!(account.dispute(dec!(5).into()).is_ok());
+        assert_eq!(account.peak_held(), dec!(15));
+        assert!(account.resolve(dec!(10).into()).is_ok());
+        assert!(account.dispute(dec!(3).into()).is_ok());
+        assert!(account.resolve(dec!(8).into()).is_ok());
+
+        assert_eq!(account.held(), dec!(0));
+        assert_eq!(account.peak_held(), dec!(15));
+        let options = OutputOptions {
+            peak_held: true,
+            ..Default::default()
+        };
+        assert_eq!(
+            to_csv(&[account.output(options)]),
+            "client,available,held,total,locked,peak_held\n1,15.0000,0.0000,15.0000,false,15.0000\n"
+        );
+    }
+
+    #[test]
+    fn test_output_adds_the_currency_column_on_request() {
+        let mut usd = Account::new(1).with_currency(Some("USD".to_string()));
+        assert!(usd.deposit(dec!(1.5).into()).is_ok());
+        let btc = Account::new(1).with_currency(Some("BTC".to_string()));
+
+        let options = OutputOptions {
+            currency_column: true,
+            ..Default::default()
+        };
+        assert_eq!(
+            to_csv(&[usd.output(options), btc.output(options)]),
+            "client,currency,available,held,total,locked\n\
+             1,USD,1.5000,0.0000,1.5000,false\n\
+             1,BTC,0.0000,0.0000,0.0000,false\n"
+        );
+        assert_eq!(
+            serde_json::to_string(&usd.json(options)).unwrap(),
+            r#"{"client":1,"currency":"USD","available":"1.5000","held":"0.0000","total":"1.5000","locked":false}"#
+        );
+    }
+
+    #[test]
+    fn test_output_zero_pads_client_to_requested_width() {
+        let account = Account::new(42);
+        let options = OutputOptions {
+            client_width: Some(5),
+            ..Default::default()
+        };
+
+        assert_eq!(
+            to_csv(&[account.output(options)]),
+            "client,available,held,total,locked\n00042,0.0000,0.0000,0.0000,false\n"
+        );
+        assert_eq!(
+            serde_json::to_string(&account.json(options)).unwrap(),
+            r#"{"client":"00042","available":"0.0000","held":"0.0000","total":"0.0000","locked":false}"#
+        );
+    }
+
+    #[test]
+    fn test_negative_amounts_are_rejected_by_the_account() {
+        let mut account = Account::new(1);
+        assert!(account.deposit(dec!(10).into()).is_ok());
+
+        assert!(matches!(
+            account.deposit(dec!(-5).into()),
+            Err(Errors::NegativeAmount(_))
+        ));
+        assert!(matches!(
+            account.withdrawal(dec!(-5).into()),
+            Err(Errors::NegativeAmount(_))
+        ));
+        assert!(matches!(
+            account.withdrawal_with_fee(dec!(1).into(), dec!(-5)),
+            Err(Errors::NegativeAmount(_))
+        ));
+        assert_eq!(account.available(), dec!(10));
+
+        assert!(account.deposit(dec!(-0).into()).is_ok());
+        assert!(account.withdrawal(dec!(0).into()).is_ok());
+        assert_eq!(account.available(), dec!(10));
+    }
+
+    #[test]
+    fn test_dispute_family_operations_are_permitted_on_locked_account() {
+        let mut account = Account::new(1);
+        assert!(account.deposit(dec!(10).into()).is_ok());
+        assert!(account.dispute(dec!(1).into()).is_ok());
+        assert!(account.chargeback(dec!(1).into()).is_ok());
+        assert!(account.is_locked());
+
+        assert!(account.dispute(dec!(4).into()).is_ok());
+        assert!(account.resolve(dec!(3).into()).is_ok());
+        assert!(account.chargeback(dec!(1).into()).is_ok());
+        assert!(account.chargeback_withdrawal(dec!(2).into(), false).is_ok());
+        assert_eq!(account.available(), dec!(10));
+        assert_eq!(account.held(), dec!(0));
+
+        assert!(matches!(
+            account.deposit(dec!(1).into()),
+            Err(Errors::AccountLocked(1))
+        ));
+        assert!(matches!(
+            account.withdrawal(dec!(1).into()),
+            Err(Errors::AccountLocked(1))
+        ));
+    }
+
+    #[test]
+    fn test_withdrawal_chargeback_credits_available_without_locking() {
+        let mut account = Account::new(1);
+        assert!(account.deposit(dec!(10).into()).is_ok());
+        assert!(account.withdrawal(dec!(4).into()).is_ok());
+        assert!(account.chargeback_withdrawal(dec!(4).into(), false).is_ok());
+
+        assert_eq!(account.available(), dec!(10));
+        assert_eq!(account.held(), dec!(0));
+        assert!(!account.is_locked());
+    }
+
+    #[test]
+    fn test_held_withdrawal_dispute() {
+        let mut account = Account::new(1);
+        assert!(account.deposit(dec!(10).into()).is_ok());
+        assert!(account.withdrawal(dec!(4).into()).is_ok());
+
+        assert!(account.dispute_withdrawal(dec!(4).into()).is_ok());
+        assert_eq!(account.available(), dec!(6));
+        assert_eq!(account.held(), dec!(4));
+        assert_eq!(account.total(), Some(dec!(10)));
+
+        assert!(matches!(
+            account.resolve_withdrawal(dec!(5).into()),
+            Err(Errors::HeldUnderflow(1))
+        ));
+        assert!(account.resolve_withdrawal(dec!(4).into()).is_ok());
+        assert_eq!(account.available(), dec!(6));
+        assert_eq!(account.held(), dec!(0));
+    }
+
+    #[test]
+    fn test_resolve_of_held_withdrawal_beyond_decimal_max_is_rejected() {
+        let mut account = Account::new(1);
+        assert!(account.dispute_withdrawal(Decimal::MAX.into()).is_ok());
+
+        assert!(matches!(
+            account.resolve_withdrawal(dec!(-1).into()),
+            Err(Errors::HeldUnderflow(1))
+        ));
+        assert!(matches!(
+            account.chargeback_held_withdrawal(dec!(-1).into(), false),
+            Err(Errors::HeldUnderflow(1))
+        ));
+        assert_eq!(account.held(), Decimal::MAX);
+        assert_eq!(account.available(), dec!(0));
+    }
+
+    #[test]
+    fn test_withdrawal_chargeback_locks_account_on_request() {
+        let mut account = Account::new(1);
+        assert!(account.deposit(dec!(10).into()).is_ok());
+        assert!(account.withdrawal(dec!(4).into()).is_ok());
+        assert!(account.chargeback_withdrawal(dec!(4).into(), true).is_ok());
+
+        assert_eq!(account.available(), dec!(10));
+        assert!(account.is_locked());
+    }
+
+    #[test]
+    fn test_json_output_keeps_decimals_as_strings_and_locked_as_boolean() {
+        let mut account = Account::new(1);
+        assert!(account.deposit(dec!(1.5).into()).is_ok());
+        assert!(account.dispute(dec!(1.5).into()).is_ok());
+        assert!(account.chargeback(dec!(1.5).into()).is_ok());
+
+        assert_eq!(
+            serde_json::to_string(&[account.json(Default::default())]).unwrap(),
+            r#"[{"client":1,"available":"0.0000","held":"0.0000","total":"0.0000","locked":true}]"#
+        );
+    }
+
+    #[test]
+    fn test_overflowing_total_fails_serialization_instead_of_panicking() {
+        let mut account = Account::new(1);
+        account.available = Decimal::MAX;
+        account.held = Decimal::MAX;
+        assert!(account.total().is_none());
+
+        let mut writer = csv::Writer::from_writer(Vec::new());
+        assert!(writer.serialize(&account).is_err());
+        assert!(writer.into_inner().unwrap().is_empty());
+        assert!(serde_json::to_string(&account.json(Default::default())).is_err());
+    }
+
+    #[test]
+    fn test_output_precision_rounds_half_to_even() {
+        let mut account = Account::new(1);
+        assert!(account.deposit(dec!(1.125).into()).is_ok());
+        assert!(account.deposit(dec!(1.0).into()).is_ok());
+        assert!(account.dispute(dec!(1.0).into()).is_ok());
+        let with_precision = |precision| OutputOptions {
+            precision: Some(precision),
+            ..Default::default()
+        };
+
+        assert_eq!(
+            to_csv(&[account.output(with_precision(2))]),
+            "client,available,held,total,locked\n1,1.12,1.00,2.12,false\n"
+        );
+        assert_eq!(
+            to_csv(&[account.output(with_precision(0))]),
+            "client,available,held,total,locked\n1,1,1,2,false\n"
+        );
+        assert_eq!(
+            to_csv(&[account.output(with_precision(8))]),
+            "client,available,held,total,locked\n1,1.12500000,1.00000000,2.12500000,false\n"
+        );
+        assert_eq!(
+            serde_json::to_string(&account.json(with_precision(2))).unwrap(),
+            r#"{"client":1,"available":"1.12","held":"1.00","total":"2.12","locked":false}"#
+        );
+    }
+
+    #[test]
+    fn test_output_rounding_modes() {
+        let mut account = Account::new(1);
+        account.available = dec!(2.00005);
+        account.held = dec!(2.00015);
+        let with_rounding = |rounding| OutputOptions {
+            rounding,
+            ..Default::default()
+        };
+
+        assert_eq!(
+            to_csv(&[account.output(with_rounding(Rounding::HalfEven))]),
+            "client,available,held,total,locked\n1,2.0000,2.0002,4.0002,false\n"
+        );
+        assert_eq!(
+            to_csv(&[account.output(with_rounding(Rounding::HalfUp))]),
+            "client,available,held,total,locked\n1,2.0001,2.0002,4.0002,false\n"
+        );
+        assert_eq!(
+            to_csv(&[account.output(with_rounding(Rounding::Truncate))]),
+            "client,available,held,total,locked\n1,2.0000,2.0001,4.0002,false\n"
+        );
+        assert_eq!("half-up".parse::<Rounding>(), Ok(Rounding::HalfUp));
+        assert!("up".parse::<Rounding>().is_err());
+    }
+
+    #[test]
+    fn test_public_getters_reflect_account_state() {
+        let mut account = Account::new(1);
+        assert!(account.deposit(dec!(10.5).into()).is_ok());
+        assert!(account.dispute(dec!(2.5).into()).is_ok());
+
+        assert_eq!(account.get_client_id(), 1);
+        assert_eq!(account.available(), dec!(8.0));
+        assert_eq!(account.held(), dec!(2.5));
+        assert_eq!(account.total(), Some(dec!(10.5)));
+        assert!(!account.is_locked());
+
+        assert!(account.chargeback(dec!(2.5).into()).is_ok());
+        assert_eq!(account.total(), Some(dec!(8.0)));
+        assert!(account.is_locked());
+    }
 }
@@ -1,11 +1,55 @@
+use crate::error::Errors;
 use rust_decimal::Decimal;
-use serde::{Deserialize, Serialize};
+use serde::{de::Error, Deserialize, Deserializer, Serialize};
 
 use std::ops::Deref;
 
-#[derive(PartialEq, Eq, Clone, Copy, Debug, Default, Serialize, Deserialize)]
+// amounts are provided with a precision of up to four places past the decimal
+pub const MAX_DECIMAL_PLACES: u32 = 4;
+
+#[derive(PartialEq, Eq, Clone, Copy, Debug, Default, Serialize)]
 pub struct Amount(pub Decimal);
 
+// trailing zeros don't count, i.e. `1.23000` is accepted, while `1.00005` is not
+impl<'de> Deserialize<'de> for Amount {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        let amount = <Decimal as Deserialize>::deserialize(deserializer)?;
+        with_precision(amount).map_err(D::Error::custom)
+    }
+}
+
+// the same rules as for the input, for amounts parsed outside of serde
+impl std::str::FromStr for Amount {
+    type Err = Errors;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let amount = s
+            .parse::<Decimal>()
+            .map_err(|_| Errors::InvalidAmount(s.to_string()))?;
+        with_precision(amount)
+    }
+}
+
+fn with_precision(amount: Decimal) -> Result<Amount, Errors> {
+    if amount.normalize().scale() > MAX_DECIMAL_PLACES {
+        return Err(Errors::TooManyDecimalPlaces(amount));
+    }
+    Ok(Amount(amount))
+}
+
+impl Amount {
+    // the same value with exactly `scale` decimal places, so e.g. `5` and `5.0000` are stored (and
+    // printed) the same way; a scale below the amount's own one rounds it (half to even)
+    pub fn normalized(self, scale: u32) -> Amount {
+        let mut amount = self.0;
+        amount.rescale(scale);
+        Amount(amount)
+    }
+}
+
 // just to ease usage of Amount acros other components
 impl Deref for Amount {
     type Target = Decimal;
@@ -19,3 +63,91 @@ impl From<Decimal> for Amount {
         Amount(d)
     }
 }
+
+// a plain integer literal is an `i32`, so `Amount::from(5)` needs this one
+impl From<i32> for Amount {
+    fn from(value: i32) -> Self {
+        Amount(value.into())
+    }
+}
+
+impl From<i64> for Amount {
+    fn from(value: i64) -> Self {
+        Amount(value.into())
+    }
+}
+
+impl From<u64> for Amount {
+    fn from(value: u64) -> Self {
+        Amount(value.into())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rust_decimal_macros::dec;
+
+    #[derive(Debug, Deserialize)]
+    struct Row {
+        amount: Amount,
+    }
+
+    fn parse(amount: &str) -> csv::Result<Amount> {
+        let input = format!("amount\n{}\n", amount);
+        let mut reader = csv::Reader::from_reader(input.as_bytes());
+        let row: Row = reader.deserialize().next().unwrap()?;
+        Ok(row.amount)
+    }
+
+    #[test]
+    fn test_amount_with_more_than_four_decimal_places_is_rejected() {
+        let err = parse("1.00005").unwrap_err();
+        assert!(err
+            .to_string()
+            .contains(&Errors::TooManyDecimalPlaces(dec!(1.00005)).to_string()));
+    }
+
+    #[test]
+    fn test_amount_with_up_to_four_decimal_places_is_accepted() {
+        assert_eq!(parse("0.0001").unwrap(), dec!(0.0001).into());
+        assert_eq!(parse("1.2300").unwrap(), dec!(1.23).into());
+        assert_eq!(parse("1.230000").unwrap(), dec!(1.23).into());
+    }
+
+    #[test]
+    fn test_integer_amount_is_accepted() {
+        assert_eq!(parse("42").unwrap(), dec!(42).into());
+    }
+
+    #[test]
+    fn test_amount_from_integers_and_strings() {
+        assert_eq!(Amount::from(5), dec!(5).into());
+        assert_eq!(Amount::from(-5i64), dec!(-5).into());
+        assert_eq!(Amount::from(u64::MAX), Decimal::from(u64::MAX).into());
+        assert_eq!("1.2345".parse::<Amount>().unwrap(), dec!(1.2345).into());
+        assert_eq!("1.230000".parse::<Amount>().unwrap(), dec!(1.23).into());
+        assert!(matches!(
+            "1.00005".parse::<Amount>(),
+            Err(Errors::TooManyDecimalPlaces(amount)) if amount == dec!(1.00005)
+        ));
+        assert!(matches!(
+            "1,5".parse::<Amount>(),
+            Err(Errors::InvalidAmount(amount)) if amount == "1,5"
+        ));
+    }
+
+    #[test]
+    fn test_normalized_amount_has_the_given_scale() {
+        let five = Amount::from(5).normalized(4);
+        assert_eq!(five.0.to_string(), "5.0000");
+        assert_eq!(five, dec!(5.0000).into());
+        assert_eq!(
+            five.0.to_string(),
+            Amount(dec!(5.0000)).normalized(4).0.to_string()
+        );
+        assert_eq!(Amount(dec!(1.23)).normalized(6).0.to_string(), "1.230000");
+        // a lower scale rounds the amount
+        assert_eq!(Amount(dec!(1.2345)).normalized(2).0.to_string(), "1.23");
+    }
+}
@@ -0,0 +1,57 @@
+use payments_engine::Record;
+use std::path::Path;
+
+// writes the close-out records in the input format, so the ledger might be fed back to the engine;
+// the `currency` column is there only for the accounts of a multi-asset input
+pub fn write<P: AsRef<Path>>(path: P, records: &[Record]) -> csv::Result<()> {
+    let currency_column = records.iter().any(|record| record.currency.is_some());
+    let mut writer = csv::WriterBuilder::new()
+        .has_headers(false)
+        .from_path(path)?;
+    let mut header = vec!["type", "client", "tx", "amount"];
+    if currency_column {
+        header.push("currency");
+    }
+    writer.write_record(&header)?;
+    for record in records {
+        let mut row = vec![
+            record.r#type.to_string(),
+            record.client.to_string(),
+            record.tx.to_string(),
+            record
+                .amount
+                .map_or_else(String::new, |amount| amount.to_string()),
+        ];
+        if currency_column {
+            row.push(record.currency.clone().unwrap_or_default());
+        }
+        writer.write_record(&row)?;
+    }
+    writer.flush()?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use payments_engine::OperationType;
+    use rust_decimal_macros::dec;
+
+    #[test]
+    fn test_closeout_is_written_in_the_input_format() {
+        let path = std::env::temp_dir().join(format!("closeout-{}.csv", std::process::id()));
+        let records = vec![
+            Record::new(OperationType::Deposit, 1, 6, Some(dec!(2.5).into())),
+            Record::new(OperationType::Withdrawal, 2, 7, Some(dec!(10).into())),
+        ];
+
+        write(&path, &records).unwrap();
+        let content = std::fs::read_to_string(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+
+        assert_eq!(
+            content,
+            "type,client,tx,amount\ndeposit,1,6,2.5\nwithdrawal,2,7,10\n"
+        );
+    }
+}
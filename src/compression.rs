@@ -0,0 +1,124 @@
+use clap::ValueEnum;
+use std::io::{Result, Write};
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq, ValueEnum)]
+pub enum Compression {
+    None,
+    #[cfg(feature = "gzip")]
+    Gzip,
+    #[cfg(feature = "zstd")]
+    Zstd,
+}
+
+// wraps the output sink with the chosen encoder, the encoder has to be finished explicitly
+// so the compressed stream gets its trailer written
+pub enum Encoder<W: Write> {
+    Plain(W),
+    #[cfg(feature = "gzip")]
+    Gzip(flate2::write::GzEncoder<W>),
+    #[cfg(feature = "zstd")]
+    Zstd(zstd::Encoder<'static, W>),
+}
+
+impl<W: Write> Encoder<W> {
+    pub fn new(sink: W, compression: Compression) -> Result<Self> {
+        Ok(match compression {
+            Compression::None => Encoder::Plain(sink),
+            #[cfg(feature = "gzip")]
+            Compression::Gzip => Encoder::Gzip(flate2::write::GzEncoder::new(
+                sink,
+                flate2::Compression::default(),
+            )),
+            #[cfg(feature = "zstd")]
+            Compression::Zstd => Encoder::Zstd(zstd::Encoder::new(sink, 0)?),
+        })
+    }
+
+    pub fn finish(self) -> Result<W> {
+        match self {
+            Encoder::Plain(mut sink) => {
+                sink.flush()?;
+                Ok(sink)
+            }
+            #[cfg(feature = "gzip")]
+            Encoder::Gzip(encoder) => encoder.finish(),
+            #[cfg(feature = "zstd")]
+            Encoder::Zstd(encoder) => encoder.finish(),
+        }
+    }
+}
+
+impl<W: Write> Write for Encoder<W> {
+    fn write(&mut self, buf: &[u8]) -> Result<usize> {
+        match self {
+            Encoder::Plain(sink) => sink.write(buf),
+            #[cfg(feature = "gzip")]
+            Encoder::Gzip(encoder) => encoder.write(buf),
+            #[cfg(feature = "zstd")]
+            Encoder::Zstd(encoder) => encoder.write(buf),
+        }
+    }
+
+    fn flush(&mut self) -> Result<()> {
+        match self {
+            Encoder::Plain(sink) => sink.flush(),
+            #[cfg(feature = "gzip")]
+            Encoder::Gzip(encoder) => encoder.flush(),
+            #[cfg(feature = "zstd")]
+            Encoder::Zstd(encoder) => encoder.flush(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const EXPECTED_CSV: &str = "client,available,held,total,locked\n1,1.5000,0.0000,1.5000,false\n";
+
+    fn encode(compression: Compression) -> Vec<u8> {
+        let mut writer = csv::Writer::from_writer(Encoder::new(Vec::new(), compression).unwrap());
+        writer
+            .write_record(["client", "available", "held", "total", "locked"])
+            .unwrap();
+        writer
+            .write_record(["1", "1.5000", "0.0000", "1.5000", "false"])
+            .unwrap();
+        writer
+            .into_inner()
+            .map_err(|e| e.into_error())
+            .unwrap()
+            .finish()
+            .unwrap()
+    }
+
+    #[test]
+    fn test_no_compression_passes_output_through() {
+        assert_eq!(encode(Compression::None), EXPECTED_CSV.as_bytes());
+    }
+
+    #[cfg(feature = "gzip")]
+    #[test]
+    fn test_gzip_compression_produces_valid_gzip_stream() {
+        use std::io::Read;
+        let compressed = encode(Compression::Gzip);
+        let mut decoded = String::new();
+        flate2::read::GzDecoder::new(compressed.as_slice())
+            .read_to_string(&mut decoded)
+            .unwrap();
+        assert_eq!(decoded, EXPECTED_CSV);
+    }
+
+    #[cfg(feature = "zstd")]
+    #[test]
+    fn test_zstd_compression_produces_valid_zstd_stream() {
+        use std::io::Read;
+        let compressed = encode(Compression::Zstd);
+        let mut decoded = String::new();
+        zstd::Decoder::new(compressed.as_slice())
+            .unwrap()
+            .read_to_string(&mut decoded)
+            .unwrap();
+        assert_eq!(decoded, EXPECTED_CSV);
+    }
+}
@@ -1,13 +1,323 @@
+use crate::record::OperationType;
+use chrono::{DateTime, Utc};
+use rust_decimal::Decimal;
 use thiserror::Error;
 
 #[derive(Error, Debug)]
 pub enum Errors {
     #[error("Account {0} is locked")]
     AccountLocked(u16),
+    #[error("Account {0} is frozen")]
+    AccountFrozen(u16),
+    #[error("Account {0} would exceed its maximum balance!")]
+    BalanceCapExceeded(u16),
     #[error("Not enough funds available for account {0}!")]
     InsuficientFunds(u16),
     #[error("Overflow occured in account {0}")]
     FundsOverflow(u16),
-    #[error("Transaction ID {0} already taken!")]
-    TransactionIdAlreadyUsed(u32),
+    #[error("Not enough funds held for account {0}!")]
+    HeldUnderflow(u16),
+    #[error("Client {0} is reserved!")]
+    ReservedClient(u16),
+    #[error("Account {0} exists in both of the merged managers!")]
+    AccountOverlap(u16),
+    #[error("Transaction ID {tx} of a {incoming} already taken by a {existing}!")]
+    TransactionIdAlreadyUsed {
+        tx: u32,
+        incoming: OperationType,
+        existing: OperationType,
+    },
+    #[error("Transaction ID {0} of client {1} already taken by client {2}!")]
+    TransactionIdUsedByAnotherClient(u32, u16, u16),
+    #[error("No tx id is left for the close-out of client {0}!")]
+    CloseoutTxIdsExhausted(u16),
+    #[error("Transaction ID {0} does not exist!")]
+    UnknownTransaction(u32),
+    #[error("Transaction ID {0} does not belong to client {1}!")]
+    ClientMismatch(u32, u16),
+    #[error("Transaction ID {0} is not held in the currency {}!", .1.as_deref().unwrap_or("(none)"))]
+    CurrencyMismatch(u32, Option<String>),
+    #[error("Transaction ID {0} is not in a disputable state!")]
+    TransactionNotDisputable(u32),
+    #[error("Transaction ID {0} has already been settled!")]
+    TransactionAlreadySettled(u32),
+    #[error("Transaction ID {0} has been charged back, it can't be resolved!")]
+    ResolveOnChargedBack(u32),
+    #[error("Transaction ID {0} has already been reversed by a chargeback!")]
+    TransactionAlreadyReversed(u32),
+    #[error("Transaction ID {0} has already been charged back!")]
+    AlreadyChargedBack(u32),
+    #[error("Transaction ID {0} refers to a {1} operation, only deposits might be disputed!")]
+    DisputeTypeMismatch(u32, OperationType),
+    #[error("Transaction ID {0} refers to a {1} operation, which can't be disputed!")]
+    UnsupportedOperation(u32, OperationType),
+    #[error("Amount {0} is not a number!")]
+    InvalidAmount(String),
+    #[error("Amount {0} has more than 4 decimal places!")]
+    TooManyDecimalPlaces(Decimal),
+    #[error("Amount {0} is negative!")]
+    NegativeAmount(Decimal),
+    #[error("Transaction ID {0} has no amount!")]
+    MissingAmount(u32),
+    #[error("Transaction ID {0} carries an unexpected amount {1}!")]
+    UnexpectedAmount(u32, Decimal),
+    #[error("Transaction ID {0} has a zero amount!")]
+    ZeroAmount(u32),
+    #[error("Transaction ID {0} holds less than {1} to be resolved!")]
+    ResolveExceedsHeld(u32, Decimal),
+    #[error("Accounts hold {actual} in total, while their transactions add up to {expected}!")]
+    ReconciliationMismatch { expected: Decimal, actual: Decimal },
+    #[error("Transaction ID {0} at {1} precedes the last applied record at {2}!")]
+    OutOfOrder(u32, DateTime<Utc>, DateTime<Utc>),
+}
+
+/// Who is to blame for an error, for classifying the failures without matching on their message
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ErrorCategory {
+    /// the record itself is wrong, or the client can't afford it (e.g. insufficient funds)
+    ClientFault,
+    /// the engine hit its own limits, or an inconsistent state (e.g. an overflow)
+    SystemFault,
+    /// a dispute/resolve/chargeback that can't be applied, dropped unless reported on request
+    Ignored,
+}
+
+impl Errors {
+    pub fn category(&self) -> ErrorCategory {
+        match self {
+            Errors::AccountLocked(_)
+            | Errors::AccountFrozen(_)
+            | Errors::BalanceCapExceeded(_)
+            | Errors::InsuficientFunds(_)
+            | Errors::ReservedClient(_)
+            | Errors::TransactionIdAlreadyUsed { .. }
+            | Errors::TransactionIdUsedByAnotherClient(..)
+            | Errors::DisputeTypeMismatch(..)
+            | Errors::InvalidAmount(_)
+            | Errors::TooManyDecimalPlaces(_)
+            | Errors::NegativeAmount(_)
+            | Errors::MissingAmount(_)
+            | Errors::UnexpectedAmount(..)
+            | Errors::ZeroAmount(_)
+            | Errors::ResolveExceedsHeld(..)
+            | Errors::OutOfOrder(..) => ErrorCategory::ClientFault,
+            Errors::FundsOverflow(_)
+            | Errors::HeldUnderflow(_)
+            | Errors::AccountOverlap(_)
+            | Errors::UnsupportedOperation(..)
+            | Errors::ReconciliationMismatch { .. }
+            | Errors::CloseoutTxIdsExhausted(_) => ErrorCategory::SystemFault,
+            Errors::UnknownTransaction(_)
+            | Errors::ClientMismatch(..)
+            | Errors::CurrencyMismatch(..)
+            | Errors::TransactionNotDisputable(_)
+            | Errors::TransactionAlreadySettled(_)
+            | Errors::ResolveOnChargedBack(_)
+            | Errors::TransactionAlreadyReversed(_)
+            | Errors::AlreadyChargedBack(_) => ErrorCategory::Ignored,
+        }
+    }
+
+    // a stable identifier of the variant, unlike the message it never changes
+    pub fn code(&self) -> &'static str {
+        match self {
+            Errors::AccountLocked(_) => "ACCOUNT_LOCKED",
+            Errors::AccountFrozen(_) => "ACCOUNT_FROZEN",
+            Errors::BalanceCapExceeded(_) => "BALANCE_CAP_EXCEEDED",
+            Errors::InsuficientFunds(_) => "INSUFFICIENT_FUNDS",
+            Errors::FundsOverflow(_) => "FUNDS_OVERFLOW",
+            Errors::HeldUnderflow(_) => "HELD_UNDERFLOW",
+            Errors::ReservedClient(_) => "RESERVED_CLIENT",
+            Errors::AccountOverlap(_) => "ACCOUNT_OVERLAP",
+            Errors::TransactionIdAlreadyUsed { .. } => "TX_ID_ALREADY_USED",
+            Errors::TransactionIdUsedByAnotherClient(..) => "TX_ID_USED_BY_ANOTHER_CLIENT",
+            Errors::CloseoutTxIdsExhausted(_) => "CLOSEOUT_TX_IDS_EXHAUSTED",
+            Errors::UnknownTransaction(_) => "UNKNOWN_TRANSACTION",
+            Errors::ClientMismatch(..) => "CLIENT_MISMATCH",
+            Errors::CurrencyMismatch(..) => "CURRENCY_MISMATCH",
+            Errors::TransactionNotDisputable(_) => "NOT_DISPUTABLE",
+            Errors::TransactionAlreadySettled(_) => "ALREADY_SETTLED",
+            Errors::ResolveOnChargedBack(_) => "RESOLVE_ON_CHARGED_BACK",
+            Errors::TransactionAlreadyReversed(_) => "ALREADY_REVERSED",
+            Errors::AlreadyChargedBack(_) => "ALREADY_CHARGED_BACK",
+            Errors::DisputeTypeMismatch(..) => "DISPUTE_TYPE_MISMATCH",
+            Errors::UnsupportedOperation(..) => "UNSUPPORTED_OPERATION",
+            Errors::InvalidAmount(_) => "INVALID_AMOUNT",
+            Errors::TooManyDecimalPlaces(_) => "TOO_MANY_DECIMAL_PLACES",
+            Errors::NegativeAmount(_) => "NEGATIVE_AMOUNT",
+            Errors::MissingAmount(_) => "MISSING_AMOUNT",
+            Errors::UnexpectedAmount(..) => "UNEXPECTED_AMOUNT",
+            Errors::ZeroAmount(_) => "ZERO_AMOUNT",
+            Errors::ResolveExceedsHeld(..) => "RESOLVE_EXCEEDS_HELD",
+            Errors::ReconciliationMismatch { .. } => "RECONCILIATION_MISMATCH",
+            Errors::OutOfOrder(..) => "OUT_OF_ORDER",
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rust_decimal_macros::dec;
+    use std::collections::HashSet;
+
+    #[test]
+    fn test_every_variant_has_a_category_and_a_unique_code() {
+        use ErrorCategory::*;
+        let epoch = DateTime::<Utc>::UNIX_EPOCH;
+        let cases = [
+            (Errors::AccountLocked(1), ClientFault, "ACCOUNT_LOCKED"),
+            (Errors::AccountFrozen(1), ClientFault, "ACCOUNT_FROZEN"),
+            (
+                Errors::BalanceCapExceeded(1),
+                ClientFault,
+                "BALANCE_CAP_EXCEEDED",
+            ),
+            (
+                Errors::InsuficientFunds(1),
+                ClientFault,
+                "INSUFFICIENT_FUNDS",
+            ),
+            (Errors::FundsOverflow(1), SystemFault, "FUNDS_OVERFLOW"),
+            (Errors::HeldUnderflow(1), SystemFault, "HELD_UNDERFLOW"),
+            (Errors::ReservedClient(1), ClientFault, "RESERVED_CLIENT"),
+            (Errors::AccountOverlap(1), SystemFault, "ACCOUNT_OVERLAP"),
+            (
+                Errors::TransactionIdAlreadyUsed {
+                    tx: 1,
+                    incoming: OperationType::Withdrawal,
+                    existing: OperationType::Deposit,
+                },
+                ClientFault,
+                "TX_ID_ALREADY_USED",
+            ),
+            (
+                Errors::TransactionIdUsedByAnotherClient(1, 2, 3),
+                ClientFault,
+                "TX_ID_USED_BY_ANOTHER_CLIENT",
+            ),
+            (
+                Errors::UnknownTransaction(1),
+                Ignored,
+                "UNKNOWN_TRANSACTION",
+            ),
+            (Errors::ClientMismatch(1, 2), Ignored, "CLIENT_MISMATCH"),
+            (
+                Errors::CurrencyMismatch(1, None),
+                Ignored,
+                "CURRENCY_MISMATCH",
+            ),
+            (
+                Errors::TransactionNotDisputable(1),
+                Ignored,
+                "NOT_DISPUTABLE",
+            ),
+            (
+                Errors::TransactionAlreadySettled(1),
+                Ignored,
+                "ALREADY_SETTLED",
+            ),
+            (
+                Errors::ResolveOnChargedBack(1),
+                Ignored,
+                "RESOLVE_ON_CHARGED_BACK",
+            ),
+            (
+                Errors::TransactionAlreadyReversed(1),
+                Ignored,
+                "ALREADY_REVERSED",
+            ),
+            (
+                Errors::AlreadyChargedBack(1),
+                Ignored,
+                "ALREADY_CHARGED_BACK",
+            ),
+            (
+                Errors::DisputeTypeMismatch(1, OperationType::Withdrawal),
+                ClientFault,
+                "DISPUTE_TYPE_MISMATCH",
+            ),
+            (
+                Errors::UnsupportedOperation(1, OperationType::Dispute),
+                SystemFault,
+                "UNSUPPORTED_OPERATION",
+            ),
+            (
+                Errors::InvalidAmount("x".to_string()),
+                ClientFault,
+                "INVALID_AMOUNT",
+            ),
+            (
+                Errors::TooManyDecimalPlaces(dec!(0.00001)),
+                ClientFault,
+                "TOO_MANY_DECIMAL_PLACES",
+            ),
+            (
+                Errors::NegativeAmount(dec!(-1)),
+                ClientFault,
+                "NEGATIVE_AMOUNT",
+            ),
+            (Errors::MissingAmount(1), ClientFault, "MISSING_AMOUNT"),
+            (
+                Errors::UnexpectedAmount(1, dec!(1)),
+                ClientFault,
+                "UNEXPECTED_AMOUNT",
+            ),
+            (Errors::ZeroAmount(1), ClientFault, "ZERO_AMOUNT"),
+            (
+                Errors::ResolveExceedsHeld(1, dec!(1)),
+                ClientFault,
+                "RESOLVE_EXCEEDS_HELD",
+            ),
+            (
+                Errors::ReconciliationMismatch {
+                    expected: dec!(1),
+                    actual: dec!(2),
+                },
+                SystemFault,
+                "RECONCILIATION_MISMATCH",
+            ),
+            (
+                Errors::OutOfOrder(1, epoch, epoch),
+                ClientFault,
+                "OUT_OF_ORDER",
+            ),
+            (
+                Errors::CloseoutTxIdsExhausted(1),
+                SystemFault,
+                "CLOSEOUT_TX_IDS_EXHAUSTED",
+            ),
+        ];
+
+        for (error, category, code) in &cases {
+            assert_eq!(error.category(), *category, "{:?}", error);
+            assert_eq!(error.code(), *code, "{:?}", error);
+        }
+        let codes: HashSet<_> = cases.iter().map(|(error, _, _)| error.code()).collect();
+        assert_eq!(codes.len(), cases.len());
+    }
+
+    #[test]
+    fn test_currency_mismatch_names_the_currency() {
+        assert_eq!(
+            Errors::CurrencyMismatch(3, Some("USD".to_string())).to_string(),
+            "Transaction ID 3 is not held in the currency USD!"
+        );
+        assert_eq!(
+            Errors::CurrencyMismatch(3, None).to_string(),
+            "Transaction ID 3 is not held in the currency (none)!"
+        );
+    }
+
+    #[test]
+    fn test_operation_types_are_named_by_their_input_token() {
+        assert_eq!(
+            Errors::DisputeTypeMismatch(3, OperationType::Withdrawal).to_string(),
+            "Transaction ID 3 refers to a withdrawal operation, only deposits might be disputed!"
+        );
+        assert_eq!(
+            Errors::UnsupportedOperation(3, OperationType::Dispute).to_string(),
+            "Transaction ID 3 refers to a dispute operation, which can't be disputed!"
+        );
+    }
 }
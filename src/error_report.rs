@@ -0,0 +1,75 @@
+use serde::Serialize;
+use std::path::Path;
+
+#[derive(Debug, Serialize)]
+struct DiscardedRecord {
+    input: String,
+    line: u64,
+    tx: Option<u32>,
+    reason: String,
+}
+
+// the records discarded from all the inputs, written out once the processing completes
+#[derive(Debug, Default)]
+pub struct ErrorReport {
+    records: Vec<DiscardedRecord>,
+}
+
+impl ErrorReport {
+    // the tx id is unknown for a record that couldn't be read
+    pub fn add(&mut self, input: &str, line: u64, tx: Option<u32>, reason: impl ToString) {
+        self.records.push(DiscardedRecord {
+            input: input.to_string(),
+            line,
+            tx,
+            reason: reason.to_string(),
+        });
+    }
+
+    // the header is written even without any discarded record, so the report is always readable
+    pub fn write<P: AsRef<Path>>(&self, path: P) -> csv::Result<()> {
+        let mut writer = csv::WriterBuilder::new()
+            .has_headers(false)
+            .from_path(path)?;
+        writer.write_record(["input", "line", "tx", "reason"])?;
+        for record in &self.records {
+            writer.serialize(record)?;
+        }
+        writer.flush()?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_report_lists_discarded_records_in_order() {
+        let path = std::env::temp_dir().join(format!("error-report-{}.csv", std::process::id()));
+        let mut report = ErrorReport::default();
+        report.write(&path).unwrap();
+        assert_eq!(
+            std::fs::read_to_string(&path).unwrap(),
+            "input,line,tx,reason\n"
+        );
+
+        report.add("a.csv", 3, None, "unknown variant `bogus`");
+        report.add(
+            "a.csv",
+            5,
+            Some(7),
+            "Not enough funds available for account 1!",
+        );
+        report.write(&path).unwrap();
+        let content = std::fs::read_to_string(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+
+        assert_eq!(
+            content,
+            "input,line,tx,reason\n\
+             a.csv,3,,unknown variant `bogus`\n\
+             a.csv,5,7,Not enough funds available for account 1!\n"
+        );
+    }
+}
@@ -0,0 +1,75 @@
+use rust_decimal::Decimal;
+use std::str::FromStr;
+
+// a fee charged on top of every successful withdrawal
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum WithdrawalFee {
+    Flat(Decimal),
+    Percent(Decimal),
+}
+
+impl WithdrawalFee {
+    // the fee for the given withdrawn amount, percent fees are rounded to 4 decimal places
+    pub fn fee_for(&self, amount: Decimal) -> Option<Decimal> {
+        match self {
+            WithdrawalFee::Flat(fee) => Some(*fee),
+            WithdrawalFee::Percent(percent) => amount
+                .checked_mul(*percent)
+                .and_then(|fee| fee.checked_div(Decimal::ONE_HUNDRED))
+                .map(|fee| fee.round_dp(4)),
+        }
+    }
+}
+
+// `0.5` is a flat fee, while `1.5%` is a percentage of the withdrawn amount
+impl FromStr for WithdrawalFee {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (value, fee): (&str, fn(Decimal) -> WithdrawalFee) = match s.strip_suffix('%') {
+            Some(percent) => (percent, WithdrawalFee::Percent),
+            None => (s, WithdrawalFee::Flat),
+        };
+        let value = Decimal::from_str(value.trim()).map_err(|err| err.to_string())?;
+        if value.is_sign_negative() {
+            return Err("the fee can't be negative".to_string());
+        }
+        Ok(fee(value))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rust_decimal_macros::dec;
+
+    #[test]
+    fn test_parsing_flat_and_percent_fees() {
+        assert_eq!(
+            "0.5".parse::<WithdrawalFee>(),
+            Ok(WithdrawalFee::Flat(dec!(0.5)))
+        );
+        assert_eq!(
+            "1.5%".parse::<WithdrawalFee>(),
+            Ok(WithdrawalFee::Percent(dec!(1.5)))
+        );
+        assert!("-1".parse::<WithdrawalFee>().is_err());
+        assert!("abc%".parse::<WithdrawalFee>().is_err());
+    }
+
+    #[test]
+    fn test_fee_for_withdrawn_amount() {
+        assert_eq!(
+            WithdrawalFee::Flat(dec!(0.5)).fee_for(dec!(100)),
+            Some(dec!(0.5))
+        );
+        assert_eq!(
+            WithdrawalFee::Percent(dec!(1.5)).fee_for(dec!(100)),
+            Some(dec!(1.5))
+        );
+        assert_eq!(
+            WithdrawalFee::Percent(dec!(1)).fee_for(dec!(0.12345)),
+            Some(dec!(0.0012))
+        );
+    }
+}
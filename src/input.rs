@@ -0,0 +1,253 @@
+use anyhow::{bail, Result};
+use payments_engine::Record;
+use rayon::prelude::*;
+use std::io::{BufRead, BufReader, Read};
+
+#[derive(clap::ValueEnum, Clone, Copy, Debug)]
+pub enum InputFormat {
+    Csv,
+    // one JSON object per line, with the same fields as the CSV columns
+    Ndjson,
+}
+
+// columns every CSV input has to have, and the optional ones
+pub const REQUIRED_COLUMNS: [&str; 4] = ["type", "client", "tx", "amount"];
+const OPTIONAL_COLUMNS: [&str; 2] = ["timestamp", "currency"];
+
+// number of CSV rows read ahead, and deserialized in parallel, by the parallel parsing
+const PARSE_BATCH_SIZE: usize = 4096;
+
+// the input behind a path: stdin for `-`, the response body for an `http://`/`https://` URL (with
+// the `http` feature), and a local file otherwise. The body is streamed as the records are read,
+// it's never buffered as a whole
+pub fn open(path: &str) -> Result<Box<dyn Read>> {
+    if path == "-" {
+        return Ok(Box::new(std::io::stdin().lock()));
+    }
+    if is_url(path) {
+        return open_url(path);
+    }
+    Ok(Box::new(std::fs::File::open(path)?))
+}
+
+pub fn is_url(path: &str) -> bool {
+    path.starts_with("http://") || path.starts_with("https://")
+}
+
+#[cfg(feature = "http")]
+fn open_url(url: &str) -> Result<Box<dyn Read>> {
+    //an error status (e.g. 404) fails the same way as a missing local file
+    let response = ureq::get(url)
+        .call()
+        .map_err(|err| anyhow::anyhow!("{}: {}", url, err))?;
+    Ok(Box::new(response.into_reader()))
+}
+
+#[cfg(not(feature = "http"))]
+fn open_url(url: &str) -> Result<Box<dyn Read>> {
+    bail!("{}: reading over HTTP(S) requires the `http` feature", url)
+}
+
+// the records of the input in their order, along with their 1-based line number; a record that
+// can't be read is yielded as an error, so it's counted (or reported) the same way for every format.
+// A header row not matching the columns fails right away, as no record could be read anyway.
+// With `parallel` the CSV rows are read in batches, each batch deserialized on the rayon thread
+// pool, while the records are still yielded in the input order (the NDJSON input ignores it)
+pub fn records<'a, R: Read + 'a>(
+    input: R,
+    format: InputFormat,
+    csv_builder: &csv::ReaderBuilder,
+    columns: Option<&'a csv::StringRecord>,
+    parallel: bool,
+) -> Result<Box<dyn Iterator<Item = (u64, Result<Record>)> + 'a>> {
+    if let InputFormat::Ndjson = format {
+        return Ok(Box::new(
+            BufReader::new(input)
+                .lines()
+                .zip(1..)
+                .filter(|(line, _)| !matches!(line, Ok(line) if line.trim().is_empty()))
+                .map(|(line, number)| (number, json_record(line))),
+        ));
+    }
+    let mut reader = csv_builder.from_reader(input);
+    //the header row (if any) is skipped when the columns are given, the fields are mapped by
+    //their position instead
+    let headers = match columns {
+        Some(columns) => Ok(columns.clone()),
+        None => reader.headers().cloned(),
+    };
+    let headers = match headers {
+        Ok(headers) => headers,
+        Err(err) => {
+            return Ok(Box::new(std::iter::once((
+                line_of(&err, 1),
+                Err(err.into()),
+            ))))
+        }
+    };
+    if columns.is_none() {
+        validate_header(&headers)?;
+    }
+    let headers = headers.into_byte_record();
+    //errors without a position (e.g. I/O ones) are attributed to the line following the last one
+    let mut last_line = 0;
+    let mut rows = reader.into_byte_records().map(move |row| {
+        last_line = match &row {
+            Ok(row) => row.position().map_or(last_line + 1, csv::Position::line),
+            Err(err) => line_of(err, last_line + 1),
+        };
+        (last_line, row)
+    });
+    if !parallel {
+        return Ok(Box::new(
+            rows.map(move |(line, row)| (line, csv_record(row, &headers))),
+        ));
+    }
+    let mut parsed = Vec::new().into_iter();
+    Ok(Box::new(std::iter::from_fn(move || loop {
+        if let Some(record) = parsed.next() {
+            return Some(record);
+        }
+        let batch: Vec<_> = rows.by_ref().take(PARSE_BATCH_SIZE).collect();
+        if batch.is_empty() {
+            return None;
+        }
+        //an indexed parallel iterator collects in the input order
+        parsed = batch
+            .into_par_iter()
+            .map(|(line, row)| (line, csv_record(row, &headers)))
+            .collect::<Vec<_>>()
+            .into_iter();
+    })))
+}
+
+// e.g. a mistyped `ammount` would otherwise turn every deposit into a missing amount error; an
+// empty input has no header row at all, and nothing to be processed either
+fn validate_header(headers: &csv::StringRecord) -> Result<()> {
+    if headers.is_empty() {
+        return Ok(());
+    }
+    let unexpected: Vec<_> = headers
+        .iter()
+        .filter(|column| !REQUIRED_COLUMNS.contains(column) && !OPTIONAL_COLUMNS.contains(column))
+        .map(|column| format!("`{}`", column))
+        .collect();
+    let missing: Vec<_> = REQUIRED_COLUMNS
+        .iter()
+        .filter(|required| !headers.iter().any(|column| column == **required))
+        .map(|column| format!("`{}`", column))
+        .collect();
+    if !unexpected.is_empty() || !missing.is_empty() {
+        bail!(
+            "Unexpected header row, unexpected column(s): [{}], missing column(s): [{}]",
+            unexpected.join(", "),
+            missing.join(", ")
+        );
+    }
+    Ok(())
+}
+
+fn csv_record(row: csv::Result<csv::ByteRecord>, headers: &csv::ByteRecord) -> Result<Record> {
+    Ok(row?.deserialize(Some(headers))?)
+}
+
+fn json_record(line: std::io::Result<String>) -> Result<Record> {
+    Ok(serde_json::from_str(&line?)?)
+}
+
+fn line_of(err: &csv::Error, fallback: u64) -> u64 {
+    err.position().map_or(fallback, csv::Position::line)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use payments_engine::OperationType;
+    use rust_decimal_macros::dec;
+
+    #[test]
+    fn test_ndjson_records_are_read_line_by_line() {
+        let input = r#"{"type": "deposit", "client": 1, "tx": 1, "amount": 1.5}
+{"type": "withdrawal", "client": 1, "tx": 2, "amount": "0.25"}
+
+{"type": "dispute", "client": 1, "tx": 1}
+{"type": "unknown", "client": 1, "tx": 3}
+"#;
+        let records: Vec<_> = records(
+            input.as_bytes(),
+            InputFormat::Ndjson,
+            &csv::ReaderBuilder::new(),
+            None,
+            false,
+        )
+        .unwrap()
+        .collect();
+
+        assert_eq!(
+            records.iter().map(|(line, _)| *line).collect::<Vec<_>>(),
+            vec![1, 2, 4, 5]
+        );
+        let record = records[0].1.as_ref().unwrap();
+        assert_eq!(record.r#type, OperationType::Deposit);
+        assert_eq!(record.amount.map(|amount| *amount), Some(dec!(1.5)));
+        let record = records[1].1.as_ref().unwrap();
+        assert_eq!(record.r#type, OperationType::Withdrawal);
+        assert_eq!(record.amount.map(|amount| *amount), Some(dec!(0.25)));
+        let record = records[2].1.as_ref().unwrap();
+        assert_eq!(record.r#type, OperationType::Dispute);
+        assert!(record.amount.is_none());
+        assert!(records[3].1.is_err());
+    }
+
+    #[test]
+    fn test_csv_records_carry_their_line_numbers() {
+        let input = "type,client,tx,amount\ndeposit,1,1,1.0\nbogus,1,2,1.0\nwithdrawal,1,3,0.5\n";
+        let records: Vec<_> = records(
+            input.as_bytes(),
+            InputFormat::Csv,
+            &csv::ReaderBuilder::new(),
+            None,
+            false,
+        )
+        .unwrap()
+        .collect();
+
+        assert_eq!(
+            records.iter().map(|(line, _)| *line).collect::<Vec<_>>(),
+            vec![2, 3, 4]
+        );
+        assert!(records[0].1.is_ok());
+        assert!(records[1].1.is_err());
+        assert!(records[2].1.is_ok());
+    }
+
+    #[test]
+    fn test_header_row_is_validated_up_front() {
+        let result = records(
+            "type,client,tx,ammount\ndeposit,1,1,1.0\n".as_bytes(),
+            InputFormat::Csv,
+            csv::ReaderBuilder::new().trim(csv::Trim::All),
+            None,
+            false,
+        );
+        let message = result.err().unwrap().to_string();
+        assert!(message.contains("unexpected column(s): [`ammount`]"));
+        assert!(message.contains("missing column(s): [`amount`]"));
+
+        for header in [
+            "type, client, tx, amount",
+            "tx,type,amount,client,timestamp",
+            "",
+        ] {
+            let input = format!("{}\n", header);
+            assert!(records(
+                input.as_bytes(),
+                InputFormat::Csv,
+                csv::ReaderBuilder::new().trim(csv::Trim::All),
+                None,
+                false,
+            )
+            .is_ok());
+        }
+    }
+}
@@ -0,0 +1,35 @@
+//! Transactions engine, processing deposits, withdrawals, and the dispute family operations
+//! (dispute, resolve, chargeback) on client accounts.
+//!
+//! The engine is independent of any input format, records might come from anywhere:
+//! ```
+//! use payments_engine::{OperationType, Record, TransactionManager};
+//! use rust_decimal_macros::dec;
+//!
+//! let mut manager = TransactionManager::new();
+//! manager
+//!     .parse_entry(&Record::new(OperationType::Deposit, 1, 1, Some(dec!(1.5).into())))
+//!     .unwrap();
+//! assert_eq!(manager.accounts().count(), 1);
+//! ```
+
+mod account;
+mod amount;
+mod error;
+mod fee;
+mod record;
+mod reserved;
+#[cfg(feature = "async")]
+mod stream;
+mod transaction_manager;
+
+pub use account::{Account, AccountJson, AccountOutput, OutputOptions, OverflowPolicy, Rounding};
+pub use amount::Amount;
+pub use error::{ErrorCategory, Errors};
+pub use fee::WithdrawalFee;
+pub use record::{OperationType, Record};
+pub use reserved::ReservedClients;
+pub use transaction_manager::{
+    DisputeState, DroppedRecord, FailureCounts, IgnoredOperationPolicy, Metrics, ParseOutcome,
+    ProcessedCounts, RunSummary, TransactionManager, TransactionSummary, WithdrawalDisputeMode,
+};
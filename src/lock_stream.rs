@@ -0,0 +1,39 @@
+use std::fs::{File, OpenOptions};
+use std::io::{Result, Write};
+use std::path::Path;
+
+// appends a `client,tx` line for every account locked by a chargeback, flushed right away
+// so the file can be tailed while the input is still being processed
+pub struct LockStream {
+    file: File,
+}
+
+impl LockStream {
+    pub fn open<P: AsRef<Path>>(path: P) -> Result<Self> {
+        let file = OpenOptions::new().create(true).append(true).open(path)?;
+        Ok(Self { file })
+    }
+
+    pub fn record(&mut self, client: u16, tx: u32) -> Result<()> {
+        writeln!(self.file, "{},{}", client, tx)?;
+        self.file.flush()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_lock_stream_appends_entries_immediately() {
+        let path = std::env::temp_dir().join(format!("lock-stream-{}.csv", std::process::id()));
+        let mut stream = LockStream::open(&path).unwrap();
+
+        stream.record(1, 10).unwrap();
+        assert_eq!(std::fs::read_to_string(&path).unwrap(), "1,10\n");
+
+        stream.record(2, 20).unwrap();
+        assert_eq!(std::fs::read_to_string(&path).unwrap(), "1,10\n2,20\n");
+        std::fs::remove_file(&path).unwrap();
+    }
+}
@@ -1,41 +1,808 @@
 use anyhow::Result;
-use clap::Parser;
+use clap::{Parser, Subcommand};
+use payments_engine::{
+    Errors, IgnoredOperationPolicy, OutputOptions, OverflowPolicy, ParseOutcome, ReservedClients,
+    Rounding, TransactionManager, WithdrawalDisputeMode, WithdrawalFee,
+};
+use std::collections::HashMap;
+use std::io::Write;
 
-mod account;
-mod amount;
-mod error;
-mod record;
-mod transaction_manager;
+mod closeout;
+mod compression;
+mod error_report;
+mod input;
+mod lock_stream;
+mod offset;
+mod progress;
+mod run_metadata;
+mod verify;
+
+#[derive(clap::ValueEnum, Clone, Copy, Debug)]
+enum OutputFormat {
+    Csv,
+    Json,
+}
+
+#[derive(clap::ValueEnum, Clone, Copy, Debug)]
+enum AccountOrder {
+    /// ascending client id, stable across runs
+    Client,
+    /// the order the clients were first seen in the input
+    FirstSeen,
+}
 
 #[derive(Parser, Debug)]
+#[clap(args_conflicts_with_subcommands = true, subcommand_negates_reqs = true)]
+struct Cli {
+    #[clap(subcommand)]
+    command: Option<Command>,
+    // without a subcommand, the arguments are processed as by `process`
+    #[clap(flatten)]
+    args: Args,
+}
+
+#[derive(Subcommand, Debug)]
+enum Command {
+    /// process the input files and write out the accounts (the default)
+    Process(Args),
+    /// validate the input without producing any output, as `--check` does
+    Validate(Args),
+    /// process the input again on top of a snapshot, skipping the deposits/withdrawals at or below a
+    /// checkpoint
+    Replay {
+        /// checkpoint to replay from, i.e. the highest deposit/withdrawal tx id processed before
+        #[clap(long, value_name = "SEQ")]
+        from: u32,
+        #[clap(flatten)]
+        args: Args,
+    },
+}
+
+#[derive(clap::Args, Debug)]
 struct Args {
-    csv_path: String,
+    /// input files, processed in the given order as a single stream of transactions, `-` reads the standard input
+    #[clap(value_name = "CSV_PATH", required = true)]
+    csv_paths: Vec<String>,
+    /// report dispute/resolve/chargeback operations that can't be applied, instead of silently dropping them
+    #[clap(long)]
+    report_ignored: bool,
+    /// format of the input files
+    #[clap(long, value_enum, default_value = "csv")]
+    input_format: input::InputFormat,
+    /// field delimiter of the CSV input, one of `,`, `;`, or `\t`
+    #[clap(long, default_value = ",", value_parser = parse_delimiter)]
+    delimiter: u8,
+    /// quote character of the CSV input
+    #[clap(long, default_value = "\"", value_parser = parse_quote)]
+    quote: u8,
+    /// the input files have no header row, the columns are `type,client,tx,amount` unless --columns says otherwise
+    #[clap(long)]
+    no_headers: bool,
+    /// names of the input columns in their order (e.g. `client,type,amount,tx`), overriding the header row
+    #[clap(long, value_parser = parse_columns)]
+    columns: Option<csv::StringRecord>,
+    /// process only the first N records read successfully, from all of the input files
+    #[clap(long, value_parser = clap::value_parser!(u64).range(1..))]
+    limit: Option<u64>,
+    /// abort on the first record that can't be read or applied (or account that can't be written),
+    /// instead of skipping it
+    #[clap(long)]
+    strict: bool,
+    /// exit with an error once everything is processed (and written out), if any record was discarded
+    #[clap(long)]
+    fail_on_discard: bool,
+    /// print the number of records processed so far (and the share of the input file read) to stderr, every few seconds
+    #[clap(long)]
+    progress: bool,
+    /// deserialize the CSV records on a thread pool, they're still applied one by one in the input order
+    #[clap(long)]
+    parallel_parse: bool,
+    /// validate the input without producing any output, failing if a record can't be read or applied
+    #[clap(long, conflicts_with_all = &["verify", "persist-offset", "resume-offset"])]
+    check: bool,
+    // set by the `replay` subcommand
+    #[clap(skip)]
+    replay_from: Option<u32>,
+    /// restore the accounts and the transactions saved by `--save-snapshot` before processing the input
+    #[clap(long, value_name = "PATH")]
+    snapshot: Option<String>,
+    /// save the accounts and the transactions into the given file once the input is processed
+    #[clap(long, value_name = "PATH")]
+    save_snapshot: Option<String>,
+    /// skip the deposits/withdrawals with a tx id below the given one (disputes are still processed)
+    #[clap(long)]
+    since_tx: Option<u32>,
+    /// skip the deposits/withdrawals with a tx id above the given one (disputes are still processed)
+    #[clap(long)]
+    until_tx: Option<u32>,
+    /// number of decimal places of the output amounts (rounded as set by --rounding)
+    #[clap(long, default_value_t = 4, value_parser = clap::value_parser!(u32).range(0..=8))]
+    precision: u32,
+    /// rounding of the output amounts: `half-even`, `half-up`, or `truncate`
+    #[clap(long, default_value = "half-even")]
+    rounding: Rounding,
+    /// format of the produced output
+    #[clap(long, value_enum, default_value = "csv")]
+    format: OutputFormat,
+    /// order of the accounts in the output
+    #[clap(long, value_enum, default_value = "client")]
+    order: AccountOrder,
+    /// print the accounts one per line for eyeballing (e.g. `client=1 available=1.0000 ...`), instead of CSV
+    #[clap(long, conflicts_with = "format")]
+    pretty: bool,
+    /// write the output into the given file (created or truncated) instead of stdout
+    #[clap(long)]
+    output: Option<String>,
+    /// compression applied to the produced output
+    #[clap(long, value_enum, default_value = "none")]
+    compress: compression::Compression,
+    /// suppress unlocked accounts whose total is below the given amount
+    #[clap(long)]
+    min_balance: Option<rust_decimal::Decimal>,
+    /// output only the accounts holding funds, or locked
+    #[clap(long)]
+    only_flagged: bool,
+    /// write every discarded record, with its line, tx id, and the reason, into the given CSV file
+    #[clap(long)]
+    error_report: Option<String>,
+    /// write the number of processed records per client and operation type into the given CSV file
+    #[clap(long)]
+    metrics: Option<String>,
+    /// write the engine version, a run id, and the input digest into the given file
+    #[clap(long)]
+    run_metadata: Option<String>,
+    /// write the synthetic records zeroing the available funds of every account into the given
+    /// ledger file (in the input format), once the input is processed
+    #[clap(long, value_name = "PATH")]
+    closeout: Option<String>,
+    /// apply the close-out records as well, so the written accounts have no available funds left
+    #[clap(long, requires = "closeout")]
+    apply_closeout: bool,
+    /// append a `client,tx` line to the given file as soon as a chargeback locks an account
+    #[clap(long)]
+    lock_stream: Option<String>,
+    /// report disputes referencing anything else than a deposit, instead of applying them
+    #[clap(long)]
+    deposit_disputes_only: bool,
+    /// reject the records of the given client ids or inclusive ranges of them, e.g. `0,60000-65535`
+    #[clap(long)]
+    reserved_clients: Option<ReservedClients>,
+    /// reject deposits and withdrawals of exactly zero
+    #[clap(long)]
+    reject_zero: bool,
+    /// lock the account on a chargeback of a withdrawal as well
+    #[clap(long)]
+    lock_on_withdrawal_chargeback: bool,
+    /// what a dispute of a withdrawal does: `none` (only marks it disputed), or `hold` (holds the
+    /// withdrawn amount until it's resolved or charged back)
+    #[clap(long, default_value = "none")]
+    withdrawal_dispute_mode: WithdrawalDisputeMode,
+    /// reject records timestamped before the last applied one (records without a timestamp are
+    /// never rejected)
+    #[clap(long)]
+    enforce_order: bool,
+    /// multiply every deposit/withdrawal amount by the given (positive) factor before processing
+    #[clap(long, value_parser = parse_scale_factor)]
+    scale_amounts: Option<rust_decimal::Decimal>,
+    /// store every incoming amount with exactly the given number of decimal places (4 to 28), e.g. `5` as `5.0000`
+    #[clap(long, value_parser = clap::value_parser!(u32).range(4..=28))]
+    normalize_scale: Option<u32>,
+    /// add a `held_exceeds_available` column to the output
+    #[clap(long)]
+    held_exceeds_available: bool,
+    /// add the `dispute_count` and `chargeback_count` columns to the output
+    #[clap(long)]
+    with_fraud_columns: bool,
+    /// add the `peak_held` column to the output, the highest held funds the account ever reached
+    #[clap(long)]
+    with_peak_held: bool,
+    /// periodically persist the number of processed records into `<first csv_path>.offset`, along with the state they left behind
+    #[clap(long)]
+    persist_offset: bool,
+    /// restore the state saved with `<first csv_path>.offset`, and skip the records it counts (implies --persist-offset)
+    #[clap(long)]
+    resume_offset: bool,
+    /// number of records between two offset writes
+    #[clap(long, default_value_t = 1000)]
+    offset_interval: u64,
+    /// print a summary of the accounts, transactions, and failed operations to stderr
+    #[clap(long)]
+    summary: bool,
+    /// fee charged on every successful withdrawal, either flat (e.g. `0.5`) or a percentage (e.g. `1.5%`)
+    #[clap(long)]
+    withdrawal_fee: Option<WithdrawalFee>,
+    /// let withdrawals take the available funds of every account down to minus the given amount
+    #[clap(long, value_parser = parse_credit_limit)]
+    credit_limit: Option<rust_decimal::Decimal>,
+    /// reject the operations that would take the total funds of an account above the given amount
+    #[clap(long, value_parser = parse_credit_limit)]
+    max_balance: Option<rust_decimal::Decimal>,
+    /// what an overflow of the funds does: `error` rejects the operation, `saturate` clamps the funds (losing the excess)
+    #[clap(long, default_value = "error")]
+    on_overflow: OverflowPolicy,
+    /// compare the resulting accounts against the expected summary instead of writing them out
+    #[clap(long)]
+    verify: Option<String>,
+    /// zero-pad the client column of the output to the given number of digits (a string in JSON)
+    #[clap(long)]
+    client_width: Option<usize>,
+    /// defer dispute/resolve/chargeback records referencing unknown transactions for up to the given number of records
+    #[clap(long, conflicts_with_all = &["persist-offset", "resume-offset"])]
+    buffer_out_of_order: Option<u64>,
+    /// drop the charged back transactions, and the undisputed ones older than the given number of
+    /// records, to bound the memory use (later disputes of them are ignored)
+    #[clap(long, value_parser = clap::value_parser!(u64).range(1..))]
+    prune_after: Option<u64>,
+}
+
+fn parse_scale_factor(factor: &str) -> Result<rust_decimal::Decimal, String> {
+    match factor.parse::<rust_decimal::Decimal>() {
+        Ok(factor) if factor.is_sign_positive() && !factor.is_zero() => Ok(factor),
+        Ok(_) => Err("the factor has to be greater than zero".to_string()),
+        Err(err) => Err(err.to_string()),
+    }
+}
+
+fn parse_credit_limit(limit: &str) -> Result<rust_decimal::Decimal, String> {
+    match limit.parse::<rust_decimal::Decimal>() {
+        Ok(limit) if !limit.is_sign_negative() => Ok(limit),
+        Ok(_) => Err("the limit can't be negative".to_string()),
+        Err(err) => Err(err.to_string()),
+    }
+}
+
+fn parse_delimiter(delimiter: &str) -> Result<u8, String> {
+    match delimiter {
+        "," => Ok(b','),
+        ";" => Ok(b';'),
+        "\\t" | "\t" => Ok(b'\t'),
+        _ => Err("the delimiter has to be one of `,`, `;`, or `\\t`".to_string()),
+    }
+}
+
+fn parse_quote(quote: &str) -> Result<u8, String> {
+    match quote.as_bytes() {
+        [quote] if quote.is_ascii() => Ok(*quote),
+        _ => Err("the quote has to be a single ASCII character".to_string()),
+    }
+}
+
+// number of discarded records (per input file) whose line is listed in the discard summary
+const REPORTED_DISCARDED_LINES: usize = 10;
+
+// a dispute/resolve/chargeback that can never apply fails `--check`, though it's dropped silently
+// otherwise
+fn fails_check(outcome: ParseOutcome) -> bool {
+    matches!(
+        outcome,
+        ParseOutcome::IgnoredNoSuchTx
+            | ParseOutcome::IgnoredNotDisputable
+            | ParseOutcome::IgnoredNotDisputed
+    )
+}
+
+// least time between two progress reports of `--progress`
+const PROGRESS_INTERVAL: std::time::Duration = std::time::Duration::from_secs(2);
+
+fn parse_columns(columns: &str) -> Result<csv::StringRecord, String> {
+    let columns: Vec<String> = columns.split(',').map(|c| c.trim().to_string()).collect();
+    for required in input::REQUIRED_COLUMNS {
+        match columns.iter().filter(|column| *column == required).count() {
+            0 => return Err(format!("the `{}` column is missing", required)),
+            1 => {}
+            _ => return Err(format!("the `{}` column is given more than once", required)),
+        }
+    }
+    Ok(csv::StringRecord::from(columns))
 }
 
 fn main() -> Result<()> {
-    let args = Args::parse();
-    let mut reader = csv::ReaderBuilder::new()
-        .delimiter(b',')
-        .has_headers(true)
-        .flexible(true)
-        .trim(csv::Trim::All)
-        .from_path(args.csv_path)?;
+    let cli = Cli::parse();
+    let args = match cli.command {
+        None => cli.args,
+        Some(Command::Process(args)) => args,
+        Some(Command::Validate(mut args)) => {
+            if args.verify.is_some() || args.persist_offset || args.resume_offset {
+                anyhow::bail!("validate can't be combined with --verify or the offset options");
+            }
+            args.check = true;
+            args
+        }
+        Some(Command::Replay { from, mut args }) => {
+            //on a fresh state, the skipped records would be simply missing from the accounts
+            if args.snapshot.is_none() {
+                anyhow::bail!("replay needs the --snapshot the checkpoint was reached with");
+            }
+            args.replay_from = Some(from);
+            args
+        }
+    };
+    run(args)
+}
 
-    let entries = reader
-        .deserialize::<record::Record>()
-        .filter_map(|r| r.ok());
+fn run(args: Args) -> Result<()> {
+    let mut reader_builder = csv::ReaderBuilder::new();
+    reader_builder
+        .delimiter(args.delimiter)
+        .quote(args.quote)
+        .has_headers(!args.no_headers)
+        .flexible(true)
+        .trim(csv::Trim::All);
+    let mut input_digest = run_metadata::InputDigest::new(args.run_metadata.is_some());
+    let columns = match args.columns {
+        Some(columns) => Some(columns),
+        None if args.no_headers => Some(csv::StringRecord::from(input::REQUIRED_COLUMNS.to_vec())),
+        None => None,
+    };
 
-    let mut transactions_manager = transaction_manager::TransactionManager::new();
-    for e in entries {
-        if let Err(err) = transactions_manager.parse_entry(&e) {
-            eprintln!("Input parsing error: {:?}", err);
+    if let (Some(since), Some(until)) = (args.since_tx, args.until_tx) {
+        if since > until {
+            anyhow::bail!("--since-tx {} is above --until-tx {}", since, until);
         }
     }
-    let mut output_writer = csv::Writer::from_writer(std::io::stdout());
-    for acc in transactions_manager.accounts() {
-        if let Err(err) = output_writer.serialize(acc) {
-            eprintln!("Deserialisation error: {:?}", err);
+    let mut transactions_manager = TransactionManager::new();
+    if args.report_ignored {
+        transactions_manager.set_ignored_policy(IgnoredOperationPolicy::Report);
+    }
+    transactions_manager.set_tx_range(args.since_tx, args.until_tx);
+    transactions_manager.set_deposit_disputes_only(args.deposit_disputes_only);
+    transactions_manager.set_reject_zero(args.reject_zero);
+    transactions_manager.set_lock_on_withdrawal_chargeback(args.lock_on_withdrawal_chargeback);
+    transactions_manager.set_enforce_order(args.enforce_order);
+    transactions_manager.set_withdrawal_dispute_mode(args.withdrawal_dispute_mode);
+    if let Some(reserved) = args.reserved_clients {
+        transactions_manager.set_reserved_clients(reserved);
+    }
+    if let Some(fee) = args.withdrawal_fee {
+        transactions_manager.set_withdrawal_fee(fee);
+    }
+    if let Some(limit) = args.credit_limit {
+        transactions_manager.set_credit_limit(limit)?;
+    }
+    if let Some(limit) = args.max_balance {
+        transactions_manager.set_max_balance(limit)?;
+    }
+    transactions_manager.set_overflow_policy(args.on_overflow);
+    if let Some(window) = args.buffer_out_of_order {
+        transactions_manager.set_out_of_order_window(window);
+    }
+    if let Some(horizon) = args.prune_after {
+        transactions_manager.set_prune_after(horizon);
+    }
+    if let Some(factor) = args.scale_amounts {
+        transactions_manager.set_amount_scale(factor);
+    }
+    if let Some(scale) = args.normalize_scale {
+        transactions_manager.set_normalize_scale(scale);
+    }
+    if let Some(path) = &args.lock_stream {
+        let mut lock_stream = lock_stream::LockStream::open(path)?;
+        transactions_manager.set_on_lock(move |client, tx| {
+            if let Err(err) = lock_stream.record(client, tx) {
+                eprintln!("Lock stream error: {:?}", err);
+            }
+        });
+    }
+    //restored once configured, the limits apply to the restored accounts
+    if let Some(path) = &args.snapshot {
+        transactions_manager
+            .load_snapshot(path)
+            .map_err(|err| anyhow::anyhow!("{}: {}", path, err))?;
+    }
+    //loading the snapshot resets the checkpoint resumed from
+    if let Some(seq) = args.replay_from {
+        transactions_manager.resume_from(seq);
+    }
+    //the offset counts the records across all of the input files
+    let offset_path = format!("{}.offset", args.csv_paths[0]);
+    let processed = if args.resume_offset {
+        offset::load(&offset_path)?
+    } else {
+        0
+    };
+    //the skipped records are applied on top of the state they left behind
+    if processed > 0 {
+        let state = offset::state_path(&offset_path, processed);
+        transactions_manager
+            .load_snapshot(&state)
+            .map_err(|err| anyhow::anyhow!("{}: {}", state.display(), err))?;
+    }
+    let mut offset_tracker = (args.persist_offset || args.resume_offset)
+        .then(|| offset::OffsetTracker::new(&offset_path, processed, args.offset_interval));
+    //records are applied as they're read, only the per-client state is kept in memory
+    let mut skipped = 0u64;
+    //records left to be processed, unlimited when not set
+    let mut remaining = args.limit;
+    let mut error_report = args
+        .error_report
+        .as_ref()
+        .map(|_| error_report::ErrorReport::default());
+    let (mut valid_total, mut invalid_total, mut failed_total) = (0u64, 0u64, 0u64);
+    //where the deferred records came from, by the number they were parsed as
+    let mut deferred_lines = HashMap::new();
+    //the error report is written whatever stops the processing, e.g. a `--strict` failure
+    let read = (|| -> Result<()> {
+        for csv_path in &args.csv_paths {
+            let input = input::open(csv_path)?;
+            //only a regular file has a length to tell the share of
+            let length = std::fs::metadata(csv_path)
+                .ok()
+                .filter(|metadata| {
+                    metadata.is_file() && csv_path != "-" && !input::is_url(csv_path)
+                })
+                .map(|metadata| metadata.len());
+            let mut progress = args
+                .progress
+                .then(|| progress::Progress::new(csv_path, length, PROGRESS_INTERVAL));
+            let input: Box<dyn std::io::Read> = match &progress {
+                Some(progress) => Box::new(progress.reader(input)),
+                None => input,
+            };
+            let entries = input::records(
+                run_metadata::DigestReader::new(input, &mut input_digest),
+                args.input_format,
+                &reader_builder,
+                columns.as_ref(),
+                args.parallel_parse,
+            )
+            .map_err(|err| anyhow::anyhow!("{}: {}", csv_path, err))?;
+            let mut invalid_records = 0u64;
+            let mut failed_transactions = 0u64;
+            let mut discarded_lines = Vec::new();
+            for (line, entry) in entries {
+                if let Some(progress) = progress.as_mut() {
+                    progress.record();
+                }
+                //the offset counts the invalid records as well, they were already reported
+                if skipped < processed {
+                    skipped += 1;
+                    continue;
+                }
+                let entry = match entry {
+                    Ok(entry) => entry,
+                    Err(err) => {
+                        if let Some(report) = error_report.as_mut() {
+                            report.add(csv_path, line, None, &err);
+                        }
+                        if args.strict {
+                            anyhow::bail!(
+                                "Invalid record at line {} of {}: {}",
+                                line,
+                                csv_path,
+                                err
+                            );
+                        }
+                        eprintln!("Invalid record at line {}: {}", line, err);
+                        invalid_records += 1;
+                        if discarded_lines.len() < REPORTED_DISCARDED_LINES {
+                            discarded_lines.push(line.to_string());
+                        }
+                        if let Some(tracker) = offset_tracker.as_mut() {
+                            tracker.advance(|state| transactions_manager.save_snapshot(state))?;
+                        }
+                        continue;
+                    }
+                };
+                //an amount that doesn't fit the operation type is rejected before it's applied
+                match entry
+                    .validate()
+                    .and_then(|()| transactions_manager.parse_entry(&entry))
+                {
+                    //referencing a transaction that never existed points to a malformed feed
+                    Ok(ParseOutcome::IgnoredNoSuchTx) if args.strict => {
+                        let err = Errors::UnknownTransaction(entry.tx);
+                        if let Some(report) = error_report.as_mut() {
+                            report.add(csv_path, line, Some(entry.tx), &err);
+                        }
+                        anyhow::bail!("Transaction {} in {} failed: {}", entry.tx, csv_path, err)
+                    }
+                    Ok(ParseOutcome::Deferred) => {
+                        deferred_lines
+                            .insert(transactions_manager.records_seen(), (csv_path, line));
+                        valid_total += 1;
+                    }
+                    Ok(outcome) if args.check && fails_check(outcome) => {
+                        failed_transactions += 1;
+                        eprintln!("Failed check at line {}: {}", line, outcome);
+                        if discarded_lines.len() < REPORTED_DISCARDED_LINES {
+                            discarded_lines.push(line.to_string());
+                        }
+                        if let Some(report) = error_report.as_mut() {
+                            report.add(csv_path, line, Some(entry.tx), outcome);
+                        }
+                    }
+                    Ok(outcome) => {
+                        valid_total += 1;
+                        //unlike the other ignored records, it points to a malformed feed
+                        if outcome == ParseOutcome::IgnoredNoSuchTx {
+                            let err = Errors::UnknownTransaction(entry.tx);
+                            eprintln!("Ignored record at line {}: {} ({})", line, err, err.code());
+                        }
+                        if let (true, Some(report)) = (outcome.is_ignored(), error_report.as_mut())
+                        {
+                            report.add(csv_path, line, Some(entry.tx), outcome);
+                        }
+                    }
+                    Err(err) => {
+                        if let Some(report) = error_report.as_mut() {
+                            report.add(csv_path, line, Some(entry.tx), &err);
+                        }
+                        if args.strict {
+                            anyhow::bail!(
+                                "Transaction {} in {} failed: {}",
+                                entry.tx,
+                                csv_path,
+                                err
+                            );
+                        }
+                        failed_transactions += 1;
+                        eprintln!(
+                            "Input parsing error at line {}: {} ({})",
+                            line,
+                            err,
+                            err.code()
+                        );
+                        if discarded_lines.len() < REPORTED_DISCARDED_LINES {
+                            discarded_lines.push(line.to_string());
+                        }
+                    }
+                }
+                if let Some(tracker) = offset_tracker.as_mut() {
+                    tracker.advance(|state| transactions_manager.save_snapshot(state))?;
+                }
+                if let Some(limit) = remaining.as_mut() {
+                    *limit -= 1;
+                    if *limit == 0 {
+                        break;
+                    }
+                }
+            }
+            if let Some(progress) = progress.as_mut() {
+                progress.report();
+            }
+            if invalid_records + failed_transactions > 0 {
+                eprintln!(
+                    "Discarded {} record(s) from {}: {} invalid record(s), {} failed transaction(s), first at line(s) {}",
+                    invalid_records + failed_transactions,
+                    csv_path,
+                    invalid_records,
+                    failed_transactions,
+                    discarded_lines.join(", ")
+                );
+            }
+            invalid_total += invalid_records;
+            failed_total += failed_transactions;
+            if remaining == Some(0) {
+                break;
+            }
+        }
+        //the records still waiting for their transaction are dropped, like the ones beyond the window
+        for dropped in transactions_manager.finish() {
+            let tx = dropped.record.tx;
+            let (csv_path, line) = deferred_lines
+                .remove(&dropped.seq)
+                .expect("every deferred record was parsed by this run");
+            match dropped.result {
+                Ok(ParseOutcome::IgnoredNoSuchTx) if args.strict => {
+                    let err = Errors::UnknownTransaction(tx);
+                    if let Some(report) = error_report.as_mut() {
+                        report.add(csv_path, line, Some(tx), &err);
+                    }
+                    anyhow::bail!("Transaction {} in {} failed: {}", tx, csv_path, err)
+                }
+                Ok(outcome) => {
+                    if args.check && fails_check(outcome) {
+                        //it was counted as valid once deferred
+                        valid_total -= 1;
+                        failed_total += 1;
+                    }
+                    let err = Errors::UnknownTransaction(tx);
+                    eprintln!(
+                        "Ignored record at line {} of {}: {} ({})",
+                        line,
+                        csv_path,
+                        err,
+                        err.code()
+                    );
+                    if let Some(report) = error_report.as_mut() {
+                        report.add(csv_path, line, Some(tx), outcome);
+                    }
+                }
+                Err(err) => {
+                    if let Some(report) = error_report.as_mut() {
+                        report.add(csv_path, line, Some(tx), &err);
+                    }
+                    if args.strict {
+                        anyhow::bail!("Transaction {} in {} failed: {}", tx, csv_path, err);
+                    }
+                    //it was counted as valid once deferred
+                    valid_total -= 1;
+                    failed_total += 1;
+                    eprintln!(
+                        "Input parsing error at line {} of {}: {} ({})",
+                        line,
+                        csv_path,
+                        err,
+                        err.code()
+                    );
+                }
+            }
+        }
+        Ok(())
+    })();
+    if let (Some(path), Some(report)) = (&args.error_report, &error_report) {
+        report.write(path)?;
+    }
+    read?;
+    //such disputes are applied, though they move no funds, which might go unnoticed otherwise
+    let unheld_withdrawal_disputes: u64 = transactions_manager
+        .metrics()
+        .iter()
+        .map(|metrics| metrics.unheld_withdrawal_disputes)
+        .sum();
+    if unheld_withdrawal_disputes > 0 {
+        eprintln!(
+            "Warning: {} dispute(s) of withdrawal(s) held no funds, as the withdrawal dispute mode is `none`",
+            unheld_withdrawal_disputes
+        );
+    }
+    if args.check {
+        eprintln!(
+            "Checked {} record(s): {} valid, {} invalid record(s), {} failed transaction(s)",
+            valid_total + invalid_total + failed_total,
+            valid_total,
+            invalid_total,
+            failed_total
+        );
+        if invalid_total + failed_total > 0 {
+            anyhow::bail!(
+                "{} record(s) failed the check",
+                invalid_total + failed_total
+            );
+        }
+        return Ok(());
+    }
+    if let Some(tracker) = offset_tracker.as_mut() {
+        tracker.persist(|state| transactions_manager.save_snapshot(state))?;
+    }
+    if let Some(path) = &args.closeout {
+        let records = transactions_manager.closeout(args.apply_closeout)?;
+        closeout::write(path, &records)?;
+    }
+    if let Some(path) = &args.save_snapshot {
+        transactions_manager.save_snapshot(path)?;
+    }
+    if let Some(path) = &args.metrics {
+        let mut writer = csv::Writer::from_path(path)?;
+        for metrics in transactions_manager.metrics() {
+            writer.serialize(metrics)?;
+        }
+        writer.flush()?;
+    }
+    if let Some(expected) = &args.verify {
+        let differences = verify::verify(
+            transactions_manager.accounts_by_client().into_iter(),
+            std::fs::File::open(expected)?,
+        )?;
+        for difference in &differences {
+            eprintln!("{}", difference);
+        }
+        if !differences.is_empty() {
+            anyhow::bail!("{} account(s) differ from {}", differences.len(), expected);
+        }
+        return Ok(());
+    }
+    let sink: Box<dyn Write> = match &args.output {
+        Some(path) => Box::new(std::io::BufWriter::new(std::fs::File::create(path)?)),
+        None => Box::new(std::io::stdout()),
+    };
+    let mut encoder = compression::Encoder::new(sink, args.compress)?;
+    let accounts = match args.order {
+        AccountOrder::Client => transactions_manager.accounts_by_client(),
+        AccountOrder::FirstSeen => transactions_manager.accounts().collect(),
+    };
+    let accounts = accounts
+        .into_iter()
+        .filter(|acc| !args.min_balance.is_some_and(|min| acc.is_dust(min)))
+        .filter(|acc| {
+            !args.only_flagged || !acc.held().is_zero() || acc.is_locked() || acc.is_frozen()
+        });
+    let output_options = OutputOptions {
+        //a single-asset input keeps the plain output
+        currency_column: transactions_manager
+            .accounts()
+            .any(|acc| acc.currency().is_some()),
+        held_exceeds_available: args.held_exceeds_available,
+        fraud_columns: args.with_fraud_columns,
+        peak_held: args.with_peak_held,
+        client_width: args.client_width,
+        precision: Some(args.precision),
+        rounding: args.rounding,
+    };
+    //clients whose account failed to serialize, and is hence missing from the output
+    let mut skipped_clients = Vec::new();
+    let mut skip = |client: u16, err: &dyn std::fmt::Debug| {
+        eprintln!("Deserialisation error for client {}: {:?}", client, err);
+        skipped_clients.push(client.to_string());
+        if args.strict {
+            anyhow::bail!("Account of client {} failed to serialize", client);
+        }
+        Ok(())
+    };
+    let encoder = match args.format {
+        //only the filters apply to it, the amounts are always shown with 4 decimal places
+        _ if args.pretty => {
+            for acc in accounts {
+                writeln!(encoder, "{}", acc)?;
+            }
+            encoder
         }
+        OutputFormat::Csv => {
+            let mut output_writer = csv::WriterBuilder::new()
+                .has_headers(false)
+                .from_writer(encoder);
+            output_writer.write_record(output_options.columns())?;
+            for acc in accounts {
+                if let Err(err) = output_writer.serialize(acc.output(output_options)) {
+                    skip(acc.get_client_id(), &err)?;
+                }
+            }
+            //flushes the rows written so far, whatever got skipped
+            output_writer.into_inner().map_err(|e| e.into_error())?
+        }
+        OutputFormat::Json => {
+            //accounts are serialized one by one, so a failing account is skipped like in the CSV output
+            let mut separator = "";
+            encoder.write_all(b"[")?;
+            for acc in accounts {
+                match serde_json::to_vec(&acc.json(output_options)) {
+                    Ok(json) => {
+                        encoder.write_all(separator.as_bytes())?;
+                        encoder.write_all(&json)?;
+                        separator = ",";
+                    }
+                    Err(err) => skip(acc.get_client_id(), &err)?,
+                }
+            }
+            encoder.write_all(b"]\n")?;
+            encoder
+        }
+    };
+    //write failures (e.g. a full disk) surface as an error, instead of a silently truncated output
+    encoder.finish()?.flush()?;
+    if !skipped_clients.is_empty() {
+        eprintln!(
+            "Skipped {} account(s) failing to serialize, of client(s) {}",
+            skipped_clients.len(),
+            skipped_clients.join(", ")
+        );
+    }
+
+    if args.summary {
+        eprintln!("Run summary: {}", transactions_manager.summary());
+        let failures = transactions_manager.failures();
+        eprintln!(
+            "Failed operations: {} overflow(s), {} insufficient funds, {} other",
+            failures.overflows, failures.insufficient_funds, failures.other
+        );
+        eprintln!(
+            "Withdrawal fees collected: {:.04}",
+            transactions_manager.fees_collected().round_dp(4)
+        );
+        eprintln!("Checkpoint: {}", transactions_manager.checkpoint());
+    }
+
+    if let (Some(path), Some(digest)) = (args.run_metadata, input_digest.hex()) {
+        run_metadata::RunMetadata::new(digest).write(path)?;
+    }
+
+    if args.fail_on_discard && invalid_total + failed_total > 0 {
+        anyhow::bail!(
+            "Discarded {} record(s) in total: {} invalid record(s), {} failed transaction(s)",
+            invalid_total + failed_total,
+            invalid_total,
+            failed_total
+        );
     }
 
     Ok(())
@@ -0,0 +1,144 @@
+use std::fs;
+use std::io::{Error, ErrorKind, Result};
+use std::path::{Path, PathBuf};
+
+// reads the number of already processed records, a missing file means nothing was processed yet
+pub fn load<P: AsRef<Path>>(path: P) -> Result<u64> {
+    match fs::read_to_string(path) {
+        Ok(content) => content
+            .trim()
+            .parse()
+            .map_err(|err| Error::new(ErrorKind::InvalidData, err)),
+        Err(err) if err.kind() == ErrorKind::NotFound => Ok(0),
+        Err(err) => Err(err),
+    }
+}
+
+// the file holding the state the given number of processed records left behind
+pub fn state_path<P: AsRef<Path>>(path: P, processed: u64) -> PathBuf {
+    let mut state = path.as_ref().to_path_buf().into_os_string();
+    state.push(format!(".{}.snapshot", processed));
+    state.into()
+}
+
+// keeps the number of processed records persisted every `interval` records, along with the state
+// they left behind, hence after a crash at most `interval - 1` records get processed again on
+// resume, on top of the state from before them
+pub struct OffsetTracker {
+    path: PathBuf,
+    processed: u64,
+    persisted: u64,
+    interval: u64,
+}
+
+impl OffsetTracker {
+    pub fn new<P: AsRef<Path>>(path: P, processed: u64, interval: u64) -> Self {
+        Self {
+            path: path.as_ref().to_path_buf(),
+            processed,
+            persisted: processed,
+            interval: interval.max(1),
+        }
+    }
+
+    pub fn advance<F>(&mut self, save_state: F) -> anyhow::Result<()>
+    where
+        F: FnOnce(&Path) -> anyhow::Result<()>,
+    {
+        self.processed += 1;
+        if self.processed.is_multiple_of(self.interval) {
+            self.persist(save_state)?;
+        }
+        Ok(())
+    }
+
+    // the state is saved into a file of its own first, then the offset is written into a temporary
+    // file and renamed, so the offset file always holds a complete value, and whatever it holds,
+    // the state of that offset is there; the state of the previous offset is removed only then
+    pub fn persist<F>(&mut self, save_state: F) -> anyhow::Result<()>
+    where
+        F: FnOnce(&Path) -> anyhow::Result<()>,
+    {
+        save_state(&state_path(&self.path, self.processed))?;
+        let mut temporary = self.path.clone().into_os_string();
+        temporary.push(".tmp");
+        fs::write(&temporary, self.processed.to_string())?;
+        fs::rename(&temporary, &self.path)?;
+        if self.persisted != self.processed {
+            match fs::remove_file(state_path(&self.path, self.persisted)) {
+                Err(err) if err.kind() != ErrorKind::NotFound => return Err(err.into()),
+                _ => self.persisted = self.processed,
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn offset_path(name: &str) -> PathBuf {
+        std::env::temp_dir().join(format!("{}-{}.offset", name, std::process::id()))
+    }
+
+    #[test]
+    fn test_missing_offset_file_means_nothing_was_processed() {
+        assert_eq!(load(offset_path("missing")).unwrap(), 0);
+    }
+
+    #[test]
+    fn test_offset_is_persisted_every_interval() {
+        let path = offset_path("interval");
+        let mut tracker = OffsetTracker::new(&path, 0, 3);
+        let save_state = |state: &Path| Ok(fs::write(state, "state")?);
+
+        tracker.advance(save_state).unwrap();
+        tracker.advance(save_state).unwrap();
+        assert_eq!(load(&path).unwrap(), 0);
+        tracker.advance(save_state).unwrap();
+        assert_eq!(load(&path).unwrap(), 3);
+
+        tracker.persist(save_state).unwrap();
+        assert_eq!(load(&path).unwrap(), 3);
+        fs::remove_file(&path).unwrap();
+        fs::remove_file(state_path(&path, 3)).unwrap();
+    }
+
+    #[test]
+    fn test_only_the_state_of_the_persisted_offset_is_kept() {
+        let path = offset_path("state");
+        let mut tracker = OffsetTracker::new(&path, 0, 2);
+        let save_state = |state: &Path| Ok(fs::write(state, "state")?);
+
+        tracker.advance(save_state).unwrap();
+        tracker.advance(save_state).unwrap();
+        assert!(state_path(&path, 2).exists());
+        tracker.advance(save_state).unwrap();
+        tracker.advance(save_state).unwrap();
+
+        assert_eq!(load(&path).unwrap(), 4);
+        assert!(!state_path(&path, 2).exists());
+        assert!(state_path(&path, 4).exists());
+        fs::remove_file(&path).unwrap();
+        fs::remove_file(state_path(&path, 4)).unwrap();
+    }
+
+    #[test]
+    fn test_offset_is_left_as_it_was_when_the_state_cannot_be_saved() {
+        let path = offset_path("failed-state");
+        let mut tracker = OffsetTracker::new(&path, 0, 1);
+
+        tracker
+            .advance(|state| Ok(fs::write(state, "state")?))
+            .unwrap();
+        assert!(tracker
+            .advance(|_| Err(anyhow::anyhow!("disk full")))
+            .is_err());
+
+        assert_eq!(load(&path).unwrap(), 1);
+        assert!(state_path(&path, 1).exists());
+        fs::remove_file(&path).unwrap();
+        fs::remove_file(state_path(&path, 1)).unwrap();
+    }
+}
@@ -0,0 +1,106 @@
+use std::cell::Cell;
+use std::io::{Read, Result};
+use std::rc::Rc;
+use std::time::{Duration, Instant};
+
+// number of records between two looks at the clock, so the hot loop only counts them
+const CHECK_EVERY: u64 = 1024;
+
+// counts the bytes pulled from the input, while the reader itself is owned by the records iterator
+pub struct CountingReader<R: Read> {
+    inner: R,
+    read: Rc<Cell<u64>>,
+}
+
+impl<R: Read> Read for CountingReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> Result<usize> {
+        let read = self.inner.read(buf)?;
+        self.read.set(self.read.get() + read as u64);
+        Ok(read)
+    }
+}
+
+// prints the progress of an input to stderr at most once per `interval`, along with the share of
+// the bytes consumed when the length of the input is known (i.e. it's a regular file); the bytes
+// are counted as the parser reads them ahead, so they lead the records by a buffer at most
+pub struct Progress {
+    input: String,
+    length: Option<u64>,
+    read: Rc<Cell<u64>>,
+    records: u64,
+    interval: Duration,
+    last_report: Instant,
+}
+
+impl Progress {
+    pub fn new(input: &str, length: Option<u64>, interval: Duration) -> Self {
+        Self {
+            input: input.to_string(),
+            length,
+            read: Rc::new(Cell::new(0)),
+            records: 0,
+            interval,
+            last_report: Instant::now(),
+        }
+    }
+
+    pub fn reader<R: Read>(&self, inner: R) -> CountingReader<R> {
+        CountingReader {
+            inner,
+            read: Rc::clone(&self.read),
+        }
+    }
+
+    pub fn record(&mut self) {
+        self.records += 1;
+        if self.records.is_multiple_of(CHECK_EVERY) && self.last_report.elapsed() >= self.interval {
+            self.report();
+        }
+    }
+
+    pub fn report(&mut self) {
+        eprintln!("{}", self.line());
+        self.last_report = Instant::now();
+    }
+
+    fn line(&self) -> String {
+        let read = self.read.get();
+        let share = match self.length {
+            Some(length) if length > 0 => {
+                format!(" ({:.1}%)", read.min(length) as f64 * 100.0 / length as f64)
+            }
+            _ => String::new(),
+        };
+        format!(
+            "Progress of {}: {} record(s), {} byte(s) read{}",
+            self.input, self.records, read, share
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_progress_counts_the_records_and_the_bytes_read() {
+        let mut progress = Progress::new("input.csv", Some(8), Duration::ZERO);
+        let mut content = String::new();
+        progress
+            .reader("abcd".as_bytes())
+            .read_to_string(&mut content)
+            .unwrap();
+        progress.record();
+        progress.record();
+
+        assert_eq!(content, "abcd");
+        assert_eq!(
+            progress.line(),
+            "Progress of input.csv: 2 record(s), 4 byte(s) read (50.0%)"
+        );
+
+        let mut stdin = Progress::new("-", None, Duration::ZERO);
+        stdin.record();
+        assert_eq!(stdin.line(), "Progress of -: 1 record(s), 0 byte(s) read");
+    }
+}
@@ -1,8 +1,8 @@
-use crate::amount::Amount;
-use serde::Deserialize;
+use crate::{amount::Amount, error::Errors};
+use chrono::{DateTime, Utc};
+use serde::{de::Error, Deserialize, Deserializer, Serialize, Serializer};
 
-#[derive(Copy, Clone, Debug, Deserialize, PartialEq, Eq)]
-#[serde(rename_all = "camelCase")]
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
 pub enum OperationType {
     Chargeback,
     Dispute,
@@ -11,10 +11,197 @@ pub enum OperationType {
     Withdrawal,
 }
 
-#[derive(Debug, Deserialize)]
+const OPERATION_TYPES: [(&str, OperationType); 5] = [
+    ("chargeback", OperationType::Chargeback),
+    ("dispute", OperationType::Dispute),
+    ("deposit", OperationType::Deposit),
+    ("resolve", OperationType::Resolve),
+    ("withdrawal", OperationType::Withdrawal),
+];
+
+// the same tokens as the serde ones
+impl std::fmt::Display for OperationType {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let (token, _) = OPERATION_TYPES
+            .iter()
+            .find(|(_, operation)| operation == self)
+            .expect("every operation type has a token");
+        f.write_str(token)
+    }
+}
+
+// for callers building the records by hand, the tokens are matched case-insensitively
+impl std::str::FromStr for OperationType {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        OPERATION_TYPES
+            .iter()
+            .find(|(token, _)| token.eq_ignore_ascii_case(s))
+            .map(|(_, operation)| *operation)
+            .ok_or_else(|| {
+                format!("unknown operation type `{s}`, it has to be one of `deposit`, `withdrawal`, `dispute`, `resolve`, or `chargeback`")
+            })
+    }
+}
+
+// partners send the types title-cased or uppercased as well, so they're matched the same way as
+// by `FromStr`, ignoring the case (and any surrounding whitespace)
+impl<'de> Deserialize<'de> for OperationType {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        let token = String::deserialize(deserializer)?;
+        token.trim().parse().map_err(D::Error::custom)
+    }
+}
+
+impl Serialize for OperationType {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        serializer.collect_str(self)
+    }
+}
+
+#[derive(Clone, Debug, Deserialize)]
 pub struct Record {
     pub r#type: OperationType,
     pub client: u16,
     pub tx: u32,
     pub amount: Option<Amount>,
+    //ISO-8601, `None` when the input has no `timestamp` column (or the field is empty)
+    #[serde(default)]
+    pub timestamp: Option<DateTime<Utc>>,
+    //the asset of the amount, `None` when the input has no `currency` column (or the field is empty)
+    #[serde(default)]
+    pub currency: Option<String>,
+}
+
+impl Record {
+    pub fn new(r#type: OperationType, client: u16, tx: u32, amount: Option<Amount>) -> Self {
+        Self {
+            r#type,
+            client,
+            tx,
+            amount,
+            timestamp: None,
+            currency: None,
+        }
+    }
+
+    pub fn with_timestamp(mut self, timestamp: DateTime<Utc>) -> Self {
+        self.timestamp = Some(timestamp);
+        self
+    }
+
+    pub fn with_currency(mut self, currency: &str) -> Self {
+        self.currency = Some(currency.to_string());
+        self
+    }
+
+    // a deposit/withdrawal has to carry an amount, while a dispute/chargeback mustn't; a resolve
+    // might carry one, releasing only a part of the disputed funds
+    pub fn validate(&self) -> Result<(), Errors> {
+        match (self.r#type, self.amount) {
+            (OperationType::Deposit | OperationType::Withdrawal, None) => {
+                Err(Errors::MissingAmount(self.tx))
+            }
+            (OperationType::Dispute | OperationType::Chargeback, Some(amount)) => {
+                Err(Errors::UnexpectedAmount(self.tx, *amount))
+            }
+            _ => Ok(()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rust_decimal_macros::dec;
+
+    #[test]
+    fn test_operation_type_round_trips_through_its_token() {
+        for (_, operation) in OPERATION_TYPES {
+            assert_eq!(
+                operation.to_string().parse::<OperationType>(),
+                Ok(operation)
+            );
+        }
+        assert_eq!(
+            "Deposit".parse::<OperationType>(),
+            Ok(OperationType::Deposit)
+        );
+        assert_eq!(
+            "CHARGEBACK".parse::<OperationType>(),
+            Ok(OperationType::Chargeback)
+        );
+        assert!("refund".parse::<OperationType>().is_err());
+        assert!("".parse::<OperationType>().is_err());
+    }
+
+    #[test]
+    fn test_operation_type_is_deserialized_case_insensitively() {
+        let input = "type,client,tx,amount\ndeposit,1,1,1\nDeposit,1,2,1\nDEPOSIT,1,3,1\n WithDrawal ,1,4,1\nrefund,1,5,1\n";
+        let mut reader = csv::Reader::from_reader(input.as_bytes());
+        let records: Vec<_> = reader.deserialize::<Record>().collect();
+
+        let types: Vec<_> = records[..4]
+            .iter()
+            .map(|record| record.as_ref().unwrap().r#type)
+            .collect();
+        assert_eq!(
+            types,
+            vec![
+                OperationType::Deposit,
+                OperationType::Deposit,
+                OperationType::Deposit,
+                OperationType::Withdrawal
+            ]
+        );
+        assert!(records[4]
+            .as_ref()
+            .unwrap_err()
+            .to_string()
+            .contains("unknown operation type `refund`"));
+    }
+
+    #[test]
+    fn test_amount_has_to_match_the_operation_type() {
+        let amount = Some(dec!(1.5).into());
+        assert!(Record::new(OperationType::Deposit, 1, 1, amount)
+            .validate()
+            .is_ok());
+        assert!(Record::new(OperationType::Withdrawal, 1, 2, amount)
+            .validate()
+            .is_ok());
+        assert!(Record::new(OperationType::Dispute, 1, 1, None)
+            .validate()
+            .is_ok());
+        assert!(Record::new(OperationType::Resolve, 1, 1, amount)
+            .validate()
+            .is_ok());
+        assert!(Record::new(OperationType::Chargeback, 1, 1, None)
+            .validate()
+            .is_ok());
+
+        assert!(matches!(
+            Record::new(OperationType::Deposit, 1, 3, None).validate(),
+            Err(Errors::MissingAmount(3))
+        ));
+        assert!(matches!(
+            Record::new(OperationType::Withdrawal, 1, 4, None).validate(),
+            Err(Errors::MissingAmount(4))
+        ));
+        assert!(matches!(
+            Record::new(OperationType::Dispute, 1, 1, amount).validate(),
+            Err(Errors::UnexpectedAmount(1, _))
+        ));
+        assert!(matches!(
+            Record::new(OperationType::Chargeback, 1, 1, amount).validate(),
+            Err(Errors::UnexpectedAmount(1, _))
+        ));
+    }
 }
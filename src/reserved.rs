@@ -0,0 +1,57 @@
+use std::ops::RangeInclusive;
+use std::str::FromStr;
+
+// client ids reserved for other purposes (e.g. internal or test accounts), whose records are rejected
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct ReservedClients(Vec<RangeInclusive<u16>>);
+
+impl ReservedClients {
+    pub fn contains(&self, client: u16) -> bool {
+        self.0.iter().any(|range| range.contains(&client))
+    }
+}
+
+// comma separated ids or inclusive ranges of them, e.g. `0,60000-65535`
+impl FromStr for ReservedClients {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let parse = |id: &str| {
+            id.trim()
+                .parse::<u16>()
+                .map_err(|err| format!("invalid client id `{}`: {}", id.trim(), err))
+        };
+        s.split(',')
+            .map(|range| match range.split_once('-') {
+                Some((first, last)) => {
+                    let (first, last) = (parse(first)?, parse(last)?);
+                    if first > last {
+                        return Err(format!("the range `{}` is empty", range.trim()));
+                    }
+                    Ok(first..=last)
+                }
+                None => parse(range).map(|id| id..=id),
+            })
+            .collect::<Result<_, _>>()
+            .map(ReservedClients)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parsing_ids_and_ranges() {
+        let reserved: ReservedClients = "0, 60000-65535".parse().unwrap();
+
+        assert!(reserved.contains(0));
+        assert!(!reserved.contains(1));
+        assert!(!reserved.contains(59999));
+        assert!(reserved.contains(60000));
+        assert!(reserved.contains(65535));
+        assert!("5-1".parse::<ReservedClients>().is_err());
+        assert!("1,abc".parse::<ReservedClients>().is_err());
+        assert!("70000".parse::<ReservedClients>().is_err());
+    }
+}
@@ -0,0 +1,139 @@
+use serde::Serialize;
+use sha2::{Digest, Sha256};
+use std::io::{Read, Result};
+use std::path::Path;
+use uuid::Uuid;
+
+// (optional) digest of all the inputs, in the order they were read
+pub struct InputDigest(Option<Sha256>);
+
+impl InputDigest {
+    pub fn new(enabled: bool) -> Self {
+        Self(enabled.then(Sha256::new))
+    }
+
+    pub fn hex(self) -> Option<String> {
+        self.0.map(|hasher| {
+            hasher
+                .finalize()
+                .iter()
+                .map(|byte| format!("{:02x}", byte))
+                .collect()
+        })
+    }
+}
+
+// passes the input through while feeding the digest, so the input is read only once
+pub struct DigestReader<'a, R: Read> {
+    inner: R,
+    digest: &'a mut InputDigest,
+}
+
+impl<'a, R: Read> DigestReader<'a, R> {
+    pub fn new(inner: R, digest: &'a mut InputDigest) -> Self {
+        Self { inner, digest }
+    }
+}
+
+impl<R: Read> Read for DigestReader<'_, R> {
+    fn read(&mut self, buf: &mut [u8]) -> Result<usize> {
+        let read = self.inner.read(buf)?;
+        if let Some(hasher) = self.digest.0.as_mut() {
+            hasher.update(&buf[..read]);
+        }
+        Ok(read)
+    }
+}
+
+#[derive(Debug, Serialize)]
+pub struct RunMetadata {
+    version: &'static str,
+    run_id: Uuid,
+    input_digest: String,
+}
+
+impl RunMetadata {
+    pub fn new(input_digest: String) -> Self {
+        Self {
+            version: env!("CARGO_PKG_VERSION"),
+            run_id: Uuid::new_v4(),
+            input_digest,
+        }
+    }
+
+    pub fn write<P: AsRef<Path>>(&self, path: P) -> csv::Result<()> {
+        let mut writer = csv::Writer::from_path(path)?;
+        writer.serialize(self)?;
+        writer.flush()?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashMap;
+
+    #[test]
+    fn test_digest_reader_computes_sha256_of_consumed_input() {
+        let mut digest = InputDigest::new(true);
+        let mut content = String::new();
+        DigestReader::new("abc".as_bytes(), &mut digest)
+            .read_to_string(&mut content)
+            .unwrap();
+
+        assert_eq!(content, "abc");
+        assert_eq!(
+            digest.hex().unwrap(),
+            "ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad"
+        );
+    }
+
+    #[test]
+    fn test_digest_covers_all_inputs_in_order() {
+        let mut digest = InputDigest::new(true);
+        let mut content = String::new();
+        DigestReader::new("a".as_bytes(), &mut digest)
+            .read_to_string(&mut content)
+            .unwrap();
+        DigestReader::new("bc".as_bytes(), &mut digest)
+            .read_to_string(&mut content)
+            .unwrap();
+
+        assert_eq!(content, "abc");
+        assert_eq!(
+            digest.hex().unwrap(),
+            "ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad"
+        );
+    }
+
+    #[test]
+    fn test_disabled_digest_reader_only_passes_input_through() {
+        let mut digest = InputDigest::new(false);
+        let mut content = String::new();
+        DigestReader::new("abc".as_bytes(), &mut digest)
+            .read_to_string(&mut content)
+            .unwrap();
+
+        assert_eq!(content, "abc");
+        assert!(digest.hex().is_none());
+    }
+
+    #[test]
+    fn test_metadata_file_contains_version_and_valid_run_id() {
+        let path = std::env::temp_dir().join(format!("run-metadata-{}.csv", Uuid::new_v4()));
+        let metadata = RunMetadata::new("digest".to_string());
+        metadata.write(&path).unwrap();
+
+        let mut reader = csv::Reader::from_path(&path).unwrap();
+        let written: HashMap<String, String> = reader.deserialize().next().unwrap().unwrap();
+        std::fs::remove_file(&path).unwrap();
+
+        assert_eq!(written["version"], env!("CARGO_PKG_VERSION"));
+        assert_eq!(
+            Uuid::parse_str(&written["run_id"]).unwrap(),
+            metadata.run_id
+        );
+        assert_eq!(written["input_digest"], "digest");
+    }
+}
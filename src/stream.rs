@@ -0,0 +1,46 @@
+use crate::{Errors, ParseOutcome, Record, TransactionManager};
+use tokio_stream::{Stream, StreamExt};
+
+impl TransactionManager {
+    // applies every record of the stream as soon as it arrives, through the same `parse_entry` as
+    // the synchronous API, yielding the outcome of each of them in order
+    pub fn process_stream<'a, S>(
+        &'a mut self,
+        stream: S,
+    ) -> impl Stream<Item = Result<ParseOutcome, Errors>> + 'a
+    where
+        S: Stream<Item = Record> + 'a,
+    {
+        stream.map(move |record| self.parse_entry(&record))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::OperationType;
+    use rust_decimal_macros::dec;
+
+    #[tokio::test]
+    async fn test_stream_records_are_applied_as_they_arrive() {
+        let mut manager = TransactionManager::new();
+        let records = tokio_stream::iter(vec![
+            Record::new(OperationType::Deposit, 1, 1, Some(dec!(10).into())),
+            Record::new(OperationType::Withdrawal, 1, 2, Some(dec!(20).into())),
+            Record::new(OperationType::Dispute, 1, 1, None),
+        ]);
+
+        let outcomes: Vec<_> = manager.process_stream(records).collect().await;
+
+        assert!(matches!(
+            outcomes[0],
+            Ok(ParseOutcome::Applied { client: 1, .. })
+        ));
+        assert!(matches!(outcomes[1], Err(Errors::InsuficientFunds(1))));
+        assert!(matches!(
+            outcomes[2],
+            Ok(ParseOutcome::Applied { client: 1, .. })
+        ));
+        assert_eq!(manager.account(1).unwrap().held(), dec!(10));
+    }
+}
@@ -1,294 +1,4239 @@
 use crate::{
-    account::Account, amount::Amount, error::Errors, record::OperationType, record::Record,
+    account::{Account, AccountSnapshot, OverflowPolicy},
+    amount::Amount,
+    error::Errors,
+    fee::WithdrawalFee,
+    record::OperationType,
+    record::Record,
+    reserved::ReservedClients,
 };
-use std::collections::HashMap;
+use chrono::{DateTime, Utc};
+use indexmap::{Equivalent, IndexMap};
+use rust_decimal::Decimal;
+use serde::{Deserialize, Deserializer, Serialize};
+use std::collections::{HashMap, VecDeque};
+use std::hash::{Hash, Hasher};
+use std::path::Path;
+use std::sync::{mpsc, Arc, Mutex};
+use std::thread;
 
 use anyhow::Result;
 
-#[derive(Debug)]
+#[derive(Debug, Serialize, Deserialize)]
 struct TransactionRecord {
+    client: u16,
+    operation: OperationType,
+    //disputes have to refer to it in the same currency
+    #[serde(default)]
+    currency: Option<String>,
+    //a scaled amount might have more decimal places than the input allows
+    #[serde(deserialize_with = "scaled_amount_of")]
     amount: Option<Amount>,
     under_dispute: bool,
     already_disputed: bool,
+    charged_back: bool,
+    //the amount made it into the account (a rejected deposit/withdrawal still takes its tx id)
+    applied: bool,
+    //funds moved by the chargeback, i.e. what was still held for a deposit
+    reversed: Decimal,
+    //part of the amount still under dispute (held in the account for a deposit)
+    held: Decimal,
+    //number of the record that created the transaction, orders the transactions of a client
+    seq: u64,
+}
+
+fn scaled_amount_of<'de, D: Deserializer<'de>>(
+    deserializer: D,
+) -> Result<Option<Amount>, D::Error> {
+    Ok(Option::<Decimal>::deserialize(deserializer)?.map(Amount))
+}
+
+// the state of a manager persisted by `save_snapshot`, its configuration (and listeners) aren't
+// part of it; generic over the transactions, which are only borrowed when saving
+#[derive(Serialize, Deserialize)]
+struct Snapshot<T> {
+    accounts: Vec<AccountSnapshot>,
+    transactions: T,
+    records_seen: u64,
+    checkpoint: u32,
+    last_timestamp: Option<DateTime<Utc>>,
+    fees_collected: Decimal,
+    pruned_balance: Decimal,
 }
 
 impl TransactionRecord {
-    fn new(amount: Option<Amount>) -> Self {
+    fn new(client: u16, operation: OperationType, amount: Option<Amount>, seq: u64) -> Self {
         Self {
+            client,
+            operation,
+            currency: None,
             amount,
             under_dispute: false,
             already_disputed: false,
+            charged_back: false,
+            applied: false,
+            reversed: Decimal::ZERO,
+            held: Decimal::ZERO,
+            seq,
+        }
+    }
+
+    // what the transaction adds to the funds of its account, a held withdrawal dispute adds the
+    // amount still held
+    fn balance(&self, held_withdrawals: bool) -> Decimal {
+        if !self.applied {
+            return Decimal::ZERO;
+        }
+        let amount = self.amount.map_or(Decimal::ZERO, |amount| *amount);
+        match self.operation {
+            OperationType::Withdrawal if held_withdrawals && self.under_dispute => {
+                self.held.saturating_sub(amount)
+            }
+            OperationType::Withdrawal => self.reversed.saturating_sub(amount),
+            _ => amount.saturating_sub(self.reversed),
+        }
+    }
+
+    fn dispute_state(&self) -> DisputeState {
+        if self.charged_back {
+            DisputeState::ChargedBack
+        } else if self.under_dispute {
+            DisputeState::Disputed
+        } else if self.already_disputed {
+            DisputeState::Resolved
+        } else {
+            DisputeState::Undisputed
+        }
+    }
+}
+
+/// Where a transaction is in the dispute life cycle
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DisputeState {
+    /// never disputed
+    Undisputed,
+    /// under dispute right now
+    Disputed,
+    /// disputed, and resolved afterwards (might be disputed again)
+    Resolved,
+    /// reversed by a chargeback
+    ChargedBack,
+}
+
+// a deposit/withdrawal of a client, as listed by `client_transactions`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TransactionSummary {
+    pub tx: u32,
+    pub operation: OperationType,
+    pub amount: Option<Amount>,
+    pub state: DisputeState,
+}
+
+/// Decides what happens with dispute/resolve/chargeback operations that can't be applied
+/// (unknown tx, tx owned by another client, tx not disputable or already settled)
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum IgnoredOperationPolicy {
+    /// such operations are dropped without any notice
+    #[default]
+    Silent,
+    /// such operations are reported back as errors
+    Report,
+}
+
+impl IgnoredOperationPolicy {
+    fn apply(self, error: Errors, ignored: ParseOutcome) -> Result<ParseOutcome, Errors> {
+        match self {
+            IgnoredOperationPolicy::Silent => Ok(ignored),
+            IgnoredOperationPolicy::Report => Err(error),
+        }
+    }
+}
+
+/// How a dispute of a withdrawal affects the account (the chargeback credits the funds back to
+/// available either way)
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum WithdrawalDisputeMode {
+    /// the withdrawal is only marked as disputed, nothing is held
+    #[default]
+    None,
+    /// the withdrawn amount is held until the dispute is resolved (dropped from held) or charged
+    /// back (moved from held to available)
+    Hold,
+}
+
+impl std::str::FromStr for WithdrawalDisputeMode {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "none" => Ok(WithdrawalDisputeMode::None),
+            "hold" => Ok(WithdrawalDisputeMode::Hold),
+            _ => Err("the withdrawal dispute mode has to be either `none` or `hold`".to_string()),
+        }
+    }
+}
+
+/// Effect of a single record processed by `TransactionManager::parse_entry`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ParseOutcome {
+    /// the record was applied to the client's account, changing its funds by the deltas
+    /// (both might be zero, e.g. for a disputed withdrawal)
+    Applied {
+        client: u16,
+        delta_available: Decimal,
+        delta_held: Decimal,
+    },
+    /// the record was applied, and locked the client's account (i.e. a chargeback)
+    Locked {
+        client: u16,
+        delta_available: Decimal,
+        delta_held: Decimal,
+    },
+    /// the record refers to a yet unknown transaction, and waits for it (see `set_out_of_order_window`)
+    Deferred,
+    /// dropped: the referenced transaction was never a deposit/withdrawal
+    IgnoredNoSuchTx,
+    /// dropped: the referenced transaction belongs to another client
+    IgnoredClientMismatch,
+    /// dropped: a dispute of a transaction that is already under dispute
+    IgnoredAlreadyDisputed,
+    /// dropped: a dispute of a transaction that can't be disputed (no amount, rejected, or charged
+    /// back)
+    IgnoredNotDisputable,
+    /// dropped: a resolve/chargeback of a transaction that isn't under dispute
+    IgnoredNotDisputed,
+    /// dropped: a deposit/withdrawal at or below the checkpoint resumed from (see `resume_from`)
+    IgnoredBeforeCheckpoint,
+    /// dropped: a deposit/withdrawal with a tx id outside of the processed range (see `set_tx_range`)
+    IgnoredOutsideTxRange,
+}
+
+impl ParseOutcome {
+    // whether the record got dropped without an error
+    pub fn is_ignored(&self) -> bool {
+        !matches!(
+            self,
+            ParseOutcome::Applied { .. } | ParseOutcome::Locked { .. } | ParseOutcome::Deferred
+        )
+    }
+}
+
+// the reason of a dropped record, in the words of the error report
+impl std::fmt::Display for ParseOutcome {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            ParseOutcome::Applied { .. } => "applied",
+            ParseOutcome::Locked { .. } => "applied, locking the account",
+            ParseOutcome::Deferred => "deferred until its transaction shows up",
+            ParseOutcome::IgnoredNoSuchTx => "ignored, the referenced transaction doesn't exist",
+            ParseOutcome::IgnoredClientMismatch => {
+                "ignored, the referenced transaction belongs to another client"
+            }
+            ParseOutcome::IgnoredAlreadyDisputed => {
+                "ignored, the referenced transaction is already under dispute"
+            }
+            ParseOutcome::IgnoredNotDisputable => {
+                "ignored, the referenced transaction can't be disputed"
+            }
+            ParseOutcome::IgnoredNotDisputed => {
+                "ignored, the referenced transaction isn't under dispute"
+            }
+            ParseOutcome::IgnoredBeforeCheckpoint => {
+                "ignored, the tx id is at or below the checkpoint"
+            }
+            ParseOutcome::IgnoredOutsideTxRange => {
+                "ignored, the tx id is outside of the processed range"
+            }
+        })
+    }
+}
+
+// a client holds an account per currency, the currency being `None` for a single-asset input
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord)]
+struct AccountKey {
+    client: u16,
+    currency: Option<String>,
+}
+
+impl AccountKey {
+    fn of(record: &Record) -> Self {
+        Self {
+            client: record.client,
+            currency: record.currency.clone(),
+        }
+    }
+
+    fn of_account(account: &Account) -> Self {
+        Self {
+            client: account.get_client_id(),
+            currency: account.currency().map(str::to_string),
+        }
+    }
+}
+
+// hashes just like the bare client id without a currency, so the account of a single-asset
+// input might be looked up by the client id alone
+impl Hash for AccountKey {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.client.hash(state);
+        if let Some(currency) = &self.currency {
+            currency.hash(state);
+        }
+    }
+}
+
+impl Equivalent<AccountKey> for u16 {
+    fn equivalent(&self, key: &AccountKey) -> bool {
+        key.client == *self && key.currency.is_none()
+    }
+}
+
+//accounts are kept in the order their clients (and currencies) were first seen
+type Accounts = IndexMap<AccountKey, Account>;
+type Transactions = HashMap<u32, TransactionRecord>;
+
+// failed operations, split by the kind of failure: an overflow is a capacity problem,
+// while insufficient funds are just a client lacking money
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct FailureCounts {
+    pub overflows: u64,
+    pub insufficient_funds: u64,
+    pub other: u64,
+}
+
+impl FailureCounts {
+    fn count(&mut self, error: &Errors) {
+        match error {
+            Errors::FundsOverflow(_) => self.overflows += 1,
+            Errors::InsuficientFunds(_) => self.insufficient_funds += 1,
+            _ => self.other += 1,
+        }
+    }
+
+    fn add(&mut self, other: FailureCounts) {
+        self.overflows += other.overflows;
+        self.insufficient_funds += other.insufficient_funds;
+        self.other += other.other;
+    }
+}
+
+// records processed for a single client: the applied ones by their operation type, while the
+// silently ignored and the rejected ones regardless of it
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Serialize)]
+pub struct Metrics {
+    pub client: u16,
+    pub deposits: u64,
+    pub withdrawals: u64,
+    pub disputes: u64,
+    pub resolves: u64,
+    pub chargebacks: u64,
+    pub ignored: u64,
+    pub rejected: u64,
+    // the applied disputes (counted in `disputes` as well) of withdrawals that held nothing, as the
+    // withdrawal dispute mode is `none`; unlike an ignored dispute (e.g. of an unknown tx) the
+    // withdrawal is still marked as disputed
+    pub unheld_withdrawal_disputes: u64,
+}
+
+impl Metrics {
+    fn count(&mut self, operation: OperationType, result: &Result<ParseOutcome, Errors>) {
+        match result {
+            Ok(ParseOutcome::Applied { .. } | ParseOutcome::Locked { .. }) => match operation {
+                OperationType::Deposit => self.deposits += 1,
+                OperationType::Withdrawal => self.withdrawals += 1,
+                OperationType::Dispute => self.disputes += 1,
+                OperationType::Resolve => self.resolves += 1,
+                OperationType::Chargeback => self.chargebacks += 1,
+            },
+            //counted once it's applied (or dropped)
+            Ok(ParseOutcome::Deferred) => {}
+            Ok(_) => self.ignored += 1,
+            Err(_) => self.rejected += 1,
+        }
+    }
+}
+
+// cumulative counts of the records handled by `apply_all`/`process_batched`
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct ProcessedCounts {
+    pub applied: u64,
+    pub failed: u64,
+}
+
+impl ProcessedCounts {
+    pub fn total(&self) -> u64 {
+        self.applied + self.failed
+    }
+}
+
+// overview of the engine state, derived from the accounts and the known transactions
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct RunSummary {
+    pub accounts: usize,
+    pub locked_accounts: usize,
+    pub deposits: usize,
+    pub withdrawals: usize,
+    pub disputes_outstanding: usize,
+    pub total_held: Decimal,
+}
+
+impl std::fmt::Display for RunSummary {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{} account(s) ({} locked), {} deposit(s), {} withdrawal(s), {} dispute(s) outstanding, {:.04} held in total",
+            self.accounts,
+            self.locked_accounts,
+            self.deposits,
+            self.withdrawals,
+            self.disputes_outstanding,
+            self.total_held.round_dp(4)
+        )
+    }
+}
+
+type LockListener = Box<dyn FnMut(u16, u32) + Send>;
+type ApplyListener = Box<dyn FnMut(&Record, &Account) + Send>;
+
+// capacity of the channel feeding a shard, so the reading side doesn't run too far ahead
+const SHARD_QUEUE_SIZE: usize = 1024;
+
+// a deferred record dropped for good, `seq` is the number it was parsed as (see `records_seen`),
+// the result is the one of a record referencing an unknown transaction
+#[derive(Debug)]
+pub struct DroppedRecord {
+    pub seq: u64,
+    pub record: Record,
+    pub result: Result<ParseOutcome, Errors>,
+}
+
+// a record routed to a shard, along with the conflict spotted by the dispatcher, i.e. one that
+// involves a transaction the shard itself can't know about
+struct ShardJob {
+    record: Record,
+    conflict: Option<Errors>,
+}
+
+pub struct TransactionManager {
+    accounts: Accounts,
+    transactions: Transactions,
+    ignored_policy: IgnoredOperationPolicy,
+    deposit_disputes_only: bool,
+    reject_zero: bool,
+    lock_on_withdrawal_chargeback: bool,
+    withdrawal_dispute_mode: WithdrawalDisputeMode,
+    enforce_order: bool,
+    //timestamp of the latest applied record carrying one
+    last_timestamp: Option<DateTime<Utc>>,
+    reserved_clients: ReservedClients,
+    amount_scale: Option<Decimal>,
+    normalize_scale: Option<u32>,
+    failures: FailureCounts,
+    metrics: HashMap<u16, Metrics>,
+    withdrawal_fee: Option<WithdrawalFee>,
+    fees_collected: Decimal,
+    credit_limit: Decimal,
+    max_balance: Option<Decimal>,
+    overflow_policy: OverflowPolicy,
+    out_of_order_window: Option<u64>,
+    prune_after: Option<u64>,
+    //what the pruned transactions added to the funds, kept for `reconcile`
+    pruned_balance: Decimal,
+    //dispute/resolve/chargeback records waiting for their transaction, along with the
+    //number they were parsed as
+    pending: VecDeque<(u64, Record)>,
+    //deferred records dropped so far, handed back by `finish`
+    dropped: Vec<DroppedRecord>,
+    records_seen: u64,
+    //highest tx id of the processed deposits/withdrawals, and the one resumed from
+    checkpoint: u32,
+    resumed_from: Option<u32>,
+    since_tx: Option<u32>,
+    until_tx: Option<u32>,
+    on_lock: Option<LockListener>,
+    on_apply: Option<ApplyListener>,
+}
+
+impl Default for TransactionManager {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl TransactionManager {
+    pub fn new() -> Self {
+        Self::with_capacity(0, 0)
+    }
+
+    // pre-sizes the accounts and transactions maps, so they don't get rehashed over and over while
+    // growing on large inputs; the hints are just the expected numbers of clients and transactions
+    pub fn with_capacity(accounts: usize, transactions: usize) -> Self {
+        Self {
+            accounts: Accounts::with_capacity(accounts),
+            transactions: Transactions::with_capacity(transactions),
+            ignored_policy: Default::default(),
+            deposit_disputes_only: false,
+            reject_zero: false,
+            lock_on_withdrawal_chargeback: false,
+            withdrawal_dispute_mode: Default::default(),
+            enforce_order: false,
+            last_timestamp: None,
+            reserved_clients: Default::default(),
+            amount_scale: None,
+            normalize_scale: None,
+            failures: Default::default(),
+            metrics: HashMap::new(),
+            withdrawal_fee: None,
+            fees_collected: Decimal::ZERO,
+            credit_limit: Decimal::ZERO,
+            max_balance: None,
+            overflow_policy: OverflowPolicy::Error,
+            out_of_order_window: None,
+            prune_after: None,
+            pruned_balance: Decimal::ZERO,
+            pending: VecDeque::new(),
+            dropped: Vec::new(),
+            records_seen: 0,
+            checkpoint: 0,
+            resumed_from: None,
+            since_tx: None,
+            until_tx: None,
+            on_lock: None,
+            on_apply: None,
+        }
+    }
+
+    pub fn set_ignored_policy(&mut self, policy: IgnoredOperationPolicy) {
+        self.ignored_policy = policy;
+    }
+
+    // when enabled, a dispute referencing anything else than a deposit is reported with its operation type
+    pub fn set_deposit_disputes_only(&mut self, enabled: bool) {
+        self.deposit_disputes_only = enabled;
+    }
+
+    // when enabled, a deposit/withdrawal of exactly zero is rejected, without taking its tx id
+    pub fn set_reject_zero(&mut self, enabled: bool) {
+        self.reject_zero = enabled;
+    }
+
+    // when enabled, a chargeback of a withdrawal locks the account just like the one of a deposit
+    pub fn set_lock_on_withdrawal_chargeback(&mut self, enabled: bool) {
+        self.lock_on_withdrawal_chargeback = enabled;
+    }
+
+    // has to be set before any withdrawal gets disputed, the disputes already open aren't converted
+    pub fn set_withdrawal_dispute_mode(&mut self, mode: WithdrawalDisputeMode) {
+        self.withdrawal_dispute_mode = mode;
+    }
+
+    // when enabled, a record timestamped before the last applied one is rejected; records without
+    // a timestamp are never checked
+    pub fn set_enforce_order(&mut self, enabled: bool) {
+        self.enforce_order = enabled;
+    }
+
+    // records of the reserved clients are rejected, without creating their accounts
+    pub fn set_reserved_clients(&mut self, reserved: ReservedClients) {
+        self.reserved_clients = reserved;
+    }
+
+    // every incoming deposit/withdrawal amount gets multiplied by the factor before it's applied
+    pub fn set_amount_scale(&mut self, factor: Decimal) {
+        self.amount_scale = Some(factor);
+    }
+
+    // every incoming amount is stored with exactly `scale` decimal places (after the amount scale,
+    // if any), instead of the ones it came with; see `Amount::normalized`
+    pub fn set_normalize_scale(&mut self, scale: u32) {
+        self.normalize_scale = Some(scale);
+    }
+
+    pub fn set_withdrawal_fee(&mut self, fee: WithdrawalFee) {
+        self.withdrawal_fee = Some(fee);
+    }
+
+    // the credit limit of every account, both the already known and the new ones: a withdrawal might
+    // take the available funds down to `-limit`
+    pub fn set_credit_limit(&mut self, limit: Decimal) -> Result<(), Errors> {
+        for account in self.accounts.values_mut() {
+            account.set_credit_limit(limit)?;
+        }
+        self.credit_limit = limit;
+        Ok(())
+    }
+
+    // the cap of the total funds of every account, both the already known and the new ones; only
+    // the operations increasing the total are checked against it, so an account already above a
+    // lowered cap isn't touched
+    pub fn set_max_balance(&mut self, limit: Decimal) -> Result<(), Errors> {
+        for account in self.accounts.values_mut() {
+            account.set_max_balance(Some(limit))?;
+        }
+        self.max_balance = Some(limit);
+        Ok(())
+    }
+
+    // what an overflow of the funds does to every account, both the already known and the new ones
+    pub fn set_overflow_policy(&mut self, policy: OverflowPolicy) {
+        for account in self.accounts.values_mut() {
+            account.set_overflow_policy(policy);
+        }
+        self.overflow_policy = policy;
+    }
+
+    // total of the fees charged on successful withdrawals
+    pub fn fees_collected(&self) -> Decimal {
+        self.fees_collected
+    }
+
+    // dispute/resolve/chargeback records referencing an unknown transaction are deferred for up to
+    // `window` subsequent records, and applied once the transaction shows up; beyond the window
+    // they're dropped
+    pub fn set_out_of_order_window(&mut self, window: u64) {
+        self.out_of_order_window = Some(window);
+    }
+
+    // every `records` records, the transactions that can't change anymore are dropped: the charged
+    // back ones, and the ones created at least `records` records ago that aren't under dispute.
+    // Disputes referencing them are handled as the ones of an unknown transaction afterwards, and
+    // their tx ids might be taken again
+    pub fn set_prune_after(&mut self, records: u64) {
+        self.prune_after = Some(records.max(1));
+    }
+
+    // highest tx id of the deposits/withdrawals processed so far (whatever their result), or of the
+    // checkpoint resumed from, to be persisted for a later replay
+    pub fn checkpoint(&self) -> u32 {
+        self.checkpoint
+    }
+
+    // deposits/withdrawals with a tx id at or below `seq` are skipped from now on, since they were
+    // processed before the checkpoint was taken; dispute/resolve/chargeback records refer to such
+    // tx ids by design, so they're always processed
+    pub fn resume_from(&mut self, seq: u32) {
+        self.resumed_from = Some(seq);
+        self.checkpoint = self.checkpoint.max(seq);
+    }
+
+    // only the deposits/withdrawals with a tx id within `since..=until` (both bounds inclusive, and
+    // optional) are processed, to look into a window of the input; dispute/resolve/chargeback
+    // records are always processed, referring to whatever transaction is known (e.g. loaded by
+    // `load_snapshot`)
+    pub fn set_tx_range(&mut self, since: Option<u32>, until: Option<u32>) {
+        self.since_tx = since;
+        self.until_tx = until;
+    }
+
+    // the listener is called with the client and tx id, right after a chargeback locks an account
+    pub fn set_on_lock(&mut self, listener: impl FnMut(u16, u32) + Send + 'static) {
+        self.on_lock = Some(Box::new(listener));
+    }
+
+    // the listener is called with the record and the current state of its account, right after
+    // the record got applied; records that failed, or were ignored or deferred, aren't reported
+    pub fn set_on_apply(&mut self, listener: impl FnMut(&Record, &Account) + Send + 'static) {
+        self.on_apply = Some(Box::new(listener));
+    }
+
+    // disputes work on both deposits and withdrawals, with different effects on the funds:
+    // - a disputed deposit gets its amount moved from available to held, a resolve moves it
+    //   back, while a chargeback drops the held funds and locks the account
+    // - a disputed withdrawal only gets marked as disputed, since its funds already left the
+    //   account and there's nothing to hold; a resolve just clears the mark, while a chargeback
+    //   reverses the withdrawal, crediting the withdrawn amount back to available (unless the
+    //   withdrawal dispute mode is `Hold`, see `WithdrawalDisputeMode`)
+    //
+    // dispute/resolve/chargeback records that can't be applied are either reported as errors,
+    // or dropped with a dedicated `Ignored*` outcome, depending on the ignored operation policy
+    pub fn parse_entry(&mut self, record: &Record) -> Result<ParseOutcome, Errors> {
+        self.records_seen += 1;
+        if let Some(horizon) = self.prune_after {
+            if self.records_seen.is_multiple_of(horizon) {
+                self.prune(horizon);
+            }
+        }
+        if self.reserved_clients.contains(record.client) {
+            let err = Errors::ReservedClient(record.client);
+            self.failures.count(&err);
+            let result = Err(err);
+            self.count_metrics(record, &result);
+            return result;
+        }
+        let window = self.out_of_order_window.unwrap_or_default();
+        while matches!(self.pending.front(), Some((seq, _)) if seq + window < self.records_seen) {
+            if let Some((seq, record)) = self.pending.pop_front() {
+                self.drop_pending(seq, record);
+            }
+        }
+        let refers_to_transaction = matches!(
+            record.r#type,
+            OperationType::Dispute | OperationType::Resolve | OperationType::Chargeback
+        );
+        if !refers_to_transaction {
+            if self.resumed_from.is_some_and(|seq| record.tx <= seq) {
+                let result = Ok(ParseOutcome::IgnoredBeforeCheckpoint);
+                self.count_metrics(record, &result);
+                return result;
+            }
+            if self.since_tx.is_some_and(|since| record.tx < since)
+                || self.until_tx.is_some_and(|until| record.tx > until)
+            {
+                let result = Ok(ParseOutcome::IgnoredOutsideTxRange);
+                self.count_metrics(record, &result);
+                return result;
+            }
+            self.checkpoint = self.checkpoint.max(record.tx);
+        }
+        if let (true, Some(timestamp), Some(last)) =
+            (self.enforce_order, record.timestamp, self.last_timestamp)
+        {
+            if timestamp < last {
+                let err = Errors::OutOfOrder(record.tx, timestamp, last);
+                self.failures.count(&err);
+                let result = Err(err);
+                self.count_metrics(record, &result);
+                return result;
+            }
+        }
+        if self.out_of_order_window.is_some()
+            && refers_to_transaction
+            && !self.transactions.contains_key(&record.tx)
+        {
+            self.pending.push_back((self.records_seen, record.clone()));
+            return Ok(ParseOutcome::Deferred);
+        }
+
+        let result = self.apply_entry(record);
+        self.count_metrics(record, &result);
+        if let Ok(ParseOutcome::Applied { .. } | ParseOutcome::Locked { .. }) = result {
+            self.last_timestamp = self.last_timestamp.max(record.timestamp);
+        }
+        match &result {
+            Ok(_) if !refers_to_transaction => self.apply_pending(record.tx),
+            Ok(_) => {}
+            Err(err) => self.failures.count(err),
+        }
+        result
+    }
+
+    fn prune(&mut self, horizon: u64) {
+        let held_withdrawals = self.withdrawal_dispute_mode == WithdrawalDisputeMode::Hold;
+        let records_seen = self.records_seen;
+        let pruned_balance = &mut self.pruned_balance;
+        self.transactions.retain(|_, transaction| {
+            let settled = transaction.charged_back
+                || (!transaction.under_dispute
+                    && records_seen.saturating_sub(transaction.seq) >= horizon);
+            if settled {
+                *pruned_balance =
+                    pruned_balance.saturating_add(transaction.balance(held_withdrawals));
+            }
+            !settled
+        });
+    }
+
+    fn count_metrics(&mut self, record: &Record, result: &Result<ParseOutcome, Errors>) {
+        client_metrics(&mut self.metrics, record.client).count(record.r#type, result);
+    }
+
+    //a deferred record whose transaction never showed up, handled like any other one referencing
+    //an unknown transaction
+    fn drop_pending(&mut self, seq: u64, record: Record) {
+        let result = self.ignored_policy.apply(
+            Errors::UnknownTransaction(record.tx),
+            ParseOutcome::IgnoredNoSuchTx,
+        );
+        self.count_metrics(&record, &result);
+        if let Err(err) = &result {
+            self.failures.count(err);
+        }
+        self.dropped.push(DroppedRecord {
+            seq,
+            record,
+            result,
+        });
+    }
+
+    // ends the input: the records still waiting for their transaction are dropped, just like the
+    // ones beyond the window, and every deferred record dropped so far is handed back
+    pub fn finish(&mut self) -> Vec<DroppedRecord> {
+        while let Some((seq, record)) = self.pending.pop_front() {
+            self.drop_pending(seq, record);
+        }
+        std::mem::take(&mut self.dropped)
+    }
+
+    // the number of records parsed so far (including the ones of a loaded snapshot), the last one
+    // is parsed as this very number
+    pub fn records_seen(&self) -> u64 {
+        self.records_seen
+    }
+
+    fn apply_pending(&mut self, tx: u32) {
+        if !self.pending.iter().any(|(_, record)| record.tx == tx) {
+            return;
+        }
+        let (ready, waiting) = self
+            .pending
+            .drain(..)
+            .partition::<VecDeque<_>, _>(|(_, record)| record.tx == tx);
+        self.pending = waiting;
+        for (_, record) in ready {
+            let result = self.apply_entry(&record);
+            self.count_metrics(&record, &result);
+            if let Err(err) = result {
+                self.failures.count(&err);
+            }
+        }
+    }
+
+    // applies every record in order, failed records are only counted
+    pub fn apply_all(&mut self, records: impl IntoIterator<Item = Record>) -> ProcessedCounts {
+        let mut counts = ProcessedCounts::default();
+        for record in records {
+            match self.parse_entry(&record) {
+                Ok(_) => counts.applied += 1,
+                Err(_) => counts.failed += 1,
+            }
+        }
+        counts
+    }
+
+    // applies the records in fixed-size batches, the callback gets the cumulative counts after each batch
+    pub fn process_batched(
+        &mut self,
+        records: impl IntoIterator<Item = Record>,
+        batch_size: usize,
+        mut on_batch: impl FnMut(ProcessedCounts),
+    ) -> ProcessedCounts {
+        let batch_size = batch_size.max(1);
+        let mut records = records.into_iter().peekable();
+        let mut counts = ProcessedCounts::default();
+        while records.peek().is_some() {
+            let batch = self.apply_all(records.by_ref().take(batch_size));
+            counts.applied += batch.applied;
+            counts.failed += batch.failed;
+            on_batch(counts);
+        }
+        counts
+    }
+
+    pub fn failures(&self) -> FailureCounts {
+        self.failures
+    }
+
+    // applies the records using `num_threads` workers, each owning the clients with the same
+    // `client % num_threads` along with their transactions; the records are dispatched in order,
+    // so every client sees its records in the input order, and the tx id uniqueness (as well as
+    // the tx ownership) is checked across all of the clients on the dispatching side.
+    // Pruning, the out of order window and the enforced ordering of the timestamps depend on the
+    // state of all of the clients at the same point of the input (the last applied timestamp,
+    // the deferred records), so with `set_prune_after`, `set_out_of_order_window` or
+    // `set_enforce_order` the records are applied in order by the calling thread instead.
+    pub fn process_parallel(
+        &mut self,
+        records: impl IntoIterator<Item = Record>,
+        num_threads: usize,
+    ) -> ProcessedCounts {
+        if self.prune_after.is_some() || self.out_of_order_window.is_some() || self.enforce_order {
+            return self.apply_all(records);
+        }
+        let num_threads = num_threads.max(1);
+        let shard_of = |client: u16| usize::from(client) % num_threads;
+        let on_lock = self
+            .on_lock
+            .take()
+            .map(|listener| Arc::new(Mutex::new(listener)));
+        let on_apply = self
+            .on_apply
+            .take()
+            .map(|listener| Arc::new(Mutex::new(listener)));
+        let mut shards: Vec<Self> = (0..num_threads)
+            .map(|_| self.shard(&on_lock, &on_apply))
+            .collect();
+        let mut owners: HashMap<u32, (u16, OperationType)> =
+            HashMap::with_capacity(self.transactions.len());
+        for (tx, transaction) in self.transactions.drain() {
+            owners.insert(tx, (transaction.client, transaction.operation));
+            shards[shard_of(transaction.client)]
+                .transactions
+                .insert(tx, transaction);
+        }
+        for (key, account) in self.accounts.drain(..) {
+            shards[shard_of(key.client)].accounts.insert(key, account);
+        }
+        for (client, metrics) in self.metrics.drain() {
+            shards[shard_of(client)].metrics.insert(client, metrics);
+        }
+
+        let results = thread::scope(|scope| {
+            let (senders, workers): (Vec<_>, Vec<_>) = shards
+                .drain(..)
+                .map(|mut shard| {
+                    let (sender, receiver) = mpsc::sync_channel::<ShardJob>(SHARD_QUEUE_SIZE);
+                    let worker = scope.spawn(move || {
+                        let mut counts = ProcessedCounts::default();
+                        for job in receiver {
+                            match shard.apply_job(job) {
+                                Ok(_) => counts.applied += 1,
+                                Err(_) => counts.failed += 1,
+                            }
+                        }
+                        (shard, counts)
+                    });
+                    (sender, worker)
+                })
+                .unzip();
+
+            for record in records {
+                let conflict = match (record.r#type, owners.get(&record.tx)) {
+                    //filtered out by the worker before the tx id is even looked up
+                    (OperationType::Deposit | OperationType::Withdrawal, _)
+                        if self.skips_transaction(&record) =>
+                    {
+                        None
+                    }
+                    (
+                        OperationType::Deposit | OperationType::Withdrawal,
+                        Some(&(owner, existing)),
+                    ) => Some(duplicate_transaction(
+                        record.tx,
+                        (record.client, record.r#type),
+                        (owner, existing),
+                    )),
+                    //a deposit/withdrawal rejected before it's recorded leaves its tx id free
+                    (OperationType::Deposit | OperationType::Withdrawal, None) => {
+                        if self.records_transaction(&record) {
+                            owners.insert(record.tx, (record.client, record.r#type));
+                        }
+                        None
+                    }
+                    (_, Some((owner, _))) if *owner != record.client => {
+                        Some(Errors::ClientMismatch(record.tx, record.client))
+                    }
+                    _ => None,
+                };
+                let shard = shard_of(record.client);
+                //a worker only stops on a panic, which is propagated by the join below
+                let _ = senders[shard].send(ShardJob { record, conflict });
+            }
+            drop(senders);
+            workers
+                .into_iter()
+                .map(|worker| worker.join().expect("transactions worker panicked"))
+                .collect::<Vec<_>>()
+        });
+
+        let mut counts = ProcessedCounts::default();
+        for (shard, shard_counts) in results {
+            self.accounts.extend(shard.accounts);
+            self.transactions.extend(shard.transactions);
+            self.failures.add(shard.failures);
+            self.checkpoint = self.checkpoint.max(shard.checkpoint);
+            self.last_timestamp = self.last_timestamp.max(shard.last_timestamp);
+            self.metrics.extend(shard.metrics);
+            self.pending.extend(shard.pending);
+            self.dropped.extend(shard.dropped);
+            self.fees_collected = self.fees_collected.saturating_add(shard.fees_collected);
+            self.pruned_balance = self.pruned_balance.saturating_add(shard.pruned_balance);
+            counts.applied += shard_counts.applied;
+            counts.failed += shard_counts.failed;
+        }
+        self.records_seen += counts.total();
+        self.on_lock = on_lock.map(|listener| match Arc::try_unwrap(listener) {
+            Ok(listener) => listener.into_inner().unwrap_or_else(|err| err.into_inner()),
+            Err(_) => unreachable!("every shard is dropped by now"),
+        });
+        self.on_apply = on_apply.map(|listener| match Arc::try_unwrap(listener) {
+            Ok(listener) => listener.into_inner().unwrap_or_else(|err| err.into_inner()),
+            Err(_) => unreachable!("every shard is dropped by now"),
+        });
+        counts
+    }
+
+    // whether `parse_entry` drops a deposit/withdrawal before looking up its tx id
+    fn skips_transaction(&self, record: &Record) -> bool {
+        self.reserved_clients.contains(record.client)
+            || self.resumed_from.is_some_and(|seq| record.tx <= seq)
+            || self.since_tx.is_some_and(|since| record.tx < since)
+            || self.until_tx.is_some_and(|until| record.tx > until)
+    }
+
+    // whether a deposit/withdrawal with a free tx id gets recorded, i.e. its amount can be scaled
+    // and isn't a rejected zero; a recorded one claims the tx id even if it fails on the funds
+    fn records_transaction(&self, record: &Record) -> bool {
+        match scaled_amount(record, self.amount_scale, self.normalize_scale) {
+            Ok(amount) => !(self.reject_zero && amount.is_some_and(|amount| amount.is_zero())),
+            Err(_) => false,
+        }
+    }
+
+    // an empty manager configured the very same way, sharing the listeners
+    fn shard(
+        &self,
+        on_lock: &Option<Arc<Mutex<LockListener>>>,
+        on_apply: &Option<Arc<Mutex<ApplyListener>>>,
+    ) -> Self {
+        let mut shard = Self::new();
+        //keeps the new transactions ordered after the already known ones
+        shard.records_seen = self.records_seen;
+        shard.ignored_policy = self.ignored_policy;
+        shard.deposit_disputes_only = self.deposit_disputes_only;
+        shard.reject_zero = self.reject_zero;
+        shard.lock_on_withdrawal_chargeback = self.lock_on_withdrawal_chargeback;
+        shard.withdrawal_dispute_mode = self.withdrawal_dispute_mode;
+        shard.enforce_order = self.enforce_order;
+        shard.last_timestamp = self.last_timestamp;
+        shard.reserved_clients = self.reserved_clients.clone();
+        shard.amount_scale = self.amount_scale;
+        shard.normalize_scale = self.normalize_scale;
+        shard.withdrawal_fee = self.withdrawal_fee;
+        shard.credit_limit = self.credit_limit;
+        shard.max_balance = self.max_balance;
+        shard.overflow_policy = self.overflow_policy;
+        shard.out_of_order_window = self.out_of_order_window;
+        shard.prune_after = self.prune_after;
+        shard.checkpoint = self.checkpoint;
+        shard.resumed_from = self.resumed_from;
+        shard.since_tx = self.since_tx;
+        shard.until_tx = self.until_tx;
+        if let Some(listener) = on_lock {
+            let listener = Arc::clone(listener);
+            shard.set_on_lock(move |client, tx| {
+                let mut listener = listener.lock().unwrap_or_else(|err| err.into_inner());
+                listener(client, tx)
+            });
+        }
+        if let Some(listener) = on_apply {
+            let listener = Arc::clone(listener);
+            shard.set_on_apply(move |record, account| {
+                let mut listener = listener.lock().unwrap_or_else(|err| err.into_inner());
+                listener(record, account)
+            });
+        }
+        shard
+    }
+
+    // wipes the state of the previous batch (keeping the capacity of the maps), while the
+    // configuration and the listeners stay as they are
+    pub fn clear(&mut self) {
+        self.accounts.clear();
+        self.transactions.clear();
+        self.pending.clear();
+        self.dropped.clear();
+        self.metrics.clear();
+        self.failures = Default::default();
+        self.fees_collected = Decimal::ZERO;
+        self.pruned_balance = Decimal::ZERO;
+        self.records_seen = 0;
+        self.checkpoint = 0;
+        self.last_timestamp = None;
+        self.resumed_from = None;
+    }
+
+    // persists the accounts and the transactions (along with their dispute state) as JSON, for a
+    // warm restart with `load_snapshot`; the deferred records, the metrics, and the failure counts
+    // aren't included
+    pub fn save_snapshot(&self, path: impl AsRef<Path>) -> Result<()> {
+        let snapshot = Snapshot {
+            accounts: self.accounts.values().map(AccountSnapshot::from).collect(),
+            transactions: &self.transactions,
+            records_seen: self.records_seen,
+            checkpoint: self.checkpoint,
+            last_timestamp: self.last_timestamp,
+            fees_collected: self.fees_collected,
+            pruned_balance: self.pruned_balance,
+        };
+        let mut writer = std::io::BufWriter::new(std::fs::File::create(path)?);
+        serde_json::to_writer(&mut writer, &snapshot)?;
+        std::io::Write::flush(&mut writer)?;
+        Ok(())
+    }
+
+    // replaces the state of the manager with the one saved by `save_snapshot`, while the
+    // configuration (and listeners) stay as they are; the limits apply to the restored accounts
+    pub fn load_snapshot(&mut self, path: impl AsRef<Path>) -> Result<()> {
+        let reader = std::io::BufReader::new(std::fs::File::open(path)?);
+        let snapshot: Snapshot<Transactions> = serde_json::from_reader(reader)?;
+        self.clear();
+        for account in snapshot.accounts {
+            let mut account = Account::from(account);
+            account.set_credit_limit(self.credit_limit)?;
+            account.set_max_balance(self.max_balance)?;
+            account.set_overflow_policy(self.overflow_policy);
+            self.accounts
+                .insert(AccountKey::of_account(&account), account);
+        }
+        self.transactions = snapshot.transactions;
+        self.records_seen = snapshot.records_seen;
+        self.checkpoint = snapshot.checkpoint;
+        self.last_timestamp = snapshot.last_timestamp;
+        self.fees_collected = snapshot.fees_collected;
+        self.pruned_balance = snapshot.pruned_balance;
+        Ok(())
+    }
+
+    // combines the results of runs over disjoint client partitions, both the clients and the tx ids
+    // have to be disjoint; the configuration (and listeners) of `self` are kept
+    pub fn merge(mut self, other: TransactionManager) -> Result<TransactionManager, Errors> {
+        if let Some(key) = other
+            .accounts
+            .keys()
+            .find(|key| self.accounts.contains_key(*key))
+        {
+            return Err(Errors::AccountOverlap(key.client));
+        }
+        for (tx, transaction) in &other.transactions {
+            if let Some(existing) = self.transactions.get(tx) {
+                return Err(duplicate_transaction(
+                    *tx,
+                    (transaction.client, transaction.operation),
+                    (existing.client, existing.operation),
+                ));
+            }
+        }
+        self.accounts.extend(other.accounts);
+        self.transactions.extend(other.transactions);
+        self.failures.add(other.failures);
+        self.metrics.extend(other.metrics);
+        self.pending.extend(other.pending);
+        self.dropped.extend(other.dropped);
+        self.fees_collected = self.fees_collected.saturating_add(other.fees_collected);
+        self.pruned_balance = self.pruned_balance.saturating_add(other.pruned_balance);
+        self.records_seen += other.records_seen;
+        self.checkpoint = self.checkpoint.max(other.checkpoint);
+        self.last_timestamp = self.last_timestamp.max(other.last_timestamp);
+        Ok(self)
+    }
+
+    fn apply_job(&mut self, job: ShardJob) -> Result<ParseOutcome, Errors> {
+        let Some(conflict) = job.conflict else {
+            return self.parse_entry(&job.record);
+        };
+        if self.reserved_clients.contains(job.record.client) {
+            return self.parse_entry(&job.record);
+        }
+        //mirrors `parse_entry`: a deposit/withdrawal creates the account even when it's rejected,
+        //while an inapplicable dispute/resolve/chargeback is subject to the ignored policy
+        self.records_seen += 1;
+        let result = match job.record.r#type {
+            OperationType::Deposit | OperationType::Withdrawal => {
+                self.checkpoint = self.checkpoint.max(job.record.tx);
+                account_of(
+                    &mut self.accounts,
+                    &job.record,
+                    self.credit_limit,
+                    self.max_balance,
+                    self.overflow_policy,
+                );
+                Err(conflict)
+            }
+            _ => self
+                .ignored_policy
+                .apply(conflict, ParseOutcome::IgnoredClientMismatch),
+        };
+        self.count_metrics(&job.record, &result);
+        if let Err(err) = &result {
+            self.failures.count(err);
+        }
+        result
+    }
+
+    fn apply_entry(&mut self, record: &Record) -> Result<ParseOutcome, Errors> {
+        let policy = self.ignored_policy;
+        let before = funds_of(&self.accounts, record);
+
+        //keep track only of transactions that are of type deposit or withdrawal
+        //if there's a dispute/resolve/chargeback that is reffering to a non-existing operation
+        //then it would get dropped anyway (or reported, depending on the policy)
+        //a dispute/resolve/chargeback is applied only to the account that owns the referenced
+        //transaction, so it never creates an account on its own
+        match record.r#type {
+            OperationType::Deposit => {
+                let account = account_of(
+                    &mut self.accounts,
+                    record,
+                    self.credit_limit,
+                    self.max_balance,
+                    self.overflow_policy,
+                );
+                if let Some(existing) = self.transactions.get(&record.tx) {
+                    return Err(duplicate_transaction(
+                        record.tx,
+                        (record.client, record.r#type),
+                        (existing.client, existing.operation),
+                    ));
+                }
+                let amount = scaled_amount(record, self.amount_scale, self.normalize_scale)?;
+                if self.reject_zero && amount.is_some_and(|amount| amount.is_zero()) {
+                    return Err(Errors::ZeroAmount(record.tx));
+                }
+                self.transactions.insert(
+                    record.tx,
+                    TransactionRecord {
+                        currency: record.currency.clone(),
+                        ..TransactionRecord::new(
+                            record.client,
+                            record.r#type,
+                            amount,
+                            self.records_seen,
+                        )
+                    },
+                );
+                if let Some(amount) = amount {
+                    account.deposit(amount)?;
+                }
+                mark_applied(&mut self.transactions, record.tx);
+            }
+            OperationType::Withdrawal => {
+                let account = account_of(
+                    &mut self.accounts,
+                    record,
+                    self.credit_limit,
+                    self.max_balance,
+                    self.overflow_policy,
+                );
+                if let Some(existing) = self.transactions.get(&record.tx) {
+                    return Err(duplicate_transaction(
+                        record.tx,
+                        (record.client, record.r#type),
+                        (existing.client, existing.operation),
+                    ));
+                }
+                let amount = scaled_amount(record, self.amount_scale, self.normalize_scale)?;
+                if self.reject_zero && amount.is_some_and(|amount| amount.is_zero()) {
+                    return Err(Errors::ZeroAmount(record.tx));
+                }
+                self.transactions.insert(
+                    record.tx,
+                    TransactionRecord {
+                        currency: record.currency.clone(),
+                        ..TransactionRecord::new(
+                            record.client,
+                            record.r#type,
+                            amount,
+                            self.records_seen,
+                        )
+                    },
+                );
+                if let Some(amount) = amount {
+                    match self.withdrawal_fee {
+                        Some(fee) => {
+                            let fee = fee
+                                .fee_for(*amount)
+                                .ok_or(Errors::FundsOverflow(record.client))?;
+                            account.withdrawal_with_fee(amount, fee)?;
+                            self.fees_collected = self
+                                .fees_collected
+                                .checked_add(fee)
+                                .ok_or(Errors::FundsOverflow(record.client))?;
+                        }
+                        None => account.withdrawal(amount)?,
+                    }
+                }
+                mark_applied(&mut self.transactions, record.tx);
+            }
+            OperationType::Chargeback => {
+                let transaction = match referenced_transaction(&mut self.transactions, record) {
+                    Ok(transaction) => transaction,
+                    Err(err) => {
+                        let ignored = not_referenced(&err);
+                        return policy.apply(err, ignored);
+                    }
+                };
+                let account = account_of(
+                    &mut self.accounts,
+                    record,
+                    self.credit_limit,
+                    self.max_balance,
+                    self.overflow_policy,
+                );
+                //same as for a dispute, the funds of a charged back withdrawal were already
+                //credited back, a second chargeback never applies
+                if transaction.charged_back && transaction.operation == OperationType::Withdrawal {
+                    return policy.apply(
+                        Errors::AlreadyChargedBack(record.tx),
+                        ParseOutcome::IgnoredNotDisputed,
+                    );
+                }
+                if !transaction.under_dispute {
+                    return policy.apply(
+                        not_under_dispute(transaction, record.tx),
+                        ParseOutcome::IgnoredNotDisputed,
+                    );
+                }
+                if transaction.amount.is_some() {
+                    let was_locked = account.is_locked();
+                    //each path only fires for its own operation type, anything else stored (e.g.
+                    //by a malformed snapshot) is rejected before any funds move
+                    match transaction.operation {
+                        OperationType::Withdrawal => {
+                            let lock = self.lock_on_withdrawal_chargeback;
+                            //only what's still disputed gets back, in either mode, a partial resolve
+                            //already confirmed the rest
+                            let reversed: Amount = transaction.held.into();
+                            match self.withdrawal_dispute_mode {
+                                WithdrawalDisputeMode::None => {
+                                    account.chargeback_withdrawal(reversed, lock)?
+                                }
+                                WithdrawalDisputeMode::Hold => {
+                                    account.chargeback_held_withdrawal(reversed, lock)?
+                                }
+                            }
+                            transaction.reversed = *reversed;
+                            transaction.held = Decimal::ZERO;
+                        }
+                        OperationType::Deposit => {
+                            //only what's still held for this dispute is dropped, a partial resolve
+                            //already released the rest
+                            account.chargeback(transaction.held.into())?;
+                            transaction.reversed = transaction.held;
+                            transaction.held = Decimal::ZERO;
+                        }
+                        other => return Err(Errors::UnsupportedOperation(record.tx, other)),
+                    }
+                    //only the chargeback locking the account notifies, a later one on the
+                    //already locked account doesn't lock it again
+                    if let (false, true, Some(on_lock)) =
+                        (was_locked, account.is_locked(), self.on_lock.as_mut())
+                    {
+                        on_lock(record.client, record.tx);
+                    }
+                    //marked only once the funds moved, a rejected chargeback (e.g. exceeding the
+                    //maximum balance) leaves the transaction disputed
+                    transaction.under_dispute = false;
+                    transaction.charged_back = true;
+                    account.count_chargeback();
+                }
+            }
+            OperationType::Dispute => {
+                let transaction = match referenced_transaction(&mut self.transactions, record) {
+                    Ok(transaction) => transaction,
+                    Err(err) => {
+                        let ignored = not_referenced(&err);
+                        return policy.apply(err, ignored);
+                    }
+                };
+                if self.deposit_disputes_only && transaction.operation != OperationType::Deposit {
+                    return Err(Errors::DisputeTypeMismatch(
+                        record.tx,
+                        transaction.operation,
+                    ));
+                }
+                //funds of a charged back withdrawal were already reversed, disputing it again
+                //would account for them twice, so it never applies
+                if transaction.charged_back && transaction.operation == OperationType::Withdrawal {
+                    return policy.apply(
+                        Errors::TransactionAlreadyReversed(record.tx),
+                        ParseOutcome::IgnoredNotDisputable,
+                    );
+                }
+                if transaction.under_dispute {
+                    return policy.apply(
+                        Errors::TransactionNotDisputable(record.tx),
+                        ParseOutcome::IgnoredAlreadyDisputed,
+                    );
+                }
+                //a rejected deposit/withdrawal never moved any funds, a chargeback of it would
+                //make them up (e.g. crediting a withdrawal that failed on insufficient funds)
+                if transaction.amount.is_none() || !transaction.applied {
+                    return policy.apply(
+                        Errors::TransactionNotDisputable(record.tx),
+                        ParseOutcome::IgnoredNotDisputable,
+                    );
+                }
+                //a resolved transaction might be disputed again, a charged back one never
+                if transaction.charged_back {
+                    return policy.apply(
+                        Errors::TransactionAlreadySettled(record.tx),
+                        ParseOutcome::IgnoredNotDisputable,
+                    );
+                }
+                let account = account_of(
+                    &mut self.accounts,
+                    record,
+                    self.credit_limit,
+                    self.max_balance,
+                    self.overflow_policy,
+                );
+                //unless the withdrawal dispute mode says otherwise, nothing is held for a
+                //withdrawal, its funds aren't in the account anymore
+                if let Some(amount) = transaction.amount {
+                    match (transaction.operation, self.withdrawal_dispute_mode) {
+                        (OperationType::Deposit, _) => account.dispute(amount)?,
+                        (OperationType::Withdrawal, WithdrawalDisputeMode::Hold) => {
+                            account.dispute_withdrawal(amount)?
+                        }
+                        (OperationType::Withdrawal, WithdrawalDisputeMode::None) => {
+                            client_metrics(&mut self.metrics, record.client)
+                                .unheld_withdrawal_disputes += 1;
+                        }
+                        (other, _) => return Err(Errors::UnsupportedOperation(record.tx, other)),
+                    }
+                    transaction.held = *amount;
+                }
+                transaction.under_dispute = true;
+                transaction.already_disputed = true;
+                account.count_dispute();
+            }
+            OperationType::Resolve => {
+                let transaction = match referenced_transaction(&mut self.transactions, record) {
+                    Ok(transaction) => transaction,
+                    Err(err) => {
+                        let ignored = not_referenced(&err);
+                        return policy.apply(err, ignored);
+                    }
+                };
+                let account = account_of(
+                    &mut self.accounts,
+                    record,
+                    self.credit_limit,
+                    self.max_balance,
+                    self.overflow_policy,
+                );
+                //a charged back transaction is final, which is worth telling apart for audits
+                if transaction.charged_back {
+                    return policy.apply(
+                        Errors::ResolveOnChargedBack(record.tx),
+                        ParseOutcome::IgnoredNotDisputed,
+                    );
+                }
+                if !transaction.under_dispute {
+                    return policy.apply(
+                        not_under_dispute(transaction, record.tx),
+                        ParseOutcome::IgnoredNotDisputed,
+                    );
+                }
+                //a resolve carrying an amount releases only that much, the rest stays disputed
+                let amount = scaled_amount(record, self.amount_scale, self.normalize_scale)?;
+                let released = match amount {
+                    Some(amount) if *amount < Decimal::ZERO => {
+                        return Err(Errors::NegativeAmount(*amount))
+                    }
+                    Some(amount) if *amount > transaction.held => {
+                        return Err(Errors::ResolveExceedsHeld(record.tx, *amount))
+                    }
+                    Some(amount) => *amount,
+                    None => transaction.held,
+                };
+                match (transaction.operation, self.withdrawal_dispute_mode) {
+                    (OperationType::Deposit, _) => account.resolve(released.into())?,
+                    (OperationType::Withdrawal, WithdrawalDisputeMode::Hold) => {
+                        account.resolve_withdrawal(released.into())?
+                    }
+                    (OperationType::Withdrawal, WithdrawalDisputeMode::None) => {}
+                    (other, _) => return Err(Errors::UnsupportedOperation(record.tx, other)),
+                }
+                transaction.held -= released;
+                transaction.under_dispute = !transaction.held.is_zero();
+            }
+        }
+        if let (Some(on_apply), Some(account)) = (
+            self.on_apply.as_mut(),
+            self.accounts.get(&AccountKey::of(record)),
+        ) {
+            on_apply(record, account);
+        }
+        let (available, held, locked) = funds_of(&self.accounts, record);
+        let (client, delta_available, delta_held) =
+            (record.client, available - before.0, held - before.1);
+        if locked && !before.2 {
+            Ok(ParseOutcome::Locked {
+                client,
+                delta_available,
+                delta_held,
+            })
+        } else {
+            Ok(ParseOutcome::Applied {
+                client,
+                delta_available,
+                delta_held,
+            })
+        }
+    }
+
+    // checks the funds of all the accounts against the ones the transaction log adds up to:
+    // the applied deposits, less the applied withdrawals and their fees, with the chargebacks
+    // reversing their transactions
+    pub fn reconcile(&self) -> Result<(), Errors> {
+        let held_withdrawals = self.withdrawal_dispute_mode == WithdrawalDisputeMode::Hold;
+        let expected = self.transactions.values().fold(
+            self.pruned_balance.saturating_sub(self.fees_collected),
+            |expected, transaction| expected.saturating_add(transaction.balance(held_withdrawals)),
+        );
+        let actual = self
+            .accounts
+            .values()
+            .fold(Decimal::ZERO, |actual, account| {
+                actual
+                    .saturating_add(account.available())
+                    .saturating_add(account.held())
+            });
+        if expected != actual {
+            return Err(Errors::ReconciliationMismatch { expected, actual });
+        }
+        Ok(())
+    }
+
+    // the records processed so far, per client ordered by the client id
+    pub fn metrics(&self) -> Vec<Metrics> {
+        let mut metrics: Vec<_> = self.metrics.values().copied().collect();
+        metrics.sort_unstable_by_key(|metrics| metrics.client);
+        metrics
+    }
+
+    // the accounts in the order their clients were first seen (in the order of the workers, or
+    // of the merged managers, after `process_parallel`/`merge`)
+    pub fn accounts(&self) -> impl Iterator<Item = &Account> {
+        self.accounts.values()
+    }
+
+    // the account of a single-asset input, see `currency_account` for a multi-asset one
+    pub fn account(&self, client: u16) -> Option<&Account> {
+        self.accounts.get(&client)
+    }
+
+    pub fn currency_account(&self, client: u16, currency: &str) -> Option<&Account> {
+        self.accounts.get(&AccountKey {
+            client,
+            currency: Some(currency.to_string()),
+        })
+    }
+
+    pub fn account_count(&self) -> usize {
+        self.accounts.len()
+    }
+
+    pub fn summary(&self) -> RunSummary {
+        let mut summary = RunSummary {
+            accounts: self.accounts.len(),
+            ..Default::default()
+        };
+        for account in self.accounts.values() {
+            if account.is_locked() {
+                summary.locked_accounts += 1;
+            }
+            summary.total_held = summary.total_held.saturating_add(account.held());
+        }
+        //a rejected deposit/withdrawal is kept for the tx id uniqueness only, as in `reconcile`
+        for transaction in self.transactions.values().filter(|t| t.applied) {
+            match transaction.operation {
+                OperationType::Deposit => summary.deposits += 1,
+                OperationType::Withdrawal => summary.withdrawals += 1,
+                _ => {}
+            }
+            if transaction.under_dispute {
+                summary.disputes_outstanding += 1;
+            }
+        }
+        summary
+    }
+
+    // every deposit/withdrawal of the client, in the order they were processed
+    pub fn client_transactions(&self, client: u16) -> Vec<TransactionSummary> {
+        let mut transactions: Vec<_> = self
+            .transactions
+            .iter()
+            .filter(|(_, transaction)| transaction.client == client)
+            .collect();
+        transactions.sort_unstable_by_key(|(_, transaction)| transaction.seq);
+        transactions
+            .into_iter()
+            .map(|(tx, transaction)| TransactionSummary {
+                tx: *tx,
+                operation: transaction.operation,
+                amount: transaction.amount,
+                state: transaction.dispute_state(),
+            })
+            .collect()
+    }
+
+    // the accounts ordered by the client id (then the currency) ascending, so the output is the
+    // same for the same input
+    pub fn accounts_by_client(&self) -> Vec<&Account> {
+        let mut accounts: Vec<_> = self.accounts.iter().collect();
+        accounts.sort_unstable_by_key(|(key, _)| *key);
+        accounts.into_iter().map(|(_, account)| account).collect()
+    }
+
+    // the synthetic records balancing every account to zero available funds at the end of the
+    // input, ordered by the client id: a withdrawal of the available funds, or a deposit of an
+    // overdraft, with tx ids following the checkpoint. The accounts are left as they are unless
+    // `apply` is set, then the records are applied (whatever the state of the accounts, without
+    // locking them) and recorded like any other deposit/withdrawal
+    pub fn closeout(&mut self, apply: bool) -> Result<Vec<Record>, Errors> {
+        let mut keys: Vec<_> = self.accounts.keys().cloned().collect();
+        keys.sort_unstable();
+        let mut tx = self.checkpoint;
+        let mut records = Vec::new();
+        for key in keys {
+            let available = self.accounts[&key].available();
+            if available.is_zero() {
+                continue;
+            }
+            tx = tx
+                .checked_add(1)
+                .ok_or(Errors::CloseoutTxIdsExhausted(key.client))?;
+            let operation = if available.is_sign_positive() {
+                OperationType::Withdrawal
+            } else {
+                OperationType::Deposit
+            };
+            records.push(Record {
+                currency: key.currency,
+                ..Record::new(operation, key.client, tx, Some(available.abs().into()))
+            });
+        }
+        if apply {
+            for record in &records {
+                if let Some(account) = self.accounts.get_mut(&AccountKey::of(record)) {
+                    account.close_out();
+                }
+                self.records_seen += 1;
+                self.transactions.insert(
+                    record.tx,
+                    TransactionRecord {
+                        currency: record.currency.clone(),
+                        applied: true,
+                        ..TransactionRecord::new(
+                            record.client,
+                            record.r#type,
+                            record.amount,
+                            self.records_seen,
+                        )
+                    },
+                );
+                self.checkpoint = record.tx;
+            }
+        }
+        Ok(records)
+    }
+}
+
+//the account of the record's client in the record's currency
+fn account_of<'a>(
+    accounts: &'a mut Accounts,
+    record: &Record,
+    credit_limit: Decimal,
+    max_balance: Option<Decimal>,
+    overflow_policy: OverflowPolicy,
+) -> &'a mut Account {
+    accounts.entry(AccountKey::of(record)).or_insert_with(|| {
+        let mut account = Account::new(record.client).with_currency(record.currency.clone());
+        //the limits are validated by the setters already
+        let _ = account.set_credit_limit(credit_limit);
+        let _ = account.set_max_balance(max_balance);
+        account.set_overflow_policy(overflow_policy);
+        account
+    })
+}
+
+//available funds, held funds, and the lock state of the client's account (a new one if there's none)
+fn funds_of(accounts: &Accounts, record: &Record) -> (Decimal, Decimal, bool) {
+    accounts
+        .get(&AccountKey::of(record))
+        .map_or((Decimal::ZERO, Decimal::ZERO, false), |account| {
+            (account.available(), account.held(), account.is_locked())
+        })
+}
+
+fn client_metrics(metrics: &mut HashMap<u16, Metrics>, client: u16) -> &mut Metrics {
+    metrics.entry(client).or_insert_with(|| Metrics {
+        client,
+        ..Default::default()
+    })
+}
+
+fn scaled_amount(
+    record: &Record,
+    scale: Option<Decimal>,
+    normalize: Option<u32>,
+) -> Result<Option<Amount>, Errors> {
+    let amount = match (record.amount, scale) {
+        (Some(amount), Some(factor)) => amount
+            .checked_mul(factor)
+            .map(|scaled| Some(scaled.into()))
+            .ok_or(Errors::FundsOverflow(record.client))?,
+        (amount, _) => amount,
+    };
+    Ok(match (amount, normalize) {
+        (Some(amount), Some(scale)) => Some(amount.normalized(scale)),
+        (amount, _) => amount,
+    })
+}
+
+//looks up the transaction a dispute/resolve/chargeback refers to, making sure it belongs to the same
+//client, and is in the same currency
+fn referenced_transaction<'a>(
+    transactions: &'a mut Transactions,
+    record: &Record,
+) -> Result<&'a mut TransactionRecord, Errors> {
+    let transaction = transactions
+        .get_mut(&record.tx)
+        .ok_or(Errors::UnknownTransaction(record.tx))?;
+    if transaction.client != record.client {
+        return Err(Errors::ClientMismatch(record.tx, record.client));
+    }
+    if transaction.currency != record.currency {
+        return Err(Errors::CurrencyMismatch(record.tx, record.currency.clone()));
+    }
+    Ok(transaction)
+}
+
+//a reused tx id means a different upstream bug depending on whether the same client reused it,
+//both of the incoming and the existing operations are given as (client, operation type)
+fn duplicate_transaction(
+    tx: u32,
+    (client, incoming): (u16, OperationType),
+    (owner, existing): (u16, OperationType),
+) -> Errors {
+    if owner == client {
+        Errors::TransactionIdAlreadyUsed {
+            tx,
+            incoming,
+            existing,
+        }
+    } else {
+        Errors::TransactionIdUsedByAnotherClient(tx, client, owner)
+    }
+}
+
+fn mark_applied(transactions: &mut Transactions, tx: u32) {
+    if let Some(transaction) = transactions.get_mut(&tx) {
+        transaction.applied = true;
+    }
+}
+
+//the outcome of a dropped dispute/resolve/chargeback, whose transaction couldn't be referenced
+fn not_referenced(error: &Errors) -> ParseOutcome {
+    match error {
+        //the account of the client in the record's currency doesn't own the transaction either
+        Errors::ClientMismatch(..) | Errors::CurrencyMismatch(..) => {
+            ParseOutcome::IgnoredClientMismatch
+        }
+        _ => ParseOutcome::IgnoredNoSuchTx,
+    }
+}
+
+//a transaction that isn't under dispute either was never disputed, or its dispute is already settled
+fn not_under_dispute(transaction: &TransactionRecord, tx: u32) -> Errors {
+    if transaction.already_disputed {
+        Errors::TransactionAlreadySettled(tx)
+    } else {
+        Errors::TransactionNotDisputable(tx)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rust_decimal_macros::dec;
+    use std::collections::BTreeSet;
+
+    #[test]
+    fn test_dispute_on_non_existing_transaction_has_no_effets() {
+        let mut manager = TransactionManager::new();
+        let records: Vec<Record> = vec![
+            Record::new(OperationType::Deposit, 1, 1, Some(dec!(12.5).into())),
+            Record::new(OperationType::Dispute, 1, 100, None),
+        ];
+
+        assert!(records.into_iter().all(|r| manager.parse_entry(&r).is_ok()));
+
+        assert_eq!(manager.accounts.get(&1).unwrap().available(), dec!(12.5));
+        assert_eq!(manager.accounts.get(&1).unwrap().held(), dec!(0.0));
+    }
+
+    #[test]
+    fn test_dispute_on_locked_account_shall_be_be_completed() {
+        let mut manager = TransactionManager::new();
+        let records: Vec<Record> = vec![
+            Record::new(OperationType::Deposit, 1, 1, Some(dec!(12).into())),
+            Record::new(OperationType::Deposit, 1, 2, Some(dec!(5).into())),
+            Record::new(OperationType::Dispute, 1, 1, None),
+            Record::new(OperationType::Chargeback, 1, 1, None),
+            Record::new(OperationType::Dispute, 1, 2, None),
+            Record::new(OperationType::Chargeback, 1, 2, None),
+        ];
+
+        assert!(records.into_iter().all(|r| manager.parse_entry(&r).is_ok()));
+
+        assert_eq!(manager.accounts.get(&1).unwrap().held(), dec!(0));
+        assert_eq!(manager.accounts.get(&1).unwrap().available(), dec!(0));
+    }
+
+    #[test]
+    fn test_closeout_balances_every_account_to_zero_available_funds() {
+        let mut manager = TransactionManager::new();
+        manager.set_credit_limit(dec!(5)).unwrap();
+        let records: Vec<Record> = vec![
+            Record::new(OperationType::Deposit, 2, 1, Some(dec!(10).into())),
+            Record::new(OperationType::Deposit, 1, 2, Some(dec!(4).into())),
+            Record::new(OperationType::Withdrawal, 1, 3, Some(dec!(6.5).into())),
+            Record::new(OperationType::Deposit, 3, 4, Some(dec!(3).into())),
+            Record::new(OperationType::Dispute, 3, 4, None),
+            Record::new(OperationType::Deposit, 4, 5, Some(dec!(1).into())),
+            Record::new(OperationType::Dispute, 4, 5, None),
+            Record::new(OperationType::Chargeback, 4, 5, None),
+        ];
+        assert!(records.into_iter().all(|r| manager.parse_entry(&r).is_ok()));
+        let closeout = |records: &[Record]| -> Vec<(OperationType, u16, u32, Decimal)> {
+            records
+                .iter()
+                .map(|r| (r.r#type, r.client, r.tx, *r.amount.unwrap()))
+                .collect()
+        };
+
+        let records = manager.closeout(false).unwrap();
+
+        assert_eq!(
+            closeout(&records),
+            vec![
+                (OperationType::Deposit, 1, 6, dec!(2.5)),
+                (OperationType::Withdrawal, 2, 7, dec!(10)),
+            ]
+        );
+        assert_eq!(manager.accounts.get(&2).unwrap().available(), dec!(10));
+
+        let applied = manager.closeout(true).unwrap();
+
+        assert_eq!(closeout(&applied), closeout(&records));
+        assert!(manager.accounts().all(|acc| acc.available().is_zero()));
+        assert_eq!(manager.accounts.get(&3).unwrap().held(), dec!(3));
+        assert!(!manager.accounts.get(&2).unwrap().is_locked());
+        assert!(manager.transactions.contains_key(&7));
+        assert_eq!(manager.checkpoint(), 7);
+        assert!(manager.reconcile().is_ok());
+        assert!(manager.closeout(false).unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_holds_on_locked_account_are_still_settled() {
+        let mut manager = TransactionManager::new();
+        let records: Vec<Record> = vec![
+            Record::new(OperationType::Deposit, 1, 1, Some(dec!(3).into())),
+            Record::new(OperationType::Deposit, 1, 2, Some(dec!(5).into())),
+            Record::new(OperationType::Deposit, 1, 5, Some(dec!(10).into())),
+            Record::new(OperationType::Withdrawal, 1, 3, Some(dec!(2).into())),
+            Record::new(OperationType::Dispute, 1, 2, None),
+            Record::new(OperationType::Dispute, 1, 3, None),
+            Record::new(OperationType::Dispute, 1, 1, None),
+            Record::new(OperationType::Chargeback, 1, 1, None),
+        ];
+        assert!(records.into_iter().all(|r| manager.parse_entry(&r).is_ok()));
+        assert!(manager.accounts.get(&1).unwrap().is_locked());
+
+        assert!(manager
+            .parse_entry(&Record::new(OperationType::Resolve, 1, 2, None))
+            .is_ok());
+        assert!(manager
+            .parse_entry(&Record::new(OperationType::Chargeback, 1, 3, None))
+            .is_ok());
+        assert!(matches!(
+            manager.parse_entry(&Record::new(
+                OperationType::Withdrawal,
+                1,
+                4,
+                Some(dec!(1).into())
+            )),
+            Err(Errors::AccountLocked(1))
+        ));
+        let account = manager.accounts.get(&1).unwrap();
+        assert_eq!(account.available(), dec!(15));
+        assert_eq!(account.held(), dec!(0));
+    }
+
+    #[test]
+    fn test_chargeback_for_operation_that_was_not_under_dispute_shall_have_no_effect() {
+        let mut manager = TransactionManager::new();
+        let records: Vec<Record> = vec![
+            Record::new(OperationType::Deposit, 1, 1, Some(dec!(100.0).into())),
+            Record::new(OperationType::Deposit, 1, 2, Some(dec!(20.0).into())),
+            Record::new(OperationType::Deposit, 1, 3, Some(dec!(15.0).into())),
+            Record::new(OperationType::Dispute, 1, 3, None), //blocks 15.0, available 120
+            Record::new(OperationType::Chargeback, 1, 2, None), //#2 wasn't under dispute, no effect
+        ];
+
+        assert!(records.into_iter().all(|r| manager.parse_entry(&r).is_ok()));
+
+        assert_eq!(manager.accounts.get(&1).unwrap().held(), dec!(15.0));
+        assert_eq!(manager.accounts.get(&1).unwrap().available(), dec!(120.0));
+    }
+
+    #[test]
+    fn test_within_multiple_dispute_and_chargebacks_only_first_shall_affect_account_balance() {
+        let mut manager = TransactionManager::new();
+        let records: Vec<Record> = vec![
+            Record::new(OperationType::Deposit, 1, 1, Some(dec!(100.0).into())),
+            Record::new(OperationType::Deposit, 1, 2, Some(dec!(20.0).into())),
+            Record::new(OperationType::Dispute, 1, 2, None),
+            Record::new(OperationType::Chargeback, 1, 2, None),
+            Record::new(OperationType::Dispute, 1, 2, None),
+            Record::new(OperationType::Chargeback, 1, 2, None),
+            Record::new(OperationType::Dispute, 1, 2, None),
+            Record::new(OperationType::Chargeback, 1, 2, None),
+        ];
+
+        assert!(records.into_iter().all(|r| manager.parse_entry(&r).is_ok()));
+
+        assert_eq!(manager.accounts.get(&1).unwrap().held(), dec!(0.0));
+        assert!(manager.accounts.get(&1).unwrap().is_locked());
+        assert_eq!(manager.accounts.get(&1).unwrap().available(), dec!(100.0));
+    }
+
+    #[test]
+    fn test_resolve_for_operation_that_is_not_under_dispute_shall_have_no_effect() {
+        let mut manager = TransactionManager::new();
+        let records: Vec<Record> = vec![
+            Record::new(OperationType::Deposit, 1, 1, Some(dec!(1.234).into())),
+            Record::new(OperationType::Resolve, 1, 1, None),
+        ];
+
+        assert!(records.into_iter().all(|r| manager.parse_entry(&r).is_ok()));
+
+        assert_eq!(manager.accounts.get(&1).unwrap().held(), dec!(0.0));
+        assert_eq!(manager.accounts.get(&1).unwrap().available(), dec!(1.234));
+    }
+
+    #[test]
+    fn test_chargeback_for_operation_that_is_not_under_dispute_shall_have_no_effect() {
+        let mut manager = TransactionManager::new();
+        let records: Vec<Record> = vec![
+            Record::new(OperationType::Deposit, 1, 1, Some(dec!(1.234).into())),
+            Record::new(OperationType::Chargeback, 1, 1, None),
+        ];
+
+        assert!(records.into_iter().all(|r| manager.parse_entry(&r).is_ok()));
+
+        assert_eq!(manager.accounts.get(&1).unwrap().held(), dec!(0.0));
+        assert!(!manager.accounts.get(&1).unwrap().is_locked());
+        assert_eq!(manager.accounts.get(&1).unwrap().available(), dec!(1.234));
+    }
+
+    #[test]
+    fn test_resolve_for_operation_that_was_already_chargedbacked_shall_have_no_effect() {
+        let mut manager = TransactionManager::new();
+        let records: Vec<Record> = vec![
+            Record::new(OperationType::Deposit, 1, 1, Some(dec!(0.234).into())),
+            Record::new(OperationType::Deposit, 1, 2, Some(dec!(1.0).into())),
+            Record::new(OperationType::Dispute, 1, 2, None),
+            Record::new(OperationType::Chargeback, 1, 2, None),
+            Record::new(OperationType::Resolve, 1, 2, None),
+        ];
+
+        assert!(records.into_iter().all(|r| manager.parse_entry(&r).is_ok()));
+
+        assert_eq!(manager.accounts.get(&1).unwrap().held(), dec!(0.0));
+        assert!(manager.accounts.get(&1).unwrap().is_locked());
+        assert_eq!(manager.accounts.get(&1).unwrap().available(), dec!(0.234));
+
+        //reported apart from a resolve of a transaction that was never disputed
+        manager.set_ignored_policy(IgnoredOperationPolicy::Report);
+        assert!(matches!(
+            manager.parse_entry(&Record::new(OperationType::Resolve, 1, 2, None)),
+            Err(Errors::ResolveOnChargedBack(2))
+        ));
+        assert!(matches!(
+            manager.parse_entry(&Record::new(OperationType::Resolve, 1, 1, None)),
+            Err(Errors::TransactionNotDisputable(1))
+        ));
+    }
+
+    #[test]
+    fn test_resolve_without_amount_releases_everything_held() {
+        let mut manager = TransactionManager::new();
+        let records: Vec<Record> = vec![
+            Record::new(OperationType::Deposit, 1, 1, Some(dec!(3).into())),
+            Record::new(OperationType::Dispute, 1, 1, None),
+            Record::new(OperationType::Resolve, 1, 1, None),
+        ];
+        assert!(records.into_iter().all(|r| manager.parse_entry(&r).is_ok()));
+
+        assert_eq!(manager.accounts.get(&1).unwrap().available(), dec!(3));
+        assert_eq!(manager.accounts.get(&1).unwrap().held(), dec!(0));
+        assert_eq!(
+            manager.client_transactions(1)[0].state,
+            DisputeState::Resolved
+        );
+    }
+
+    #[test]
+    fn test_dispute_of_already_withdrawn_deposit_is_held_anyway() {
+        let mut manager = reporting_manager();
+        let records: Vec<Record> = vec![
+            Record::new(OperationType::Deposit, 1, 1, Some(dec!(100).into())),
+            Record::new(OperationType::Withdrawal, 1, 2, Some(dec!(100).into())),
+            Record::new(OperationType::Dispute, 1, 1, None),
+        ];
+        assert!(records.into_iter().all(|r| manager.parse_entry(&r).is_ok()));
+
+        let account = manager.account(1).unwrap();
+        assert_eq!(account.available(), dec!(-100));
+        assert_eq!(account.held(), dec!(100));
+        assert_eq!(
+            manager.client_transactions(1)[0].state,
+            DisputeState::Disputed
+        );
+    }
+
+    #[test]
+    fn test_chargeback_after_partial_resolve_drops_only_the_remaining_held_funds() {
+        let mut manager = reporting_manager();
+        let records: Vec<Record> = vec![
+            Record::new(OperationType::Deposit, 1, 1, Some(dec!(10).into())),
+            Record::new(OperationType::Deposit, 1, 2, Some(dec!(5).into())),
+            Record::new(OperationType::Dispute, 1, 1, None),
+            Record::new(OperationType::Dispute, 1, 2, None),
+            Record::new(OperationType::Resolve, 1, 1, Some(dec!(4).into())),
+            Record::new(OperationType::Chargeback, 1, 1, None),
+        ];
+        assert!(records.into_iter().all(|r| manager.parse_entry(&r).is_ok()));
+
+        let account = manager.accounts.get(&1).unwrap();
+        assert_eq!(account.available(), dec!(4));
+        assert_eq!(account.held(), dec!(5));
+        assert!(account.is_locked());
+        assert_eq!(
+            manager.client_transactions(1)[0].state,
+            DisputeState::ChargedBack
+        );
+    }
+
+    #[test]
+    fn test_partial_resolve_keeps_the_rest_held_and_disputed() {
+        let mut manager = reporting_manager();
+        let records: Vec<Record> = vec![
+            Record::new(OperationType::Deposit, 1, 1, Some(dec!(10).into())),
+            Record::new(OperationType::Dispute, 1, 1, None),
+            Record::new(OperationType::Resolve, 1, 1, Some(dec!(4).into())),
+        ];
+        assert!(records.into_iter().all(|r| manager.parse_entry(&r).is_ok()));
+        assert_eq!(manager.accounts.get(&1).unwrap().available(), dec!(4));
+        assert_eq!(manager.accounts.get(&1).unwrap().held(), dec!(6));
+        assert_eq!(
+            manager.client_transactions(1)[0].state,
+            DisputeState::Disputed
+        );
+
+        assert!(matches!(
+            manager.parse_entry(&Record::new(
+                OperationType::Resolve,
+                1,
+                1,
+                Some(dec!(6.5).into())
+            )),
+            Err(Errors::ResolveExceedsHeld(1, _))
+        ));
+        assert!(matches!(
+            manager.parse_entry(&Record::new(
+                OperationType::Resolve,
+                1,
+                1,
+                Some(dec!(-1).into())
+            )),
+            Err(Errors::NegativeAmount(_))
+        ));
+        assert_eq!(manager.accounts.get(&1).unwrap().held(), dec!(6));
+
+        assert!(manager
+            .parse_entry(&Record::new(
+                OperationType::Resolve,
+                1,
+                1,
+                Some(dec!(6).into())
+            ))
+            .is_ok());
+        assert_eq!(manager.accounts.get(&1).unwrap().available(), dec!(10));
+        assert_eq!(manager.accounts.get(&1).unwrap().held(), dec!(0));
+        assert_eq!(
+            manager.client_transactions(1)[0].state,
+            DisputeState::Resolved
+        );
+    }
+
+    #[test]
+    fn test_resolve_on_non_existing_transaction_has_no_effets() {
+        let mut manager = TransactionManager::new();
+        let records: Vec<Record> = vec![
+            Record::new(OperationType::Deposit, 1, 1, Some(dec!(1).into())),
+            Record::new(OperationType::Resolve, 1, 2, None),
+        ];
+
+        assert!(records.into_iter().all(|r| manager.parse_entry(&r).is_ok()));
+
+        assert_eq!(manager.accounts.get(&1).unwrap().available(), dec!(1));
+        assert_eq!(manager.accounts.get(&1).unwrap().held(), dec!(0.0));
+    }
+
+    #[test]
+    fn test_chargeback_on_non_existing_transaction_has_no_effets() {
+        let mut manager = TransactionManager::new();
+        let records: Vec<Record> = vec![
+            Record::new(OperationType::Deposit, 1, 1, Some(dec!(2).into())),
+            Record::new(OperationType::Chargeback, 1, 3, None),
+        ];
+
+        assert!(records.into_iter().all(|r| manager.parse_entry(&r).is_ok()));
+
+        assert_eq!(manager.accounts.get(&1).unwrap().available(), dec!(2));
+        assert_eq!(manager.accounts.get(&1).unwrap().held(), dec!(0.0));
+    }
+
+    #[test]
+    fn test_transaction_with_the_same_id_shall_be_rejected_and_error_shall_be_reported() {
+        let mut manager = TransactionManager::new();
+        let records: Vec<Record> = vec![
+            Record::new(OperationType::Deposit, 1, 1, Some(dec!(2).into())),
+            Record::new(OperationType::Deposit, 1, 1, Some(dec!(1).into())),
+        ];
+
+        assert!(manager.parse_entry(&records[0]).is_ok());
+        assert!(matches!(
+            manager.parse_entry(&records[1]),
+            Err(Errors::TransactionIdAlreadyUsed {
+                tx: 1,
+                incoming: OperationType::Deposit,
+                existing: OperationType::Deposit
+            })
+        ));
+
+        assert_eq!(manager.transactions.len(), 1);
+        let _expected_transaction =
+            TransactionRecord::new(1, OperationType::Deposit, Some(dec!(2).into()), 1);
+        assert!(matches!(
+            manager.transactions.get(&1).unwrap(),
+            _expected_transaction
+        ));
+    }
+
+    #[test]
+    fn test_client_holds_a_separate_account_per_currency() {
+        let mut manager = TransactionManager::new();
+        let records: Vec<Record> = vec![
+            Record::new(OperationType::Deposit, 1, 1, Some(dec!(10).into())).with_currency("USD"),
+            Record::new(OperationType::Deposit, 1, 2, Some(dec!(2).into())).with_currency("BTC"),
+            Record::new(OperationType::Withdrawal, 1, 3, Some(dec!(3).into())).with_currency("BTC"),
+            Record::new(OperationType::Withdrawal, 1, 4, Some(dec!(3).into())).with_currency("USD"),
+        ];
+
+        assert!(manager.parse_entry(&records[0]).is_ok());
+        assert!(manager.parse_entry(&records[1]).is_ok());
+        assert!(matches!(
+            manager.parse_entry(&records[2]),
+            Err(Errors::InsuficientFunds(1))
+        ));
+        assert!(manager.parse_entry(&records[3]).is_ok());
+
+        assert_eq!(manager.account_count(), 2);
+        assert!(manager.account(1).is_none());
+        let usd = manager.currency_account(1, "USD").unwrap();
+        assert_eq!((usd.available(), usd.currency()), (dec!(7), Some("USD")));
+        let btc = manager.currency_account(1, "BTC").unwrap();
+        assert_eq!((btc.available(), btc.currency()), (dec!(2), Some("BTC")));
+        assert!(manager.reconcile().is_ok());
+    }
+
+    #[test]
+    fn test_dispute_has_to_match_the_currency_of_the_transaction() {
+        let mut manager = TransactionManager::new();
+        let deposits: Vec<Record> = vec![
+            Record::new(OperationType::Deposit, 1, 1, Some(dec!(10).into())).with_currency("USD"),
+            Record::new(OperationType::Deposit, 1, 2, Some(dec!(2).into())).with_currency("BTC"),
+        ];
+        assert!(deposits.iter().all(|r| manager.parse_entry(r).is_ok()));
+
+        let wrong_currency = Record::new(OperationType::Dispute, 1, 2, None).with_currency("USD");
+        let no_currency = Record::new(OperationType::Dispute, 1, 2, None);
+        assert!(matches!(
+            manager.parse_entry(&wrong_currency),
+            Ok(ParseOutcome::IgnoredClientMismatch)
+        ));
+        assert!(matches!(
+            manager.parse_entry(&no_currency),
+            Ok(ParseOutcome::IgnoredClientMismatch)
+        ));
+        manager.set_ignored_policy(IgnoredOperationPolicy::Report);
+        assert!(matches!(
+            manager.parse_entry(&wrong_currency),
+            Err(Errors::CurrencyMismatch(2, Some(currency))) if currency == "USD"
+        ));
+
+        let dispute = Record::new(OperationType::Dispute, 1, 2, None).with_currency("BTC");
+        let chargeback = Record::new(OperationType::Chargeback, 1, 2, None).with_currency("BTC");
+        assert!(manager.parse_entry(&dispute).is_ok());
+        assert!(manager.parse_entry(&chargeback).is_ok());
+
+        let btc = manager.currency_account(1, "BTC").unwrap();
+        assert_eq!((btc.available(), btc.held()), (dec!(0), dec!(0)));
+        assert!(btc.is_locked());
+        let usd = manager.currency_account(1, "USD").unwrap();
+        assert_eq!((usd.available(), usd.held()), (dec!(10), dec!(0)));
+        assert!(!usd.is_locked());
+    }
+
+    #[test]
+    fn test_transaction_id_reused_by_another_client_is_reported_with_both_clients() {
+        let mut manager = TransactionManager::new();
+        let records: Vec<Record> = vec![
+            Record::new(OperationType::Deposit, 1, 1, Some(dec!(2).into())),
+            Record::new(OperationType::Withdrawal, 2, 1, Some(dec!(1).into())),
+        ];
+
+        assert!(manager.parse_entry(&records[0]).is_ok());
+        assert!(matches!(
+            manager.parse_entry(&records[1]),
+            Err(Errors::TransactionIdUsedByAnotherClient(1, 2, 1))
+        ));
+        assert_eq!(manager.transactions.get(&1).unwrap().client, 1);
+    }
+
+    #[test]
+    fn test_withdrawal_reusing_a_deposit_tx_id_reports_both_operations() {
+        let mut manager = TransactionManager::new();
+        let records: Vec<Record> = vec![
+            Record::new(OperationType::Deposit, 1, 1, Some(dec!(2).into())),
+            Record::new(OperationType::Withdrawal, 1, 1, Some(dec!(1).into())),
+        ];
+
+        assert!(manager.parse_entry(&records[0]).is_ok());
+        let err = manager.parse_entry(&records[1]).unwrap_err();
+        assert!(matches!(
+            err,
+            Errors::TransactionIdAlreadyUsed {
+                tx: 1,
+                incoming: OperationType::Withdrawal,
+                existing: OperationType::Deposit
+            }
+        ));
+        assert_eq!(
+            err.to_string(),
+            "Transaction ID 1 of a withdrawal already taken by a deposit!"
+        );
+    }
+
+    fn reporting_manager() -> TransactionManager {
+        let mut manager = TransactionManager::new();
+        manager.set_ignored_policy(IgnoredOperationPolicy::Report);
+        manager
+    }
+
+    #[test]
+    fn test_dispute_on_non_existing_transaction_is_reported_as_unknown_transaction() {
+        let mut manager = reporting_manager();
+        assert!(manager
+            .parse_entry(&Record::new(
+                OperationType::Deposit,
+                1,
+                1,
+                Some(dec!(1).into())
+            ))
+            .is_ok());
+
+        assert!(matches!(
+            manager.parse_entry(&Record::new(OperationType::Dispute, 1, 2, None)),
+            Err(Errors::UnknownTransaction(2))
+        ));
+        assert!(matches!(
+            manager.parse_entry(&Record::new(OperationType::Resolve, 1, 2, None)),
+            Err(Errors::UnknownTransaction(2))
+        ));
+        assert!(matches!(
+            manager.parse_entry(&Record::new(OperationType::Chargeback, 1, 2, None)),
+            Err(Errors::UnknownTransaction(2))
+        ));
+    }
+
+    #[test]
+    fn test_dispute_on_transaction_of_another_client_is_reported_as_client_mismatch() {
+        let mut manager = reporting_manager();
+        assert!(manager
+            .parse_entry(&Record::new(
+                OperationType::Deposit,
+                1,
+                1,
+                Some(dec!(5).into())
+            ))
+            .is_ok());
+
+        assert!(matches!(
+            manager.parse_entry(&Record::new(OperationType::Dispute, 2, 1, None)),
+            Err(Errors::ClientMismatch(1, 2))
+        ));
+        assert_eq!(manager.accounts.get(&1).unwrap().available(), dec!(5));
+        assert_eq!(manager.accounts.get(&1).unwrap().held(), dec!(0));
+        assert!(!manager.accounts.contains_key(&2));
+    }
+
+    #[test]
+    fn test_operations_on_transaction_not_under_dispute_are_reported_as_not_disputable() {
+        let mut manager = reporting_manager();
+        let records: Vec<Record> = vec![
+            Record::new(OperationType::Deposit, 1, 1, Some(dec!(5).into())),
+            Record::new(OperationType::Deposit, 1, 2, None),
+        ];
+        assert!(records.into_iter().all(|r| manager.parse_entry(&r).is_ok()));
+
+        assert!(matches!(
+            manager.parse_entry(&Record::new(OperationType::Resolve, 1, 1, None)),
+            Err(Errors::TransactionNotDisputable(1))
+        ));
+        assert!(matches!(
+            manager.parse_entry(&Record::new(OperationType::Chargeback, 1, 1, None)),
+            Err(Errors::TransactionNotDisputable(1))
+        ));
+        assert!(matches!(
+            manager.parse_entry(&Record::new(OperationType::Dispute, 1, 2, None)),
+            Err(Errors::TransactionNotDisputable(2))
+        ));
+        assert!(manager
+            .parse_entry(&Record::new(OperationType::Dispute, 1, 1, None))
+            .is_ok());
+        assert!(matches!(
+            manager.parse_entry(&Record::new(OperationType::Dispute, 1, 1, None)),
+            Err(Errors::TransactionNotDisputable(1))
+        ));
+    }
+
+    #[test]
+    fn test_operations_on_settled_transaction_are_reported_as_already_settled() {
+        let mut manager = reporting_manager();
+        let records: Vec<Record> = vec![
+            Record::new(OperationType::Deposit, 1, 1, Some(dec!(5).into())),
+            Record::new(OperationType::Dispute, 1, 1, None),
+            Record::new(OperationType::Chargeback, 1, 1, None),
+        ];
+        assert!(records.into_iter().all(|r| manager.parse_entry(&r).is_ok()));
+
+        assert!(matches!(
+            manager.parse_entry(&Record::new(OperationType::Dispute, 1, 1, None)),
+            Err(Errors::TransactionAlreadySettled(1))
+        ));
+        assert!(matches!(
+            manager.parse_entry(&Record::new(OperationType::Resolve, 1, 1, None)),
+            Err(Errors::ResolveOnChargedBack(1))
+        ));
+        assert!(matches!(
+            manager.parse_entry(&Record::new(OperationType::Chargeback, 1, 1, None)),
+            Err(Errors::TransactionAlreadySettled(1))
+        ));
+    }
+
+    #[test]
+    fn test_resolved_transaction_might_be_disputed_again() {
+        let mut manager = reporting_manager();
+        let records: Vec<Record> = vec![
+            Record::new(OperationType::Deposit, 1, 1, Some(dec!(5).into())),
+            Record::new(OperationType::Deposit, 1, 2, Some(dec!(1).into())),
+            Record::new(OperationType::Dispute, 1, 1, Some(dec!(5).into())),
+            Record::new(OperationType::Resolve, 1, 1, Some(dec!(5).into())),
+            Record::new(OperationType::Dispute, 1, 1, None),
+        ];
+        assert!(records.into_iter().all(|r| manager.parse_entry(&r).is_ok()));
+        assert_eq!(manager.accounts.get(&1).unwrap().available(), dec!(1));
+        assert_eq!(manager.accounts.get(&1).unwrap().held(), dec!(5));
+        assert!(matches!(
+            manager.parse_entry(&Record::new(OperationType::Dispute, 1, 1, None)),
+            Err(Errors::TransactionNotDisputable(1))
+        ));
+
+        assert!(manager
+            .parse_entry(&Record::new(OperationType::Chargeback, 1, 1, None))
+            .is_ok());
+        let account = manager.accounts.get(&1).unwrap();
+        assert_eq!(account.available(), dec!(1));
+        assert_eq!(account.held(), dec!(0));
+        assert!(account.is_locked());
+        assert!(matches!(
+            manager.parse_entry(&Record::new(OperationType::Dispute, 1, 1, None)),
+            Err(Errors::TransactionAlreadySettled(1))
+        ));
+    }
+
+    #[test]
+    fn test_outcome_describes_the_effect_of_each_record() {
+        let mut manager = TransactionManager::new();
+        let mut outcome = |r#type, client, tx, amount: Option<Decimal>| {
+            manager
+                .parse_entry(&Record::new(r#type, client, tx, amount.map(Into::into)))
+                .unwrap()
+        };
+        let applied = |delta_available, delta_held| ParseOutcome::Applied {
+            client: 1,
+            delta_available,
+            delta_held,
+        };
+
+        assert_eq!(
+            outcome(OperationType::Deposit, 1, 1, Some(dec!(10))),
+            applied(dec!(10), dec!(0))
+        );
+        assert_eq!(
+            outcome(OperationType::Deposit, 1, 3, Some(dec!(5))),
+            applied(dec!(5), dec!(0))
+        );
+        assert_eq!(
+            outcome(OperationType::Withdrawal, 1, 2, Some(dec!(3))),
+            applied(dec!(-3), dec!(0))
+        );
+        assert_eq!(
+            outcome(OperationType::Dispute, 1, 1, None),
+            applied(dec!(-10), dec!(10))
+        );
+        assert_eq!(
+            outcome(OperationType::Dispute, 1, 1, None),
+            ParseOutcome::IgnoredAlreadyDisputed
+        );
+        assert_eq!(
+            outcome(OperationType::Resolve, 1, 2, None),
+            ParseOutcome::IgnoredNotDisputed
+        );
+        assert_eq!(
+            outcome(OperationType::Chargeback, 1, 7, None),
+            ParseOutcome::IgnoredNoSuchTx
+        );
+        assert_eq!(
+            outcome(OperationType::Dispute, 2, 1, None),
+            ParseOutcome::IgnoredClientMismatch
+        );
+        assert_eq!(
+            outcome(OperationType::Chargeback, 1, 1, None),
+            ParseOutcome::Locked {
+                client: 1,
+                delta_available: dec!(0),
+                delta_held: dec!(-10),
+            }
+        );
+        assert_eq!(
+            outcome(OperationType::Dispute, 1, 1, None),
+            ParseOutcome::IgnoredNotDisputable
+        );
+    }
+
+    #[test]
+    fn test_ignored_operations_are_silent_by_default() {
+        let mut manager = TransactionManager::new();
+        let records: Vec<Record> = vec![
+            Record::new(OperationType::Deposit, 1, 1, Some(dec!(5).into())),
+            Record::new(OperationType::Dispute, 1, 7, None),
+            Record::new(OperationType::Dispute, 2, 1, None),
+            Record::new(OperationType::Resolve, 1, 1, None),
+        ];
+
+        assert!(records.into_iter().all(|r| manager.parse_entry(&r).is_ok()));
+        assert_eq!(manager.accounts.get(&1).unwrap().available(), dec!(5));
+    }
+
+    #[test]
+    fn test_dispute_on_charged_back_withdrawal_is_reported_as_already_reversed() {
+        let mut manager = TransactionManager::new();
+        manager.set_ignored_policy(IgnoredOperationPolicy::Report);
+        let records: Vec<Record> = vec![
+            Record::new(OperationType::Deposit, 1, 1, Some(dec!(10).into())),
+            Record::new(OperationType::Withdrawal, 1, 2, Some(dec!(4).into())),
+            Record::new(OperationType::Dispute, 1, 2, None),
+            Record::new(OperationType::Chargeback, 1, 2, None),
+        ];
+        assert!(records.into_iter().all(|r| manager.parse_entry(&r).is_ok()));
+        let held = manager.accounts.get(&1).unwrap().held();
+        let available = manager.accounts.get(&1).unwrap().available();
+
+        assert!(matches!(
+            manager.parse_entry(&Record::new(OperationType::Dispute, 1, 2, None)),
+            Err(Errors::TransactionAlreadyReversed(2))
+        ));
+        assert_eq!(manager.accounts.get(&1).unwrap().held(), held);
+        assert_eq!(manager.accounts.get(&1).unwrap().available(), available);
+    }
+
+    #[test]
+    fn test_chargeback_follows_the_path_of_the_disputed_operation() {
+        let mut manager = TransactionManager::new();
+        let records: Vec<Record> = vec![
+            Record::new(OperationType::Deposit, 1, 1, Some(dec!(10).into())),
+            Record::new(OperationType::Withdrawal, 1, 2, Some(dec!(4).into())),
+            Record::new(OperationType::Dispute, 1, 1, None),
+            Record::new(OperationType::Chargeback, 1, 1, None),
+            Record::new(OperationType::Deposit, 2, 3, Some(dec!(10).into())),
+            Record::new(OperationType::Withdrawal, 2, 4, Some(dec!(4).into())),
+            Record::new(OperationType::Dispute, 2, 4, None),
+            Record::new(OperationType::Chargeback, 2, 4, None),
+        ];
+        assert!(records.into_iter().all(|r| manager.parse_entry(&r).is_ok()));
+
+        //the deposit's held funds are dropped and the account locked, nothing gets credited
+        let deposit = manager.accounts.get(&1).unwrap();
+        assert_eq!((deposit.available(), deposit.held()), (dec!(-4), dec!(0)));
+        assert!(deposit.is_locked());
+        //the withdrawn amount is credited back, without a lock
+        let withdrawal = manager.accounts.get(&2).unwrap();
+        assert_eq!(
+            (withdrawal.available(), withdrawal.held()),
+            (dec!(10), dec!(0))
+        );
+        assert!(!withdrawal.is_locked());
+    }
+
+    #[test]
+    fn test_transaction_of_another_operation_type_is_never_settled() {
+        let mut manager = TransactionManager::new();
+        let deposit = Record::new(OperationType::Deposit, 1, 1, Some(dec!(10).into()));
+        assert!(manager.parse_entry(&deposit).is_ok());
+        for (tx, under_dispute) in [(2, true), (3, false)] {
+            let mut transaction =
+                TransactionRecord::new(1, OperationType::Dispute, Some(dec!(5).into()), 0);
+            transaction.applied = true;
+            transaction.under_dispute = under_dispute;
+            transaction.held = if under_dispute { dec!(5) } else { dec!(0) };
+            manager.transactions.insert(tx, transaction);
+        }
+
+        for record in [
+            Record::new(OperationType::Chargeback, 1, 2, None),
+            Record::new(OperationType::Resolve, 1, 2, None),
+            Record::new(OperationType::Dispute, 1, 3, None),
+        ] {
+            assert!(matches!(
+                manager.parse_entry(&record),
+                Err(Errors::UnsupportedOperation(_, OperationType::Dispute))
+            ));
+        }
+        let account = manager.accounts.get(&1).unwrap();
+        assert_eq!((account.available(), account.held()), (dec!(10), dec!(0)));
+        assert!(!account.is_locked());
+        assert!(manager.transactions.get(&2).unwrap().under_dispute);
+        assert!(!manager.transactions.get(&3).unwrap().under_dispute);
+    }
+
+    #[test]
+    fn test_withdrawal_is_charged_back_only_once() {
+        for mode in [WithdrawalDisputeMode::None, WithdrawalDisputeMode::Hold] {
+            let mut manager = TransactionManager::new();
+            manager.set_withdrawal_dispute_mode(mode);
+            manager.set_ignored_policy(IgnoredOperationPolicy::Report);
+            let records: Vec<Record> = vec![
+                Record::new(OperationType::Deposit, 1, 1, Some(dec!(10).into())),
+                Record::new(OperationType::Withdrawal, 1, 2, Some(dec!(4).into())),
+                Record::new(OperationType::Dispute, 1, 2, None),
+                Record::new(OperationType::Chargeback, 1, 2, None),
+            ];
+            assert!(records.into_iter().all(|r| manager.parse_entry(&r).is_ok()));
+            assert_eq!(manager.accounts.get(&1).unwrap().available(), dec!(10));
+
+            assert!(matches!(
+                manager.parse_entry(&Record::new(OperationType::Dispute, 1, 2, None)),
+                Err(Errors::TransactionAlreadyReversed(2))
+            ));
+            assert!(matches!(
+                manager.parse_entry(&Record::new(OperationType::Chargeback, 1, 2, None)),
+                Err(Errors::AlreadyChargedBack(2))
+            ));
+            let account = manager.accounts.get(&1).unwrap();
+            assert_eq!((account.available(), account.held()), (dec!(10), dec!(0)));
+            assert_eq!(account.chargeback_count(), 1);
+            assert!(manager.reconcile().is_ok());
+        }
+    }
+
+    #[test]
+    fn test_further_dispute_and_chargeback_of_charged_back_withdrawal_are_silently_ignored() {
+        let mut manager = TransactionManager::new();
+        let records: Vec<Record> = vec![
+            Record::new(OperationType::Deposit, 1, 1, Some(dec!(10).into())),
+            Record::new(OperationType::Withdrawal, 1, 2, Some(dec!(4).into())),
+            Record::new(OperationType::Dispute, 1, 2, None),
+            Record::new(OperationType::Chargeback, 1, 2, None),
+        ];
+        assert!(records.into_iter().all(|r| manager.parse_entry(&r).is_ok()));
+
+        assert!(matches!(
+            manager.parse_entry(&Record::new(OperationType::Dispute, 1, 2, None)),
+            Ok(ParseOutcome::IgnoredNotDisputable)
+        ));
+        assert!(matches!(
+            manager.parse_entry(&Record::new(OperationType::Chargeback, 1, 2, None)),
+            Ok(ParseOutcome::IgnoredNotDisputed)
+        ));
+        let account = manager.accounts.get(&1).unwrap();
+        assert_eq!((account.available(), account.held()), (dec!(10), dec!(0)));
+        assert_eq!(account.chargeback_count(), 1);
+        assert_eq!(manager.failures(), FailureCounts::default());
+    }
+
+    #[test]
+    fn test_resolved_withdrawal_dispute_leaves_funds_untouched() {
+        let mut manager = TransactionManager::new();
+        let records: Vec<Record> = vec![
+            Record::new(OperationType::Deposit, 1, 1, Some(dec!(10).into())),
+            Record::new(OperationType::Withdrawal, 1, 2, Some(dec!(4).into())),
+            Record::new(OperationType::Dispute, 1, 2, None),
+        ];
+        assert!(records.into_iter().all(|r| manager.parse_entry(&r).is_ok()));
+        assert_eq!(manager.accounts.get(&1).unwrap().available(), dec!(6));
+        assert_eq!(manager.accounts.get(&1).unwrap().held(), dec!(0));
+
+        assert!(manager
+            .parse_entry(&Record::new(OperationType::Resolve, 1, 2, None))
+            .is_ok());
+        let account = manager.accounts.get(&1).unwrap();
+        assert_eq!(account.available(), dec!(6));
+        assert_eq!(account.held(), dec!(0));
+        assert!(!account.is_locked());
+    }
+
+    #[test]
+    fn test_charged_back_withdrawal_is_credited_back() {
+        let mut manager = TransactionManager::new();
+        let records: Vec<Record> = vec![
+            Record::new(OperationType::Deposit, 1, 1, Some(dec!(10).into())),
+            Record::new(OperationType::Withdrawal, 1, 2, Some(dec!(4).into())),
+            Record::new(OperationType::Dispute, 1, 2, None),
+            Record::new(OperationType::Chargeback, 1, 2, None),
+        ];
+        assert!(records.into_iter().all(|r| manager.parse_entry(&r).is_ok()));
+
+        let account = manager.accounts.get(&1).unwrap();
+        assert_eq!(account.available(), dec!(10));
+        assert_eq!(account.held(), dec!(0));
+        assert!(!account.is_locked());
+    }
+
+    #[test]
+    fn test_withdrawal_dispute_modes() {
+        for (mode, disputed_held) in [
+            (WithdrawalDisputeMode::None, dec!(0)),
+            (WithdrawalDisputeMode::Hold, dec!(5)),
+        ] {
+            let mut manager = TransactionManager::new();
+            manager.set_withdrawal_dispute_mode(mode);
+            let records: Vec<Record> = vec![
+                Record::new(OperationType::Deposit, 1, 1, Some(dec!(10).into())),
+                Record::new(OperationType::Withdrawal, 1, 2, Some(dec!(4).into())),
+                Record::new(OperationType::Withdrawal, 1, 3, Some(dec!(1).into())),
+                Record::new(OperationType::Dispute, 1, 2, None),
+                Record::new(OperationType::Dispute, 1, 3, None),
+            ];
+            assert!(records.into_iter().all(|r| manager.parse_entry(&r).is_ok()));
+            let account = manager.accounts.get(&1).unwrap();
+            assert_eq!(account.available(), dec!(5));
+            assert_eq!(account.held(), disputed_held);
+            assert!(manager.reconcile().is_ok());
+
+            let records: Vec<Record> = vec![
+                Record::new(OperationType::Resolve, 1, 3, None),
+                Record::new(OperationType::Chargeback, 1, 2, None),
+            ];
+            assert!(records.into_iter().all(|r| manager.parse_entry(&r).is_ok()));
+            let account = manager.accounts.get(&1).unwrap();
+            assert_eq!(account.available(), dec!(9));
+            assert_eq!(account.held(), dec!(0));
+            assert!(manager.reconcile().is_ok());
+        }
+    }
+
+    #[test]
+    fn test_chargeback_after_partial_resolve_credits_only_the_disputed_rest_of_a_withdrawal() {
+        for mode in [WithdrawalDisputeMode::None, WithdrawalDisputeMode::Hold] {
+            let mut manager = reporting_manager();
+            manager.set_withdrawal_dispute_mode(mode);
+            let records: Vec<Record> = vec![
+                Record::new(OperationType::Deposit, 1, 1, Some(dec!(10).into())),
+                Record::new(OperationType::Withdrawal, 1, 2, Some(dec!(4).into())),
+                Record::new(OperationType::Dispute, 1, 2, None),
+                Record::new(OperationType::Resolve, 1, 2, Some(dec!(1).into())),
+                Record::new(OperationType::Chargeback, 1, 2, None),
+            ];
+            assert!(records.into_iter().all(|r| manager.parse_entry(&r).is_ok()));
+
+            let account = manager.accounts.get(&1).unwrap();
+            assert_eq!(account.available(), dec!(9), "{:?}", mode);
+            assert_eq!(account.held(), dec!(0), "{:?}", mode);
+            assert!(manager.reconcile().is_ok());
+        }
+    }
+
+    #[test]
+    fn test_rejected_held_withdrawal_chargeback_keeps_the_dispute() {
+        let mut manager = reporting_manager();
+        manager.set_withdrawal_dispute_mode(WithdrawalDisputeMode::Hold);
+        let deposit = Record::new(OperationType::Deposit, 1, 1, Some(Decimal::MAX.into()));
+        let records: Vec<Record> = vec![
+            deposit,
+            Record::new(OperationType::Withdrawal, 1, 2, Some(dec!(4).into())),
+            Record::new(OperationType::Dispute, 1, 2, None),
+            Record::new(OperationType::Deposit, 1, 3, Some(dec!(4).into())),
+        ];
+        assert!(records.into_iter().all(|r| manager.parse_entry(&r).is_ok()));
+
+        //available is back at the maximum, crediting the held amount to it overflows
+        assert!(matches!(
+            manager.parse_entry(&Record::new(OperationType::Chargeback, 1, 2, None)),
+            Err(Errors::FundsOverflow(1))
+        ));
+        let account = manager.accounts.get(&1).unwrap();
+        assert_eq!(account.available(), Decimal::MAX);
+        assert_eq!(account.held(), dec!(4));
+        assert_eq!(
+            manager.client_transactions(1)[1].state,
+            DisputeState::Disputed
+        );
+
+        //the moved funds don't change the total, a cap lowered since the dispute doesn't apply
+        let mut capped = reporting_manager();
+        capped.set_withdrawal_dispute_mode(WithdrawalDisputeMode::Hold);
+        let records: Vec<Record> = vec![
+            Record::new(OperationType::Deposit, 1, 1, Some(dec!(10).into())),
+            Record::new(OperationType::Withdrawal, 1, 2, Some(dec!(4).into())),
+            Record::new(OperationType::Dispute, 1, 2, None),
+        ];
+        assert!(records.into_iter().all(|r| capped.parse_entry(&r).is_ok()));
+        assert!(capped.set_max_balance(dec!(8)).is_ok());
+        assert!(capped
+            .parse_entry(&Record::new(OperationType::Chargeback, 1, 2, None))
+            .is_ok());
+        let account = capped.accounts.get(&1).unwrap();
+        assert_eq!((account.available(), account.held()), (dec!(10), dec!(0)));
+    }
+
+    #[test]
+    fn test_charged_back_withdrawal_locks_account_on_request() {
+        let locks = Arc::new(Mutex::new(Vec::new()));
+        let mut manager = TransactionManager::new();
+        manager.set_lock_on_withdrawal_chargeback(true);
+        let listener_locks = Arc::clone(&locks);
+        manager.set_on_lock(move |client, tx| listener_locks.lock().unwrap().push((client, tx)));
+        let records: Vec<Record> = vec![
+            Record::new(OperationType::Deposit, 1, 1, Some(dec!(10).into())),
+            Record::new(OperationType::Withdrawal, 1, 2, Some(dec!(4).into())),
+            Record::new(OperationType::Dispute, 1, 2, None),
+        ];
+        assert!(records.into_iter().all(|r| manager.parse_entry(&r).is_ok()));
+
+        assert!(matches!(
+            manager.parse_entry(&Record::new(OperationType::Chargeback, 1, 2, None)),
+            Ok(ParseOutcome::Locked { client: 1, .. })
+        ));
+        let account = manager.accounts.get(&1).unwrap();
+        assert_eq!(account.available(), dec!(10));
+        assert!(account.is_locked());
+        assert_eq!(*locks.lock().unwrap(), vec![(1, 2)]);
+    }
+
+    #[test]
+    fn test_withdrawal_chargeback_of_another_client_credits_nothing() {
+        let mut manager = reporting_manager();
+        let records: Vec<Record> = vec![
+            Record::new(OperationType::Deposit, 1, 1, Some(dec!(10).into())),
+            Record::new(OperationType::Withdrawal, 1, 2, Some(dec!(4).into())),
+            Record::new(OperationType::Deposit, 2, 3, Some(dec!(1).into())),
+            Record::new(OperationType::Dispute, 1, 2, None),
+        ];
+        assert!(records.into_iter().all(|r| manager.parse_entry(&r).is_ok()));
+
+        assert!(matches!(
+            manager.parse_entry(&Record::new(OperationType::Chargeback, 2, 2, None)),
+            Err(Errors::ClientMismatch(2, 2))
+        ));
+        assert_eq!(manager.accounts.get(&1).unwrap().available(), dec!(6));
+        assert_eq!(manager.accounts.get(&2).unwrap().available(), dec!(1));
+    }
+
+    #[test]
+    fn test_deposit_chargeback_is_not_credited_as_a_withdrawal_one() {
+        let mut manager = reporting_manager();
+        let records: Vec<Record> = vec![
+            Record::new(OperationType::Deposit, 1, 1, Some(dec!(10).into())),
+            Record::new(OperationType::Dispute, 1, 1, None),
+            Record::new(OperationType::Chargeback, 1, 1, None),
+        ];
+        assert!(records.into_iter().all(|r| manager.parse_entry(&r).is_ok()));
+
+        let account = manager.accounts.get(&1).unwrap();
+        assert_eq!(account.available(), dec!(0));
+        assert_eq!(account.held(), dec!(0));
+    }
+
+    #[test]
+    fn test_rejected_withdrawal_cant_be_charged_back() {
+        let mut manager = reporting_manager();
+        assert!(manager
+            .parse_entry(&Record::new(
+                OperationType::Deposit,
+                1,
+                1,
+                Some(dec!(10).into())
+            ))
+            .is_ok());
+        assert!(matches!(
+            manager.parse_entry(&Record::new(
+                OperationType::Withdrawal,
+                1,
+                2,
+                Some(dec!(50).into())
+            )),
+            Err(Errors::InsuficientFunds(1))
+        ));
+
+        assert!(matches!(
+            manager.parse_entry(&Record::new(OperationType::Dispute, 1, 2, None)),
+            Err(Errors::TransactionNotDisputable(2))
+        ));
+        assert!(matches!(
+            manager.parse_entry(&Record::new(OperationType::Chargeback, 1, 2, None)),
+            Err(Errors::TransactionNotDisputable(2))
+        ));
+        assert_eq!(manager.accounts.get(&1).unwrap().available(), dec!(10));
+    }
+
+    #[test]
+    fn test_applied_disputes_and_chargebacks_are_counted_per_account() {
+        let mut manager = TransactionManager::new();
+        let records: Vec<Record> = vec![
+            Record::new(OperationType::Deposit, 1, 1, Some(dec!(10).into())),
+            Record::new(OperationType::Deposit, 1, 2, Some(dec!(5).into())),
+            Record::new(OperationType::Withdrawal, 1, 3, Some(dec!(2).into())),
+            Record::new(OperationType::Deposit, 2, 4, Some(dec!(1).into())),
+            Record::new(OperationType::Dispute, 1, 1, None),
+            Record::new(OperationType::Resolve, 1, 1, None),
+            //disputed again after the resolve
+            Record::new(OperationType::Dispute, 1, 1, None),
+            //already under dispute, ignored
+            Record::new(OperationType::Dispute, 1, 1, None),
+            Record::new(OperationType::Dispute, 1, 3, None),
+            Record::new(OperationType::Chargeback, 1, 3, None),
+            Record::new(OperationType::Chargeback, 1, 1, None),
+            //of another client, ignored
+            Record::new(OperationType::Dispute, 2, 2, None),
+        ];
+        assert!(records.into_iter().all(|r| manager.parse_entry(&r).is_ok()));
+
+        let account = manager.accounts.get(&1).unwrap();
+        assert_eq!(account.dispute_count(), 3);
+        assert_eq!(account.chargeback_count(), 2);
+        let account = manager.accounts.get(&2).unwrap();
+        assert_eq!(account.dispute_count(), 0);
+        assert_eq!(account.chargeback_count(), 0);
+    }
+
+    #[test]
+    fn test_withdrawal_chargeback_without_dispute_credits_nothing() {
+        let mut manager = reporting_manager();
+        let records: Vec<Record> = vec![
+            Record::new(OperationType::Deposit, 1, 1, Some(dec!(10).into())),
+            Record::new(OperationType::Withdrawal, 1, 2, Some(dec!(4).into())),
+        ];
+        assert!(records.into_iter().all(|r| manager.parse_entry(&r).is_ok()));
+
+        assert!(matches!(
+            manager.parse_entry(&Record::new(OperationType::Chargeback, 1, 2, None)),
+            Err(Errors::TransactionNotDisputable(2))
+        ));
+        assert_eq!(manager.accounts.get(&1).unwrap().available(), dec!(6));
+    }
+
+    fn mixed_records() -> Vec<Record> {
+        vec![
+            Record::new(OperationType::Deposit, 1, 1, Some(dec!(10).into())),
+            Record::new(OperationType::Deposit, 2, 2, Some(dec!(20).into())),
+            Record::new(OperationType::Deposit, 3, 3, Some(dec!(30).into())),
+            Record::new(OperationType::Withdrawal, 1, 4, Some(dec!(4).into())),
+            //tx id already used by another client
+            Record::new(OperationType::Deposit, 4, 2, Some(dec!(1).into())),
+            //transaction of another client
+            Record::new(OperationType::Dispute, 3, 1, None),
+            Record::new(OperationType::Dispute, 2, 2, None),
+            Record::new(OperationType::Chargeback, 2, 2, None),
+            Record::new(OperationType::Withdrawal, 3, 5, Some(dec!(50).into())),
+            Record::new(OperationType::Dispute, 1, 4, None),
+            Record::new(OperationType::Resolve, 1, 4, None),
+            Record::new(OperationType::Deposit, 2, 6, Some(dec!(1).into())),
+        ]
+    }
+
+    fn account_states(manager: &TransactionManager) -> Vec<(u16, Decimal, Decimal, bool)> {
+        let mut states: Vec<_> = manager
+            .accounts
+            .iter()
+            .map(|(key, acc)| (key.client, acc.available(), acc.held(), acc.is_locked()))
+            .collect();
+        states.sort();
+        states
+    }
+
+    #[test]
+    fn test_accounts_reconcile_with_the_transaction_log() {
+        let mut manager = TransactionManager::new();
+        manager.set_withdrawal_fee(WithdrawalFee::Flat(dec!(0.5)));
+        manager.apply_all(mixed_records());
+        manager.apply_all(vec![
+            Record::new(OperationType::Withdrawal, 3, 7, Some(dec!(5).into())),
+            Record::new(OperationType::Dispute, 3, 7, None),
+            Record::new(OperationType::Chargeback, 3, 7, None),
+            Record::new(OperationType::Deposit, 1, 8, Some(dec!(6).into())),
+            Record::new(OperationType::Dispute, 1, 8, None),
+            Record::new(OperationType::Resolve, 1, 8, Some(dec!(2).into())),
+            Record::new(OperationType::Chargeback, 1, 8, None),
+        ]);
+
+        assert!(manager.accounts.get(&1).unwrap().is_locked());
+        assert!(manager.reconcile().is_ok());
+    }
+
+    #[test]
+    fn test_records_timestamped_before_the_last_applied_one_are_rejected() {
+        let at = |time: &str| time.parse::<DateTime<Utc>>().unwrap();
+        let mut manager = TransactionManager::new();
+        manager.set_enforce_order(true);
+        let records: Vec<Record> = vec![
+            Record::new(OperationType::Deposit, 1, 1, Some(dec!(10).into()))
+                .with_timestamp(at("2024-01-01T10:00:00Z")),
+            //records without a timestamp aren't checked
+            Record::new(OperationType::Deposit, 1, 2, Some(dec!(5).into())),
+            Record::new(OperationType::Dispute, 1, 1, None)
+                .with_timestamp(at("2024-01-01T10:00:00Z")),
+        ];
+        assert!(records.into_iter().all(|r| manager.parse_entry(&r).is_ok()));
+
+        assert!(matches!(
+            manager.parse_entry(
+                &Record::new(OperationType::Resolve, 1, 1, None)
+                    .with_timestamp(at("2024-01-01T09:59:59Z"))
+            ),
+            Err(Errors::OutOfOrder(1, _, _))
+        ));
+        assert_eq!(manager.accounts.get(&1).unwrap().held(), dec!(10));
+
+        manager.set_enforce_order(false);
+        assert!(manager
+            .parse_entry(
+                &Record::new(OperationType::Resolve, 1, 1, None)
+                    .with_timestamp(at("2024-01-01T09:59:59Z"))
+            )
+            .is_ok());
+        assert_eq!(manager.accounts.get(&1).unwrap().held(), dec!(0));
+    }
+
+    #[test]
+    fn test_settled_transactions_are_pruned() {
+        let mut manager = TransactionManager::new();
+        manager.set_prune_after(2);
+        let records: Vec<Record> = vec![
+            Record::new(OperationType::Deposit, 1, 1, Some(dec!(10).into())),
+            Record::new(OperationType::Deposit, 2, 2, Some(dec!(5).into())),
+            Record::new(OperationType::Dispute, 2, 2, None),
+            Record::new(OperationType::Deposit, 3, 3, Some(dec!(7).into())),
+        ];
+        assert!(records.into_iter().all(|r| manager.parse_entry(&r).is_ok()));
+
+        //pruned on the 4th record: tx 1 is beyond the horizon, while tx 2 is under dispute
+        assert_eq!(
+            manager
+                .transactions
+                .keys()
+                .copied()
+                .collect::<BTreeSet<_>>(),
+            [2, 3].into()
+        );
+        assert!(matches!(
+            manager.parse_entry(&Record::new(OperationType::Dispute, 1, 1, None)),
+            Ok(ParseOutcome::IgnoredNoSuchTx)
+        ));
+        assert_eq!(manager.accounts.get(&1).unwrap().available(), dec!(10));
+        assert_eq!(manager.accounts.get(&1).unwrap().held(), dec!(0));
+
+        let records: Vec<Record> = vec![
+            Record::new(OperationType::Chargeback, 2, 2, None),
+            Record::new(OperationType::Deposit, 3, 4, Some(dec!(1).into())),
+            Record::new(OperationType::Deposit, 3, 5, Some(dec!(1).into())),
+        ];
+        assert!(records.into_iter().all(|r| manager.parse_entry(&r).is_ok()));
+
+        //the charged back tx 2 is gone as well
+        assert_eq!(
+            manager
+                .transactions
+                .keys()
+                .copied()
+                .collect::<BTreeSet<_>>(),
+            [4, 5].into()
+        );
+        assert!(manager.reconcile().is_ok());
+    }
+
+    #[test]
+    fn test_snapshot_round_trip_continues_where_it_left_off() {
+        let records = mixed_records();
+        let (before, after) = records.split_at(6);
+        let more: Vec<Record> = vec![
+            Record::new(OperationType::Dispute, 1, 1, None),
+            Record::new(OperationType::Resolve, 1, 1, Some(dec!(3).into())),
+            Record::new(OperationType::Dispute, 3, 3, None),
+            Record::new(OperationType::Chargeback, 3, 3, None),
+            //a tx id known from before the snapshot
+            Record::new(OperationType::Deposit, 3, 4, Some(dec!(1).into())),
+        ];
+        let mut uninterrupted = TransactionManager::new();
+        uninterrupted.apply_all(records.iter().chain(&more).cloned());
+
+        let path = std::env::temp_dir().join(format!("snapshot-{}.json", std::process::id()));
+        let mut manager = TransactionManager::new();
+        manager.apply_all(before.to_vec());
+        assert!(manager.accounts.get_mut(&2).unwrap().freeze().is_ok());
+        assert!(manager.accounts.get_mut(&2).unwrap().unfreeze().is_ok());
+        manager.save_snapshot(&path).unwrap();
+        let mut restored = TransactionManager::new();
+        restored.load_snapshot(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        restored.apply_all(after.iter().chain(&more).cloned());
+
+        assert_eq!(account_states(&restored), account_states(&uninterrupted));
+        for client in 1..=4 {
+            assert_eq!(
+                restored.client_transactions(client),
+                uninterrupted.client_transactions(client)
+            );
+        }
+        assert_eq!(restored.checkpoint(), uninterrupted.checkpoint());
+        assert!(restored.reconcile().is_ok());
+    }
+
+    #[test]
+    fn test_diverging_accounts_fail_to_reconcile() {
+        let mut manager = TransactionManager::new();
+        manager.apply_all(mixed_records());
+        assert!(manager
+            .accounts
+            .get_mut(&3)
+            .unwrap()
+            .deposit(dec!(1).into())
+            .is_ok());
+
+        assert!(matches!(
+            manager.reconcile(),
+            Err(Errors::ReconciliationMismatch { expected, actual })
+                if expected == dec!(36) && actual == dec!(37)
+        ));
+    }
+
+    #[test]
+    fn test_accounts_follow_the_order_clients_were_first_seen() {
+        let mut manager = TransactionManager::new();
+        let records: Vec<Record> = vec![
+            Record::new(OperationType::Deposit, 7, 1, Some(dec!(1).into())),
+            Record::new(OperationType::Deposit, 2, 2, Some(dec!(1).into())),
+            Record::new(OperationType::Deposit, 7, 3, Some(dec!(1).into())),
+            Record::new(OperationType::Deposit, 300, 4, Some(dec!(1).into())),
+            Record::new(OperationType::Deposit, 1, 5, Some(dec!(1).into())),
+        ];
+        assert!(records.into_iter().all(|r| manager.parse_entry(&r).is_ok()));
+
+        let clients: Vec<u16> = manager.accounts().map(Account::get_client_id).collect();
+        assert_eq!(clients, vec![7, 2, 300, 1]);
+    }
+
+    #[test]
+    fn test_accounts_are_ordered_by_client() {
+        let mut manager = TransactionManager::new();
+        let records: Vec<Record> = [7, 3, 500, 1, 42]
+            .into_iter()
+            .enumerate()
+            .map(|(tx, client)| {
+                Record::new(
+                    OperationType::Deposit,
+                    client,
+                    tx as u32,
+                    Some(dec!(1).into()),
+                )
+            })
+            .collect();
+        manager.apply_all(records);
+
+        let ordered: Vec<_> = manager
+            .accounts_by_client()
+            .into_iter()
+            .map(|acc| acc.get_client_id())
+            .collect();
+        assert_eq!(ordered, [1, 3, 7, 42, 500]);
+    }
+
+    #[test]
+    fn test_zero_amounts_are_rejected_only_when_configured() {
+        let mut manager = TransactionManager::new();
+        let records: Vec<Record> = vec![
+            Record::new(OperationType::Deposit, 1, 1, Some(dec!(0).into())),
+            Record::new(OperationType::Withdrawal, 1, 2, Some(dec!(0.0000).into())),
+        ];
+        assert!(records.iter().all(|r| manager.parse_entry(r).is_ok()));
+        assert_eq!(manager.transactions.len(), 2);
+
+        let mut manager = TransactionManager::new();
+        manager.set_reject_zero(true);
+        let records: Vec<Record> = vec![
+            Record::new(OperationType::Deposit, 1, 1, Some(dec!(0).into())),
+            Record::new(OperationType::Withdrawal, 1, 2, Some(dec!(0.0000).into())),
+            Record::new(OperationType::Deposit, 1, 3, Some(dec!(0.0001).into())),
+        ];
+        assert!(matches!(
+            manager.parse_entry(&records[0]),
+            Err(Errors::ZeroAmount(1))
+        ));
+        assert!(matches!(
+            manager.parse_entry(&records[1]),
+            Err(Errors::ZeroAmount(2))
+        ));
+        assert!(manager.parse_entry(&records[2]).is_ok());
+        assert_eq!(manager.transactions.len(), 1);
+        assert_eq!(manager.account(1).unwrap().available(), dec!(0.0001));
+    }
+
+    #[test]
+    fn test_credit_limit_applies_to_known_and_new_accounts() {
+        let mut manager = TransactionManager::new();
+        let records: Vec<Record> = vec![
+            Record::new(OperationType::Deposit, 1, 1, Some(dec!(10).into())),
+            Record::new(OperationType::Withdrawal, 1, 2, Some(dec!(15).into())),
+        ];
+        assert!(manager.parse_entry(&records[0]).is_ok());
+        assert!(matches!(
+            manager.parse_entry(&records[1]),
+            Err(Errors::InsuficientFunds(1))
+        ));
+
+        assert!(manager.set_credit_limit(dec!(5)).is_ok());
+        let records: Vec<Record> = vec![
+            Record::new(OperationType::Withdrawal, 1, 3, Some(dec!(15).into())),
+            Record::new(OperationType::Withdrawal, 2, 4, Some(dec!(5).into())),
+        ];
+        assert!(records.into_iter().all(|r| manager.parse_entry(&r).is_ok()));
+        assert_eq!(manager.account(1).unwrap().available(), dec!(-5));
+        assert_eq!(manager.account(2).unwrap().available(), dec!(-5));
+        assert!(matches!(
+            manager.parse_entry(&Record::new(
+                OperationType::Withdrawal,
+                2,
+                5,
+                Some(dec!(1).into())
+            )),
+            Err(Errors::InsuficientFunds(2))
+        ));
+    }
+
+    #[test]
+    fn test_account_lookup_by_client() {
+        let mut manager = TransactionManager::new();
+        let records: Vec<Record> = vec![
+            Record::new(OperationType::Deposit, 1, 1, Some(dec!(10).into())),
+            Record::new(OperationType::Deposit, 3, 2, Some(dec!(2).into())),
+        ];
+        assert!(records.into_iter().all(|r| manager.parse_entry(&r).is_ok()));
+
+        assert_eq!(manager.account_count(), 2);
+        let account = manager.account(3).unwrap();
+        assert_eq!(account.get_client_id(), 3);
+        assert_eq!(account.available(), dec!(2));
+        assert!(manager.account(2).is_none());
+    }
+
+    #[test]
+    fn test_client_transactions_are_listed_in_processing_order_with_dispute_state() {
+        let mut manager = TransactionManager::new();
+        let records: Vec<Record> = vec![
+            Record::new(OperationType::Deposit, 1, 30, Some(dec!(10).into())),
+            Record::new(OperationType::Deposit, 2, 20, Some(dec!(10).into())),
+            Record::new(OperationType::Withdrawal, 1, 10, Some(dec!(1).into())),
+            Record::new(OperationType::Deposit, 1, 40, Some(dec!(2).into())),
+            Record::new(OperationType::Deposit, 1, 5, Some(dec!(3).into())),
+            Record::new(OperationType::Dispute, 1, 10, None),
+            Record::new(OperationType::Dispute, 1, 40, None),
+            Record::new(OperationType::Resolve, 1, 40, Some(dec!(2).into())),
+            Record::new(OperationType::Dispute, 1, 5, None),
+            Record::new(OperationType::Chargeback, 1, 5, None),
+        ];
+        assert!(records.into_iter().all(|r| manager.parse_entry(&r).is_ok()));
+
+        let summary = |tx, operation, amount: Decimal, state| TransactionSummary {
+            tx,
+            operation,
+            amount: Some(amount.into()),
+            state,
+        };
+        assert_eq!(
+            manager.client_transactions(1),
+            vec![
+                summary(
+                    30,
+                    OperationType::Deposit,
+                    dec!(10),
+                    DisputeState::Undisputed
+                ),
+                summary(
+                    10,
+                    OperationType::Withdrawal,
+                    dec!(1),
+                    DisputeState::Disputed
+                ),
+                summary(40, OperationType::Deposit, dec!(2), DisputeState::Resolved),
+                summary(
+                    5,
+                    OperationType::Deposit,
+                    dec!(3),
+                    DisputeState::ChargedBack
+                ),
+            ]
+        );
+        assert!(manager.client_transactions(3).is_empty());
+    }
+
+    #[test]
+    fn test_summary_aggregates_accounts_and_transactions() {
+        let mut manager = TransactionManager::new();
+        let records: Vec<Record> = vec![
+            Record::new(OperationType::Deposit, 1, 1, Some(dec!(10).into())),
+            Record::new(OperationType::Deposit, 2, 2, Some(dec!(5).into())),
+            Record::new(OperationType::Deposit, 3, 3, Some(dec!(2.5).into())),
+            Record::new(OperationType::Withdrawal, 1, 4, Some(dec!(1).into())),
+            Record::new(OperationType::Dispute, 2, 2, None),
+            Record::new(OperationType::Chargeback, 2, 2, None),
+            Record::new(OperationType::Dispute, 3, 3, None),
+            Record::new(OperationType::Dispute, 1, 4, None),
+        ];
+        assert!(records.into_iter().all(|r| manager.parse_entry(&r).is_ok()));
+        //rejected ones aren't counted
+        assert!(manager
+            .parse_entry(&Record::new(
+                OperationType::Withdrawal,
+                3,
+                5,
+                Some(dec!(100).into())
+            ))
+            .is_err());
+
+        let summary = manager.summary();
+        assert_eq!(
+            summary,
+            RunSummary {
+                accounts: 3,
+                locked_accounts: 1,
+                deposits: 3,
+                withdrawals: 1,
+                disputes_outstanding: 2,
+                total_held: dec!(2.5),
+            }
+        );
+        assert_eq!(
+            summary.to_string(),
+            "3 account(s) (1 locked), 3 deposit(s), 1 withdrawal(s), 2 dispute(s) outstanding, 2.5000 held in total"
+        );
+    }
+
+    #[test]
+    fn test_parallel_processing_matches_sequential_one() {
+        let mut sequential = reporting_manager();
+        let sequential_counts = sequential.apply_all(mixed_records());
+
+        for num_threads in 1..=4 {
+            let mut parallel = reporting_manager();
+            let counts = parallel.process_parallel(mixed_records(), num_threads);
+
+            assert_eq!(counts, sequential_counts);
+            assert_eq!(parallel.failures(), sequential.failures());
+            assert_eq!(account_states(&parallel), account_states(&sequential));
+        }
+        assert_eq!(sequential_counts.failed, 4);
+    }
+
+    #[test]
+    fn test_parallel_processing_continues_from_existing_state() {
+        let locks = Arc::new(Mutex::new(Vec::new()));
+        let mut manager = TransactionManager::new();
+        let listener_locks = Arc::clone(&locks);
+        manager.set_on_lock(move |client, tx| listener_locks.lock().unwrap().push((client, tx)));
+        assert!(manager
+            .parse_entry(&Record::new(
+                OperationType::Deposit,
+                2,
+                1,
+                Some(dec!(10).into())
+            ))
+            .is_ok());
+
+        let records: Vec<Record> = vec![
+            Record::new(OperationType::Dispute, 2, 1, None),
+            Record::new(OperationType::Chargeback, 2, 1, None),
+            Record::new(OperationType::Deposit, 3, 1, Some(dec!(5).into())),
+        ];
+        let counts = manager.process_parallel(records, 2);
+
+        assert_eq!(counts.applied, 2);
+        assert_eq!(counts.failed, 1);
+        assert!(manager.accounts.get(&2).unwrap().is_locked());
+        assert_eq!(*locks.lock().unwrap(), vec![(2, 1)]);
+        assert!(manager.on_lock.is_some());
+    }
+
+    //runs the records sequentially, and over 1 to 4 workers, on managers set up by `configure`
+    fn assert_parallel_matches_sequential(
+        configure: impl Fn(&mut TransactionManager),
+        records: Vec<Record>,
+    ) {
+        let mut sequential = reporting_manager();
+        configure(&mut sequential);
+        let sequential_counts = sequential.apply_all(records.clone());
+
+        for num_threads in 1..=4 {
+            let mut parallel = reporting_manager();
+            configure(&mut parallel);
+            let counts = parallel.process_parallel(records.clone(), num_threads);
+
+            assert_eq!(counts, sequential_counts);
+            assert_eq!(parallel.failures(), sequential.failures());
+            assert_eq!(account_states(&parallel), account_states(&sequential));
+            assert_eq!(parallel.metrics(), sequential.metrics());
+        }
+    }
+
+    //the first client's deposit is dropped before it's recorded, so the second one takes its tx
+    //id, and disputes it
+    fn reused_tx_records(first_amount: Decimal) -> Vec<Record> {
+        vec![
+            Record::new(OperationType::Deposit, 1, 1, Some(first_amount.into())),
+            Record::new(OperationType::Deposit, 2, 1, Some(dec!(5).into())),
+            Record::new(OperationType::Dispute, 2, 1, None),
+            Record::new(OperationType::Dispute, 1, 1, None),
+        ]
+    }
+
+    #[test]
+    fn test_parallel_processing_frees_the_tx_id_of_a_rejected_zero_amount() {
+        assert_parallel_matches_sequential(
+            |manager| manager.set_reject_zero(true),
+            reused_tx_records(dec!(0)),
+        );
+    }
+
+    #[test]
+    fn test_parallel_processing_frees_the_tx_id_of_a_reserved_client() {
+        assert_parallel_matches_sequential(
+            |manager| manager.set_reserved_clients("1".parse().unwrap()),
+            reused_tx_records(dec!(10)),
+        );
+    }
+
+    #[test]
+    fn test_parallel_processing_frees_the_tx_id_of_an_unscalable_amount() {
+        assert_parallel_matches_sequential(
+            |manager| manager.set_amount_scale(dec!(1000000)),
+            reused_tx_records(Decimal::MAX / dec!(10)),
+        );
+    }
+
+    #[test]
+    fn test_parallel_processing_frees_the_tx_id_of_an_out_of_order_record() {
+        let at = |time: &str| time.parse::<DateTime<Utc>>().unwrap();
+        let records = vec![
+            Record::new(OperationType::Deposit, 1, 1, Some(dec!(10).into()))
+                .with_timestamp(at("2024-01-01T10:00:00Z")),
+            Record::new(OperationType::Deposit, 2, 2, Some(dec!(5).into()))
+                .with_timestamp(at("2024-01-01T09:00:00Z")),
+            Record::new(OperationType::Deposit, 2, 2, Some(dec!(7).into()))
+                .with_timestamp(at("2024-01-01T11:00:00Z")),
+            Record::new(OperationType::Dispute, 2, 2, None)
+                .with_timestamp(at("2024-01-01T12:00:00Z")),
+        ];
+        assert_parallel_matches_sequential(|manager| manager.set_enforce_order(true), records);
+    }
+
+    #[test]
+    fn test_parallel_processing_skips_the_tx_ids_outside_the_range() {
+        let records = vec![
+            Record::new(OperationType::Deposit, 1, 1, Some(dec!(10).into())),
+            Record::new(OperationType::Deposit, 2, 1, Some(dec!(5).into())),
+            Record::new(OperationType::Deposit, 2, 5, Some(dec!(5).into())),
+            Record::new(OperationType::Dispute, 1, 5, None),
+        ];
+        assert_parallel_matches_sequential(|manager| manager.set_tx_range(Some(2), None), records);
+    }
+
+    #[test]
+    fn test_parallel_processing_skips_the_tx_ids_before_the_checkpoint() {
+        let records = vec![
+            Record::new(OperationType::Deposit, 1, 1, Some(dec!(10).into())),
+            Record::new(OperationType::Deposit, 2, 1, Some(dec!(5).into())),
+            Record::new(OperationType::Deposit, 2, 5, Some(dec!(5).into())),
+            Record::new(OperationType::Dispute, 1, 5, None),
+        ];
+        assert_parallel_matches_sequential(|manager| manager.resume_from(2), records);
+    }
+
+    #[test]
+    fn test_parallel_processing_frees_the_tx_id_of_a_pruned_transaction() {
+        let records = vec![
+            Record::new(OperationType::Deposit, 1, 1, Some(dec!(10).into())),
+            Record::new(OperationType::Deposit, 1, 2, Some(dec!(10).into())),
+            Record::new(OperationType::Deposit, 1, 3, Some(dec!(10).into())),
+            //the deposit of tx 1 is pruned right before this one
+            Record::new(OperationType::Deposit, 2, 1, Some(dec!(5).into())),
+            Record::new(OperationType::Dispute, 2, 1, None),
+            Record::new(OperationType::Dispute, 1, 1, None),
+        ];
+        assert_parallel_matches_sequential(|manager| manager.set_prune_after(2), records);
+    }
+
+    #[test]
+    fn test_parallel_processing_keeps_the_deferred_records() {
+        let records = vec![
+            Record::new(OperationType::Dispute, 1, 1, None),
+            Record::new(OperationType::Dispute, 2, 2, None),
+        ];
+        let mut manager = reporting_manager();
+        manager.set_out_of_order_window(10);
+        manager.process_parallel(records, 2);
+
+        assert_eq!(manager.pending.len(), 2);
+    }
+
+    fn replayed_records() -> Vec<Record> {
+        vec![
+            Record::new(OperationType::Deposit, 1, 1, Some(dec!(10).into())),
+            Record::new(OperationType::Deposit, 1, 2, Some(dec!(5).into())),
+            Record::new(OperationType::Withdrawal, 1, 3, Some(dec!(3).into())),
+            Record::new(OperationType::Dispute, 1, 2, None),
+            Record::new(OperationType::Deposit, 2, 4, Some(dec!(1).into())),
+        ]
+    }
+
+    #[test]
+    fn test_replay_without_checkpoint_rejects_already_processed_records() {
+        let records = replayed_records();
+        let mut manager = TransactionManager::new();
+        for record in &records[..3] {
+            assert!(manager.parse_entry(record).is_ok());
+        }
+        assert_eq!(manager.checkpoint(), 3);
+
+        let counts = manager.apply_all(records);
+        assert_eq!(counts.failed, 3);
+        assert_eq!(manager.checkpoint(), 4);
+    }
+
+    #[test]
+    fn test_replay_from_checkpoint_skips_already_processed_records() {
+        let records = replayed_records();
+        let mut manager = TransactionManager::new();
+        for record in &records[..3] {
+            assert!(manager.parse_entry(record).is_ok());
+        }
+        manager.resume_from(manager.checkpoint());
+
+        let outcomes: Vec<_> = records
+            .iter()
+            .map(|record| manager.parse_entry(record).unwrap())
+            .collect();
+        assert!(outcomes[..3]
+            .iter()
+            .all(|outcome| *outcome == ParseOutcome::IgnoredBeforeCheckpoint));
+        assert!(outcomes[3..]
+            .iter()
+            .all(|outcome| matches!(outcome, ParseOutcome::Applied { .. })));
+
+        let mut straight = TransactionManager::new();
+        straight.apply_all(records);
+        assert_eq!(account_states(&manager), account_states(&straight));
+        assert_eq!(manager.checkpoint(), 4);
+    }
+
+    #[test]
+    fn test_tx_range_bounds_are_inclusive_for_deposits_and_withdrawals() {
+        let mut manager = TransactionManager::new();
+        manager.set_tx_range(Some(2), Some(4));
+        let records: Vec<Record> = vec![
+            Record::new(OperationType::Deposit, 1, 1, Some(dec!(1).into())),
+            Record::new(OperationType::Deposit, 1, 2, Some(dec!(2).into())),
+            Record::new(OperationType::Deposit, 1, 3, Some(dec!(4).into())),
+            Record::new(OperationType::Withdrawal, 1, 4, Some(dec!(1).into())),
+            Record::new(OperationType::Deposit, 1, 5, Some(dec!(8).into())),
+        ];
+
+        let outcomes: Vec<_> = records
+            .iter()
+            .map(|record| manager.parse_entry(record).unwrap())
+            .collect();
+        assert_eq!(outcomes[0], ParseOutcome::IgnoredOutsideTxRange);
+        assert!(outcomes[1..4]
+            .iter()
+            .all(|outcome| matches!(outcome, ParseOutcome::Applied { .. })));
+        assert_eq!(outcomes[4], ParseOutcome::IgnoredOutsideTxRange);
+        assert_eq!(manager.accounts.get(&1).unwrap().available(), dec!(5));
+        assert_eq!(manager.checkpoint(), 4);
+    }
+
+    #[test]
+    fn test_tx_range_still_processes_disputes_of_known_transactions() {
+        let mut manager = TransactionManager::new();
+        let deposit =
+            |tx, amount: Decimal| Record::new(OperationType::Deposit, 1, tx, Some(amount.into()));
+        assert!(manager.parse_entry(&deposit(1, dec!(10))).is_ok());
+        manager.set_tx_range(Some(5), None);
+
+        assert_eq!(
+            manager
+                .parse_entry(&Record::new(OperationType::Dispute, 1, 1, None))
+                .unwrap(),
+            ParseOutcome::Applied {
+                client: 1,
+                delta_available: dec!(-10),
+                delta_held: dec!(10)
+            }
+        );
+        //a dispute of a skipped deposit has nothing to refer to
+        assert_eq!(
+            manager.parse_entry(&deposit(2, dec!(3))).unwrap(),
+            ParseOutcome::IgnoredOutsideTxRange
+        );
+        assert_eq!(
+            manager
+                .parse_entry(&Record::new(OperationType::Dispute, 1, 2, None))
+                .unwrap(),
+            ParseOutcome::IgnoredNoSuchTx
+        );
+        assert_eq!(manager.accounts.get(&1).unwrap().held(), dec!(10));
+    }
+
+    #[test]
+    fn test_metrics_count_applied_ignored_and_rejected_records_per_client() {
+        let mut manager = TransactionManager::new();
+        let records: Vec<Record> = vec![
+            Record::new(OperationType::Deposit, 1, 1, Some(dec!(10).into())),
+            Record::new(OperationType::Deposit, 2, 2, Some(dec!(10).into())),
+            Record::new(OperationType::Withdrawal, 1, 3, Some(dec!(20).into())),
+            Record::new(OperationType::Dispute, 1, 1, None),
+            Record::new(OperationType::Dispute, 1, 1, None),
+            Record::new(OperationType::Resolve, 1, 1, None),
+            Record::new(OperationType::Chargeback, 1, 1, None),
+            Record::new(OperationType::Dispute, 2, 7, None),
+            Record::new(OperationType::Withdrawal, 2, 4, Some(dec!(1).into())),
+        ];
+        for record in &records {
+            let _ = manager.parse_entry(record);
         }
+
+        assert_eq!(
+            manager.metrics(),
+            vec![
+                Metrics {
+                    client: 1,
+                    deposits: 1,
+                    disputes: 1,
+                    resolves: 1,
+                    ignored: 2,
+                    rejected: 1,
+                    ..Default::default()
+                },
+                Metrics {
+                    client: 2,
+                    deposits: 1,
+                    withdrawals: 1,
+                    ignored: 1,
+                    ..Default::default()
+                },
+            ]
+        );
     }
-}
 
-type Accounts = HashMap<u16, Account>;
-type Transactions = HashMap<u32, TransactionRecord>;
+    #[test]
+    fn test_unheld_withdrawal_disputes_are_counted_apart_from_ignored_ones() {
+        let records: Vec<Record> = vec![
+            Record::new(OperationType::Deposit, 1, 1, Some(dec!(10).into())),
+            Record::new(OperationType::Withdrawal, 1, 2, Some(dec!(4).into())),
+            Record::new(OperationType::Dispute, 1, 2, None),
+            Record::new(OperationType::Dispute, 1, 9, None),
+        ];
+        let mut manager = TransactionManager::new();
+        manager.apply_all(records.clone());
 
-pub struct TransactionManager {
-    accounts: Accounts,
-    transactions: Transactions,
-}
+        assert_eq!(
+            manager.metrics(),
+            vec![Metrics {
+                client: 1,
+                deposits: 1,
+                withdrawals: 1,
+                disputes: 1,
+                ignored: 1,
+                unheld_withdrawal_disputes: 1,
+                ..Default::default()
+            }]
+        );
+        assert!(manager.transactions.get(&2).unwrap().under_dispute);
 
-impl TransactionManager {
-    pub fn new() -> Self {
-        Self {
-            accounts: Accounts::new(),
-            transactions: Transactions::new(),
+        //a held withdrawal dispute isn't counted
+        let mut held = TransactionManager::new();
+        held.set_withdrawal_dispute_mode(WithdrawalDisputeMode::Hold);
+        held.apply_all(records);
+        assert_eq!(held.metrics()[0].unheld_withdrawal_disputes, 0);
+        assert_eq!(held.metrics()[0].disputes, 1);
+    }
+
+    #[test]
+    fn test_parallel_processing_metrics_match_sequential_ones() {
+        let mut sequential = TransactionManager::new();
+        sequential.apply_all(mixed_records());
+        let mut parallel = TransactionManager::new();
+        parallel.process_parallel(mixed_records(), 3);
+
+        assert_eq!(parallel.metrics(), sequential.metrics());
+    }
+
+    #[test]
+    fn test_apply_listener_sees_account_state_after_each_applied_record() {
+        let applied = Arc::new(Mutex::new(Vec::new()));
+        let mut manager = TransactionManager::new();
+        let listener_applied = Arc::clone(&applied);
+        manager.set_on_apply(move |record, account| {
+            listener_applied
+                .lock()
+                .unwrap()
+                .push((record.tx, account.available(), account.held()))
+        });
+
+        let records: Vec<Record> = vec![
+            Record::new(OperationType::Deposit, 1, 1, Some(dec!(10).into())),
+            Record::new(OperationType::Withdrawal, 1, 2, Some(dec!(20).into())),
+            Record::new(OperationType::Dispute, 1, 1, None),
+            Record::new(OperationType::Dispute, 1, 1, None),
+            Record::new(OperationType::Resolve, 1, 7, None),
+            Record::new(OperationType::Resolve, 1, 1, Some(dec!(4).into())),
+        ];
+        for record in &records {
+            let _ = manager.parse_entry(record);
         }
+
+        assert_eq!(
+            *applied.lock().unwrap(),
+            vec![
+                (1, dec!(10), dec!(0)),
+                (1, dec!(0), dec!(10)),
+                (1, dec!(4), dec!(6)),
+            ]
+        );
     }
 
-    pub fn parse_entry(&mut self, record: &Record) -> Result<(), Errors> {
-        let account = self
-            .accounts
-            .entry(record.client)
-            .or_insert_with(|| Account::new(record.client));
+    #[test]
+    fn test_records_of_reserved_clients_are_rejected_without_creating_accounts() {
+        let mut manager = TransactionManager::new();
+        manager.set_reserved_clients("0,60000-65535".parse().unwrap());
+        let amount = Some(dec!(1).into());
 
-        //keep track only of transactions that are of type deposit or withdrawal
-        //if there's a dispute/resolve/chargeback that is reffering to a non-existing operation
-        //then it would get dropped anyway
-        match record.r#type {
-            OperationType::Deposit => {
-                if self.transactions.contains_key(&record.tx) {
-                    return Err(Errors::TransactionIdAlreadyUsed(record.tx));
-                }
-                self.transactions
-                    .insert(record.tx, TransactionRecord::new(record.amount));
-                if let Some(amount) = record.amount {
-                    account.deposit(amount)?;
-                }
-            }
-            OperationType::Withdrawal => {
-                if self.transactions.contains_key(&record.tx) {
-                    return Err(Errors::TransactionIdAlreadyUsed(record.tx));
-                }
-                self.transactions
-                    .insert(record.tx, TransactionRecord::new(record.amount));
-                if let Some(amount) = record.amount {
-                    account.withdrawal(amount)?;
-                }
-            }
-            OperationType::Chargeback => {
-                if let Some(transaction) = self.transactions.get_mut(&record.tx) {
-                    if transaction.under_dispute {
-                        if let Some(amount) = transaction.amount {
-                            transaction.under_dispute = false;
-                            account.chargeback(amount)?;
-                        }
-                    }
-                }
-            }
-            OperationType::Dispute => {
-                if let Some(transaction) = self.transactions.get_mut(&record.tx) {
-                    if !transaction.already_disputed {
-                        transaction.under_dispute = true;
-                        transaction.already_disputed = true;
-                        if let Some(amount) = transaction.amount {
-                            account.dispute(amount)?;
-                        }
-                    }
-                }
-            }
-            OperationType::Resolve => {
-                if let Some(transaction) = self.transactions.get_mut(&record.tx) {
-                    if transaction.under_dispute {
-                        transaction.under_dispute = false;
-                        if let Some(amount) = record.amount {
-                            account.resolve(amount)?;
-                        }
-                    }
-                }
-            }
+        for client in [0, 60000, 65535] {
+            assert!(matches!(
+                manager.parse_entry(&Record::new(OperationType::Deposit, client, 1, amount)),
+                Err(Errors::ReservedClient(reserved)) if reserved == client
+            ));
         }
-        Ok(())
+        assert_eq!(manager.account_count(), 0);
+        for client in [1, 59999] {
+            assert!(manager
+                .parse_entry(&Record::new(
+                    OperationType::Deposit,
+                    client,
+                    u32::from(client),
+                    amount
+                ))
+                .is_ok());
+        }
+        assert_eq!(manager.account_count(), 2);
     }
-    pub fn accounts(&self) -> impl Iterator<Item = &Account> {
-        self.accounts.values()
+
+    #[test]
+    fn test_cleared_manager_starts_from_scratch() {
+        let mut manager = reporting_manager();
+        manager.apply_all(mixed_records());
+        let capacity = manager.transactions.capacity();
+
+        manager.clear();
+
+        assert_eq!(manager.account_count(), 0);
+        assert_eq!(manager.failures(), FailureCounts::default());
+        assert!(manager.metrics().is_empty());
+        assert_eq!(manager.transactions.capacity(), capacity);
+        assert!(manager
+            .parse_entry(&Record::new(
+                OperationType::Deposit,
+                5,
+                1,
+                Some(dec!(1).into())
+            ))
+            .is_ok());
+        assert_eq!(manager.account(5).unwrap().available(), dec!(1));
+        //the configuration is kept
+        assert!(matches!(
+            manager.parse_entry(&Record::new(OperationType::Dispute, 5, 9, None)),
+            Err(Errors::UnknownTransaction(9))
+        ));
     }
-}
 
-#[cfg(test)]
-mod tests {
-    use super::*;
-    use crate::amount::Amount;
-    use rust_decimal_macros::dec;
+    #[test]
+    fn test_merge_of_disjoint_partitions_matches_a_single_run() {
+        let (odd, even): (Vec<Record>, Vec<Record>) = mixed_records()
+            .into_iter()
+            .partition(|record| record.client % 2 == 1);
+        let mut first = reporting_manager();
+        first.apply_all(odd);
+        let mut second = reporting_manager();
+        second.apply_all(even);
 
-    //either allow(dead_code) or keep it in here
-    impl Record {
-        pub fn new(r#type: OperationType, client: u16, tx: u32, amount: Option<Amount>) -> Self {
-            Self {
-                r#type,
-                client,
-                tx,
-                amount,
-            }
-        }
+        let merged = first.merge(second).unwrap();
+
+        let mut single = reporting_manager();
+        single.apply_all(mixed_records());
+        assert_eq!(account_states(&merged), account_states(&single));
+        assert_eq!(merged.summary(), single.summary());
     }
 
     #[test]
-    fn test_dispute_on_non_existing_transaction_has_no_effets() {
+    fn test_merge_rejects_overlapping_clients_and_transactions() {
+        let manager = |records: Vec<Record>| {
+            let mut manager = TransactionManager::new();
+            manager.apply_all(records);
+            manager
+        };
+        let first = manager(vec![Record::new(
+            OperationType::Deposit,
+            1,
+            1,
+            Some(dec!(1).into()),
+        )]);
+        let second = manager(vec![Record::new(
+            OperationType::Deposit,
+            1,
+            2,
+            Some(dec!(1).into()),
+        )]);
+        assert!(matches!(
+            first.merge(second),
+            Err(Errors::AccountOverlap(1))
+        ));
+
+        let first = manager(vec![Record::new(
+            OperationType::Deposit,
+            1,
+            1,
+            Some(dec!(1).into()),
+        )]);
+        let second = manager(vec![Record::new(
+            OperationType::Deposit,
+            2,
+            1,
+            Some(dec!(1).into()),
+        )]);
+        assert!(matches!(
+            first.merge(second),
+            Err(Errors::TransactionIdUsedByAnotherClient(1, 2, 1))
+        ));
+    }
+
+    #[test]
+    fn test_lock_listener_is_notified_at_the_moment_of_lock() {
+        let locks = Arc::new(Mutex::new(Vec::new()));
         let mut manager = TransactionManager::new();
+        let listener_locks = Arc::clone(&locks);
+        manager.set_on_lock(move |client, tx| listener_locks.lock().unwrap().push((client, tx)));
+
         let records: Vec<Record> = vec![
-            Record::new(OperationType::Deposit, 1, 1, Some(dec!(12.5).into())),
-            Record::new(OperationType::Dispute, 1, 100, None),
+            Record::new(OperationType::Deposit, 1, 1, Some(dec!(10).into())),
+            Record::new(OperationType::Deposit, 2, 2, Some(dec!(10).into())),
+            Record::new(OperationType::Dispute, 2, 2, None),
         ];
-
         assert!(records.into_iter().all(|r| manager.parse_entry(&r).is_ok()));
+        assert!(locks.lock().unwrap().is_empty());
 
-        assert_eq!(manager.accounts.get(&1).unwrap().available(), dec!(12.5));
-        assert_eq!(manager.accounts.get(&1).unwrap().held(), dec!(0.0));
+        assert!(manager
+            .parse_entry(&Record::new(OperationType::Chargeback, 2, 2, None))
+            .is_ok());
+        assert_eq!(*locks.lock().unwrap(), vec![(2, 2)]);
+
+        assert!(manager
+            .parse_entry(&Record::new(
+                OperationType::Deposit,
+                1,
+                3,
+                Some(dec!(1).into())
+            ))
+            .is_ok());
+        assert_eq!(*locks.lock().unwrap(), vec![(2, 2)]);
     }
 
     #[test]
-    fn test_dispute_on_locked_account_shall_be_be_completed() {
+    fn test_lock_listener_is_notified_once_per_locked_account() {
+        let locks = Arc::new(Mutex::new(Vec::new()));
         let mut manager = TransactionManager::new();
+        let listener_locks = Arc::clone(&locks);
+        manager.set_on_lock(move |client, tx| listener_locks.lock().unwrap().push((client, tx)));
+
         let records: Vec<Record> = vec![
-            Record::new(OperationType::Deposit, 1, 1, Some(dec!(12).into())),
+            Record::new(OperationType::Deposit, 1, 1, Some(dec!(10).into())),
             Record::new(OperationType::Deposit, 1, 2, Some(dec!(5).into())),
             Record::new(OperationType::Dispute, 1, 1, None),
-            Record::new(OperationType::Chargeback, 1, 1, None),
             Record::new(OperationType::Dispute, 1, 2, None),
+            Record::new(OperationType::Chargeback, 1, 1, None),
             Record::new(OperationType::Chargeback, 1, 2, None),
         ];
-
         assert!(records.into_iter().all(|r| manager.parse_entry(&r).is_ok()));
 
+        assert!(manager.accounts.get(&1).unwrap().is_locked());
         assert_eq!(manager.accounts.get(&1).unwrap().held(), dec!(0));
-        assert_eq!(manager.accounts.get(&1).unwrap().available(), dec!(0));
+        assert_eq!(*locks.lock().unwrap(), vec![(1, 1)]);
     }
 
     #[test]
-    fn test_chargeback_for_operation_that_was_not_under_dispute_shall_have_no_effect() {
+    fn test_dispute_on_withdrawal_is_reported_as_type_mismatch_when_only_deposits_are_disputable() {
         let mut manager = TransactionManager::new();
+        manager.set_deposit_disputes_only(true);
         let records: Vec<Record> = vec![
-            Record::new(OperationType::Deposit, 1, 1, Some(dec!(100.0).into())),
-            Record::new(OperationType::Deposit, 1, 2, Some(dec!(20.0).into())),
-            Record::new(OperationType::Deposit, 1, 3, Some(dec!(15.0).into())),
-            Record::new(OperationType::Dispute, 1, 3, None), //blocks 15.0, available 120
-            Record::new(OperationType::Chargeback, 1, 2, None), //#2 wasn't under dispute, no effect
+            Record::new(OperationType::Deposit, 1, 1, Some(dec!(10).into())),
+            Record::new(OperationType::Deposit, 1, 3, Some(dec!(10).into())),
+            Record::new(OperationType::Withdrawal, 1, 2, Some(dec!(4).into())),
         ];
-
         assert!(records.into_iter().all(|r| manager.parse_entry(&r).is_ok()));
 
-        assert_eq!(manager.accounts.get(&1).unwrap().held(), dec!(15.0));
-        assert_eq!(manager.accounts.get(&1).unwrap().available(), dec!(120.0));
+        assert!(matches!(
+            manager.parse_entry(&Record::new(OperationType::Dispute, 1, 2, None)),
+            Err(Errors::DisputeTypeMismatch(2, OperationType::Withdrawal))
+        ));
+        assert_eq!(manager.accounts.get(&1).unwrap().held(), dec!(0));
+        assert_eq!(manager.accounts.get(&1).unwrap().available(), dec!(16));
+
+        assert!(manager
+            .parse_entry(&Record::new(OperationType::Dispute, 1, 1, None))
+            .is_ok());
+        assert_eq!(manager.accounts.get(&1).unwrap().held(), dec!(10));
     }
 
     #[test]
-    fn test_within_multiple_dispute_and_chargebacks_only_first_shall_affect_account_balance() {
+    fn test_amount_scale_turns_cents_into_dollars() {
         let mut manager = TransactionManager::new();
+        manager.set_amount_scale(dec!(0.01));
         let records: Vec<Record> = vec![
-            Record::new(OperationType::Deposit, 1, 1, Some(dec!(100.0).into())),
-            Record::new(OperationType::Deposit, 1, 2, Some(dec!(20.0).into())),
-            Record::new(OperationType::Dispute, 1, 2, None),
-            Record::new(OperationType::Chargeback, 1, 2, None),
-            Record::new(OperationType::Dispute, 1, 2, None),
-            Record::new(OperationType::Chargeback, 1, 2, None),
-            Record::new(OperationType::Dispute, 1, 2, None),
-            Record::new(OperationType::Chargeback, 1, 2, None),
+            Record::new(OperationType::Deposit, 1, 1, Some(dec!(12345).into())),
+            Record::new(OperationType::Withdrawal, 1, 2, Some(dec!(345).into())),
+            Record::new(OperationType::Deposit, 1, 3, Some(dec!(1000).into())),
+            Record::new(OperationType::Dispute, 1, 3, None),
         ];
 
         assert!(records.into_iter().all(|r| manager.parse_entry(&r).is_ok()));
 
-        assert_eq!(manager.accounts.get(&1).unwrap().held(), dec!(0.0));
-        assert!(manager.accounts.get(&1).unwrap().is_locked());
-        assert_eq!(manager.accounts.get(&1).unwrap().available(), dec!(100.0));
+        assert_eq!(manager.accounts.get(&1).unwrap().available(), dec!(120.00));
+        assert_eq!(manager.accounts.get(&1).unwrap().held(), dec!(10.00));
     }
 
     #[test]
-    fn test_resolve_for_operation_that_is_not_under_dispute_shall_have_no_effect() {
+    fn test_normalize_scale_stores_amounts_with_the_same_scale() {
+        let records: Vec<Record> = vec![
+            Record::new(OperationType::Deposit, 1, 1, Some(dec!(5).into())),
+            Record::new(OperationType::Deposit, 1, 2, Some(dec!(5.0000).into())),
+        ];
+        let amounts = |manager: &TransactionManager| {
+            manager
+                .client_transactions(1)
+                .iter()
+                .map(|t| t.amount.unwrap().0.to_string())
+                .collect::<Vec<_>>()
+        };
+
+        let mut manager = TransactionManager::new();
+        assert!(records.iter().all(|r| manager.parse_entry(r).is_ok()));
+        assert_eq!(amounts(&manager), vec!["5", "5.0000"]);
+
+        let mut normalized = TransactionManager::new();
+        normalized.set_normalize_scale(4);
+        assert!(records.iter().all(|r| normalized.parse_entry(r).is_ok()));
+        assert_eq!(amounts(&normalized), vec!["5.0000", "5.0000"]);
+        assert_eq!(
+            normalized.accounts.get(&1).unwrap().available().to_string(),
+            "10.0000"
+        );
+    }
+
+    #[test]
+    fn test_amount_scale_overflow_is_reported() {
+        let mut manager = TransactionManager::new();
+        manager.set_amount_scale(dec!(10));
+
+        assert!(matches!(
+            manager.parse_entry(&Record::new(
+                OperationType::Deposit,
+                1,
+                1,
+                Some(Decimal::MAX.into())
+            )),
+            Err(Errors::FundsOverflow(1))
+        ));
+        assert!(manager.transactions.is_empty());
+    }
+
+    #[test]
+    fn test_overflows_and_insufficient_funds_are_counted_separately() {
         let mut manager = TransactionManager::new();
         let records: Vec<Record> = vec![
-            Record::new(OperationType::Deposit, 1, 1, Some(dec!(1.234).into())),
-            Record::new(OperationType::Resolve, 1, 1, None),
+            Record::new(OperationType::Deposit, 1, 1, Some(Decimal::MAX.into())),
+            Record::new(OperationType::Deposit, 1, 2, Some(Decimal::MAX.into())),
+            Record::new(OperationType::Withdrawal, 2, 3, Some(dec!(1).into())),
+            Record::new(OperationType::Withdrawal, 2, 4, Some(dec!(2).into())),
+            Record::new(OperationType::Deposit, 2, 1, Some(dec!(2).into())),
         ];
 
-        assert!(records.into_iter().all(|r| manager.parse_entry(&r).is_ok()));
+        for record in records {
+            let _ = manager.parse_entry(&record);
+        }
 
-        assert_eq!(manager.accounts.get(&1).unwrap().held(), dec!(0.0));
-        assert_eq!(manager.accounts.get(&1).unwrap().available(), dec!(1.234));
+        assert_eq!(
+            manager.failures(),
+            FailureCounts {
+                overflows: 1,
+                insufficient_funds: 2,
+                other: 1,
+            }
+        );
     }
 
     #[test]
-    fn test_chargeback_for_operation_that_is_not_under_dispute_shall_have_no_effect() {
+    fn test_withdrawal_fee_is_deducted_together_with_withdrawn_amount() {
         let mut manager = TransactionManager::new();
+        manager.set_withdrawal_fee(WithdrawalFee::Flat(dec!(0.5)));
         let records: Vec<Record> = vec![
-            Record::new(OperationType::Deposit, 1, 1, Some(dec!(1.234).into())),
-            Record::new(OperationType::Chargeback, 1, 1, None),
+            Record::new(OperationType::Deposit, 1, 1, Some(dec!(10).into())),
+            Record::new(OperationType::Withdrawal, 1, 2, Some(dec!(4).into())),
         ];
 
         assert!(records.into_iter().all(|r| manager.parse_entry(&r).is_ok()));
 
-        assert_eq!(manager.accounts.get(&1).unwrap().held(), dec!(0.0));
-        assert!(!manager.accounts.get(&1).unwrap().is_locked());
-        assert_eq!(manager.accounts.get(&1).unwrap().available(), dec!(1.234));
+        assert_eq!(manager.accounts.get(&1).unwrap().available(), dec!(5.5));
+        assert_eq!(manager.fees_collected(), dec!(0.5));
     }
 
     #[test]
-    fn test_resolve_for_operation_that_was_already_chargedbacked_shall_have_no_effect() {
+    fn test_withdrawal_not_covering_fee_is_rejected_as_a_whole() {
+        let mut manager = TransactionManager::new();
+        manager.set_withdrawal_fee(WithdrawalFee::Flat(dec!(0.5)));
+        assert!(manager
+            .parse_entry(&Record::new(
+                OperationType::Deposit,
+                1,
+                1,
+                Some(dec!(10).into())
+            ))
+            .is_ok());
+
+        assert!(matches!(
+            manager.parse_entry(&Record::new(
+                OperationType::Withdrawal,
+                1,
+                2,
+                Some(dec!(9.8).into())
+            )),
+            Err(Errors::InsuficientFunds(1))
+        ));
+        assert_eq!(manager.accounts.get(&1).unwrap().available(), dec!(10));
+        assert_eq!(manager.fees_collected(), dec!(0));
+    }
+
+    #[test]
+    fn test_batched_processing_reports_cumulative_counts_after_each_batch() {
         let mut manager = TransactionManager::new();
+        let records: Vec<Record> = (1..=10)
+            .map(|tx| match tx {
+                //tx 1 is reused, hence rejected
+                10 => Record::new(OperationType::Deposit, 1, 1, Some(dec!(1).into())),
+                _ => Record::new(OperationType::Deposit, 1, tx, Some(dec!(1).into())),
+            })
+            .collect();
+        let mut batches = Vec::new();
+
+        let counts = manager.process_batched(records, 4, |counts| batches.push(counts));
+
+        assert_eq!(
+            batches,
+            vec![
+                ProcessedCounts {
+                    applied: 4,
+                    failed: 0
+                },
+                ProcessedCounts {
+                    applied: 8,
+                    failed: 0
+                },
+                ProcessedCounts {
+                    applied: 9,
+                    failed: 1
+                },
+            ]
+        );
+        assert_eq!(counts.total(), 10);
+        assert_eq!(manager.accounts.get(&1).unwrap().available(), dec!(9));
+    }
+
+    #[test]
+    fn test_dispute_preceding_its_deposit_within_window_is_applied() {
+        let mut manager = TransactionManager::new();
+        manager.set_out_of_order_window(2);
         let records: Vec<Record> = vec![
-            Record::new(OperationType::Deposit, 1, 1, Some(dec!(0.234).into())),
-            Record::new(OperationType::Deposit, 1, 2, Some(dec!(1.0).into())),
             Record::new(OperationType::Dispute, 1, 2, None),
-            Record::new(OperationType::Chargeback, 1, 2, None),
-            Record::new(OperationType::Resolve, 1, 2, None),
+            Record::new(OperationType::Deposit, 1, 1, Some(dec!(3).into())),
+            Record::new(OperationType::Deposit, 1, 2, Some(dec!(5).into())),
         ];
 
         assert!(records.into_iter().all(|r| manager.parse_entry(&r).is_ok()));
 
-        assert_eq!(manager.accounts.get(&1).unwrap().held(), dec!(0.0));
-        assert!(manager.accounts.get(&1).unwrap().is_locked());
-        assert_eq!(manager.accounts.get(&1).unwrap().available(), dec!(0.234));
+        assert_eq!(manager.accounts.get(&1).unwrap().available(), dec!(3));
+        assert_eq!(manager.accounts.get(&1).unwrap().held(), dec!(5));
+        assert!(manager.pending.is_empty());
     }
 
     #[test]
-    fn test_resolve_on_non_existing_transaction_has_no_effets() {
+    fn test_dispute_preceding_its_deposit_beyond_window_is_dropped() {
         let mut manager = TransactionManager::new();
+        manager.set_out_of_order_window(1);
         let records: Vec<Record> = vec![
-            Record::new(OperationType::Deposit, 1, 1, Some(dec!(1).into())),
-            Record::new(OperationType::Resolve, 1, 2, None),
+            Record::new(OperationType::Dispute, 1, 2, None),
+            Record::new(OperationType::Deposit, 1, 1, Some(dec!(3).into())),
+            Record::new(OperationType::Deposit, 1, 2, Some(dec!(5).into())),
         ];
 
         assert!(records.into_iter().all(|r| manager.parse_entry(&r).is_ok()));
 
-        assert_eq!(manager.accounts.get(&1).unwrap().available(), dec!(1));
-        assert_eq!(manager.accounts.get(&1).unwrap().held(), dec!(0.0));
+        assert_eq!(manager.accounts.get(&1).unwrap().available(), dec!(8));
+        assert_eq!(manager.accounts.get(&1).unwrap().held(), dec!(0));
+        assert!(manager.pending.is_empty());
     }
 
     #[test]
-    fn test_chargeback_on_non_existing_transaction_has_no_effets() {
-        let mut manager = TransactionManager::new();
+    fn test_only_dropped_outcomes_are_ignored() {
+        assert!(ParseOutcome::IgnoredNoSuchTx.is_ignored());
+        assert!(ParseOutcome::IgnoredOutsideTxRange.is_ignored());
+        assert!(!ParseOutcome::Deferred.is_ignored());
+        assert!(!ParseOutcome::Applied {
+            client: 1,
+            delta_available: dec!(1),
+            delta_held: dec!(0)
+        }
+        .is_ignored());
+        assert_eq!(
+            ParseOutcome::IgnoredNotDisputed.to_string(),
+            "ignored, the referenced transaction isn't under dispute"
+        );
+    }
+
+    #[test]
+    fn test_finish_drops_the_deferred_records_under_the_ignored_policy() {
+        let mut manager = reporting_manager();
+        manager.set_out_of_order_window(1);
         let records: Vec<Record> = vec![
-            Record::new(OperationType::Deposit, 1, 1, Some(dec!(2).into())),
-            Record::new(OperationType::Chargeback, 1, 3, None),
+            Record::new(OperationType::Dispute, 1, 7, None),
+            Record::new(OperationType::Deposit, 1, 1, Some(dec!(3).into())),
+            Record::new(OperationType::Deposit, 1, 2, Some(dec!(5).into())),
+            Record::new(OperationType::Resolve, 1, 8, None),
         ];
-
         assert!(records.into_iter().all(|r| manager.parse_entry(&r).is_ok()));
 
-        assert_eq!(manager.accounts.get(&1).unwrap().available(), dec!(2));
-        assert_eq!(manager.accounts.get(&1).unwrap().held(), dec!(0.0));
+        let dropped = manager.finish();
+
+        assert_eq!(
+            dropped
+                .iter()
+                .map(|dropped| (dropped.seq, dropped.record.tx))
+                .collect::<Vec<_>>(),
+            vec![(1, 7), (4, 8)]
+        );
+        assert!(dropped
+            .iter()
+            .all(|dropped| matches!(dropped.result, Err(Errors::UnknownTransaction(_)))));
+        assert_eq!(manager.failures().other, 2);
+        assert!(manager.pending.is_empty());
+        assert!(manager.finish().is_empty());
     }
 
     #[test]
-    fn test_transaction_with_the_same_id_shall_be_rejected_and_error_shall_be_reported() {
+    fn test_finish_drops_the_deferred_records_silently_by_default() {
+        let mut manager = TransactionManager::new();
+        manager.set_out_of_order_window(5);
+        assert!(matches!(
+            manager.parse_entry(&Record::new(OperationType::Dispute, 1, 7, None)),
+            Ok(ParseOutcome::Deferred)
+        ));
+
+        let dropped = manager.finish();
+
+        assert_eq!(dropped.len(), 1);
+        assert!(matches!(
+            dropped[0].result,
+            Ok(ParseOutcome::IgnoredNoSuchTx)
+        ));
+        assert_eq!(manager.failures(), FailureCounts::default());
+    }
+
+    #[test]
+    fn test_cross_client_dispute_resolve_and_chargeback_leave_both_accounts_untouched() {
         let mut manager = TransactionManager::new();
         let records: Vec<Record> = vec![
-            Record::new(OperationType::Deposit, 1, 1, Some(dec!(2).into())),
-            Record::new(OperationType::Deposit, 1, 1, Some(dec!(1).into())),
+            Record::new(OperationType::Deposit, 1, 5, Some(dec!(10).into())),
+            Record::new(OperationType::Deposit, 2, 6, Some(dec!(3).into())),
+            Record::new(OperationType::Dispute, 2, 5, None),
+            Record::new(OperationType::Dispute, 999, 5, None),
+            Record::new(OperationType::Dispute, 1, 6, None),
         ];
+        assert!(records.into_iter().all(|r| manager.parse_entry(&r).is_ok()));
 
-        assert!(manager.parse_entry(&records[0]).is_ok());
+        assert_eq!(manager.accounts.get(&1).unwrap().available(), dec!(10));
+        assert_eq!(manager.accounts.get(&1).unwrap().held(), dec!(0));
+        assert_eq!(manager.accounts.get(&2).unwrap().available(), dec!(3));
+        assert_eq!(manager.accounts.get(&2).unwrap().held(), dec!(0));
+        assert!(!manager.accounts.contains_key(&999));
+
+        //the owner disputes, someone else tries to settle the dispute
+        assert!(manager
+            .parse_entry(&Record::new(OperationType::Dispute, 1, 5, None))
+            .is_ok());
+        manager.set_ignored_policy(IgnoredOperationPolicy::Report);
         assert!(matches!(
-            manager.parse_entry(&records[1]),
-            Err(Errors::TransactionIdAlreadyUsed(1))
+            manager.parse_entry(&Record::new(OperationType::Chargeback, 2, 5, None)),
+            Err(Errors::ClientMismatch(5, 2))
         ));
-
-        assert_eq!(manager.transactions.len(), 1);
-        let _expected_transaction = TransactionRecord::new(Some(dec!(2).into()));
         assert!(matches!(
-            manager.transactions.get(&1).unwrap(),
-            _expected_transaction
+            manager.parse_entry(&Record::new(OperationType::Resolve, 999, 5, None)),
+            Err(Errors::ClientMismatch(5, 999))
         ));
+        assert_eq!(manager.accounts.get(&1).unwrap().held(), dec!(10));
+        assert!(!manager.accounts.get(&1).unwrap().is_locked());
+        assert!(!manager.accounts.get(&2).unwrap().is_locked());
+    }
+}
+
+// randomized streams of operations, checking the invariants of the dispute state machine after
+// every record; the seed is fixed, so a failure reproduces on every run
+#[cfg(test)]
+mod properties {
+    use super::*;
+    use proptest::prelude::*;
+    use proptest::test_runner::{Config, RngSeed};
+
+    const CLIENTS: u16 = 3;
+
+    fn config() -> Config {
+        Config {
+            cases: 256,
+            rng_seed: RngSeed::Fixed(0x5eed),
+            failure_persistence: None,
+            ..Config::default()
+        }
+    }
+
+    // (operation, client, referenced transaction, amount in cents); deposits and withdrawals take
+    // the next tx id, the other operations refer to one of the tx ids given out so far (or to a
+    // yet unknown one), possibly of another client
+    fn operations() -> impl Strategy<Value = Vec<(OperationType, u16, u32, i64)>> {
+        let operation = prop_oneof![
+            3 => Just(OperationType::Deposit),
+            2 => Just(OperationType::Withdrawal),
+            2 => Just(OperationType::Dispute),
+            1 => Just(OperationType::Resolve),
+            1 => Just(OperationType::Chargeback),
+        ];
+        prop::collection::vec((operation, 1..=CLIENTS, 0..64u32, 1..100_000i64), 1..200)
+    }
+
+    fn records(operations: Vec<(OperationType, u16, u32, i64)>) -> Vec<Record> {
+        let mut next_tx = 1;
+        operations
+            .into_iter()
+            .map(|(operation, client, reference, cents)| match operation {
+                OperationType::Deposit | OperationType::Withdrawal => {
+                    next_tx += 1;
+                    let amount = Decimal::new(cents, 2);
+                    Record::new(operation, client, next_tx - 1, Some(amount.into()))
+                }
+                _ => Record::new(operation, client, reference % (next_tx + 1), None),
+            })
+            .collect()
+    }
+
+    fn check_invariants(manager: &mut TransactionManager, records: &[Record]) {
+        for record in records {
+            let before = manager
+                .accounts
+                .get(&record.client)
+                .map(|account| (account.available(), account.held(), account.is_locked()));
+            let outcome = manager.parse_entry(record);
+            //a record dropped before reaching the account doesn't create it
+            let (available_after, held_after) = manager
+                .accounts
+                .get(&record.client)
+                .map(|account| (account.available(), account.held()))
+                .unwrap_or_default();
+
+            // a locked account never takes a new deposit
+            let (available, held, locked) = before.unwrap_or_default();
+            if record.r#type == OperationType::Deposit && locked {
+                assert!(
+                    outcome.is_err(),
+                    "deposit {} applied to a locked account",
+                    record.tx
+                );
+                assert_eq!(available_after, available);
+            }
+
+            // the reported deltas are exactly the change of the funds
+            if let Ok(
+                ParseOutcome::Applied {
+                    delta_available,
+                    delta_held,
+                    ..
+                }
+                | ParseOutcome::Locked {
+                    delta_available,
+                    delta_held,
+                    ..
+                },
+            ) = outcome
+            {
+                assert_eq!(available_after - available, delta_available);
+                assert_eq!(held_after - held, delta_held);
+            }
+
+            for account in manager.accounts() {
+                // a disputed amount might drive available below zero, but never held
+                assert!(
+                    account.held() >= Decimal::ZERO,
+                    "negative held of client {} after tx {}",
+                    account.get_client_id(),
+                    record.tx
+                );
+                assert_eq!(account.total(), Some(account.available() + account.held()));
+            }
+            // the funds of the accounts always add up to what their transactions moved
+            assert!(
+                manager.reconcile().is_ok(),
+                "mismatch after tx {}",
+                record.tx
+            );
+        }
+    }
+
+    proptest! {
+        #![proptest_config(config())]
+
+        #[test]
+        fn test_random_streams_keep_the_invariants(operations in operations()) {
+            let mut manager = TransactionManager::new();
+            check_invariants(&mut manager, &records(operations));
+        }
+
+        #[test]
+        fn test_random_streams_with_held_withdrawal_disputes_keep_the_invariants(
+            operations in operations()
+        ) {
+            let mut manager = TransactionManager::new();
+            manager.set_withdrawal_dispute_mode(WithdrawalDisputeMode::Hold);
+            manager.set_lock_on_withdrawal_chargeback(true);
+            check_invariants(&mut manager, &records(operations));
+        }
     }
 }
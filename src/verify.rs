@@ -0,0 +1,99 @@
+use anyhow::Result;
+use payments_engine::Account;
+use rust_decimal::Decimal;
+use serde::Deserialize;
+use std::collections::BTreeMap;
+use std::io::Read;
+
+#[derive(Debug, Deserialize, PartialEq)]
+struct AccountRow {
+    client: u16,
+    available: Decimal,
+    held: Decimal,
+    total: Decimal,
+    locked: bool,
+}
+
+fn read_rows<R: Read>(reader: R) -> Result<BTreeMap<u16, AccountRow>> {
+    let mut reader = csv::ReaderBuilder::new()
+        .trim(csv::Trim::All)
+        .from_reader(reader);
+    let mut rows = BTreeMap::new();
+    for row in reader.deserialize::<AccountRow>() {
+        let row = row?;
+        rows.insert(row.client, row);
+    }
+    Ok(rows)
+}
+
+// compares the accounts against the expected summary, returns a description of every difference
+// (an empty list means an exact match); the accounts go through the regular serializer first
+// so both sides are compared with the same precision
+pub fn verify<'a, R: Read>(
+    accounts: impl Iterator<Item = &'a Account>,
+    expected: R,
+) -> Result<Vec<String>> {
+    let mut writer = csv::Writer::from_writer(Vec::new());
+    for account in accounts {
+        writer.serialize(account)?;
+    }
+    let actual = read_rows(writer.into_inner()?.as_slice())?;
+    let expected = read_rows(expected)?;
+
+    let mut clients: Vec<&u16> = actual.keys().chain(expected.keys()).collect();
+    clients.sort();
+    clients.dedup();
+
+    Ok(clients
+        .into_iter()
+        .filter_map(|client| match (expected.get(client), actual.get(client)) {
+            (Some(expected), Some(actual)) if expected == actual => None,
+            (Some(expected), Some(actual)) => Some(format!(
+                "client {}: expected {:?}, got {:?}",
+                client, expected, actual
+            )),
+            (Some(_), None) => Some(format!("client {}: missing in the output", client)),
+            (None, _) => Some(format!("client {}: not expected", client)),
+        })
+        .collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rust_decimal_macros::dec;
+
+    fn accounts() -> Vec<Account> {
+        let mut first = Account::new(1);
+        assert!(first.deposit(dec!(1.5).into()).is_ok());
+        let mut second = Account::new(2);
+        assert!(second.deposit(dec!(3).into()).is_ok());
+        assert!(second.dispute(dec!(1).into()).is_ok());
+        vec![second, first]
+    }
+
+    #[test]
+    fn test_matching_expectation_yields_no_differences() {
+        let expected = "client,available,held,total,locked\n\
+                        1,1.5,0,1.5,false\n\
+                        2,2.0000,1.0000,3.0000,false\n";
+
+        assert!(verify(accounts().iter(), expected.as_bytes())
+            .unwrap()
+            .is_empty());
+    }
+
+    #[test]
+    fn test_mismatching_expectation_reports_every_difference() {
+        let expected = "client,available,held,total,locked\n\
+                        2,3.0000,0.0000,3.0000,false\n\
+                        3,1.0000,0.0000,1.0000,false\n";
+
+        let differences = verify(accounts().iter(), expected.as_bytes()).unwrap();
+
+        assert_eq!(differences.len(), 3);
+        assert_eq!(differences[0], "client 1: not expected");
+        assert!(differences[1].starts_with("client 2: expected"));
+        assert_eq!(differences[2], "client 3: missing in the output");
+    }
+}
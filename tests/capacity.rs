@@ -0,0 +1,58 @@
+use payments_engine::{OperationType, Record, TransactionManager};
+use rust_decimal_macros::dec;
+use std::alloc::{GlobalAlloc, Layout, System};
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+struct CountingAllocator;
+
+static ALLOCATIONS: AtomicUsize = AtomicUsize::new(0);
+
+unsafe impl GlobalAlloc for CountingAllocator {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        ALLOCATIONS.fetch_add(1, Ordering::Relaxed);
+        System.alloc(layout)
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        System.dealloc(ptr, layout)
+    }
+
+    unsafe fn realloc(&self, ptr: *mut u8, layout: Layout, new_size: usize) -> *mut u8 {
+        ALLOCATIONS.fetch_add(1, Ordering::Relaxed);
+        System.realloc(ptr, layout, new_size)
+    }
+}
+
+#[global_allocator]
+static GLOBAL: CountingAllocator = CountingAllocator;
+
+const CLIENTS: u16 = 1000;
+const TRANSACTIONS: u32 = 200_000;
+
+fn allocations_while_processing(mut manager: TransactionManager) -> usize {
+    let records: Vec<Record> = (0..TRANSACTIONS)
+        .map(|tx| {
+            let client = (tx % u32::from(CLIENTS)) as u16;
+            Record::new(OperationType::Deposit, client, tx, Some(dec!(1).into()))
+        })
+        .collect();
+    let before = ALLOCATIONS.load(Ordering::Relaxed);
+    for record in &records {
+        manager.parse_entry(record).unwrap();
+    }
+    ALLOCATIONS.load(Ordering::Relaxed) - before
+}
+
+// run with `cargo test --release --test capacity -- --ignored --nocapture`
+#[test]
+#[ignore]
+fn test_capacity_hints_avoid_rehashing_allocations() {
+    let growing = allocations_while_processing(TransactionManager::new());
+    let presized = allocations_while_processing(TransactionManager::with_capacity(
+        usize::from(CLIENTS),
+        TRANSACTIONS as usize,
+    ));
+    println!("allocations: {} growing, {} pre-sized", growing, presized);
+
+    assert!(presized < growing);
+}
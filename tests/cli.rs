@@ -0,0 +1,948 @@
+use std::io::Write;
+use std::process::{Command, Stdio};
+
+fn run_with_stdin(args: &[&str], input: &str) -> std::process::Output {
+    let mut child = Command::new(env!("CARGO_BIN_EXE_payments-engine"))
+        .args(args)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .unwrap();
+    //a run refusing its options exits without reading the input
+    match child.stdin.take().unwrap().write_all(input.as_bytes()) {
+        Err(err) if err.kind() == std::io::ErrorKind::BrokenPipe => {}
+        result => result.unwrap(),
+    }
+    child.wait_with_output().unwrap()
+}
+
+#[test]
+fn test_transactions_are_read_from_stdin() {
+    let output = run_with_stdin(
+        &["-"],
+        "type, client, tx, amount\ndeposit, 1, 1, 1.5\nwithdrawal, 1, 2, 0.5\n",
+    );
+
+    assert!(output.status.success());
+    assert_eq!(
+        String::from_utf8(output.stdout).unwrap(),
+        "client,available,held,total,locked\n1,1.0000,0.0000,1.0000,false\n"
+    );
+}
+
+#[test]
+fn test_first_seen_order_follows_the_input() {
+    let input =
+        "type,client,tx,amount\ndeposit,3,1,1\ndeposit,1,2,1\ndeposit,2,3,1\ndeposit,1,4,1\n";
+
+    let by_client = run_with_stdin(&["-"], input);
+    let first_seen = run_with_stdin(&["-", "--order", "first-seen"], input);
+
+    let clients = |output: std::process::Output| -> Vec<String> {
+        assert!(output.status.success());
+        String::from_utf8(output.stdout)
+            .unwrap()
+            .lines()
+            .skip(1)
+            .map(|line| line.split(',').next().unwrap().to_string())
+            .collect()
+    };
+    assert_eq!(clients(by_client), ["1", "2", "3"]);
+    assert_eq!(clients(first_seen), ["3", "1", "2"]);
+}
+
+#[test]
+fn test_output_is_identical_between_runs() {
+    let input = std::env::temp_dir().join(format!("sorted-output-{}.csv", std::process::id()));
+    let mut content = String::from("type,client,tx,amount\n");
+    for tx in 1..=200u32 {
+        content.push_str(&format!("deposit,{},{},{}.5\n", (tx * 7919) % 1000, tx, tx));
+    }
+    std::fs::write(&input, content).unwrap();
+
+    let run = || {
+        let output = Command::new(env!("CARGO_BIN_EXE_payments-engine"))
+            .arg(&input)
+            .output()
+            .unwrap();
+        assert!(output.status.success());
+        output.stdout
+    };
+    let first = run();
+    let second = run();
+    std::fs::remove_file(&input).unwrap();
+
+    assert_eq!(first, second);
+    let clients: Vec<u16> = String::from_utf8(first)
+        .unwrap()
+        .lines()
+        .skip(1)
+        .map(|line| line.split(',').next().unwrap().parse().unwrap())
+        .collect();
+    assert_eq!(clients.len(), 200);
+    assert!(clients.windows(2).all(|pair| pair[0] < pair[1]));
+}
+
+#[test]
+fn test_output_is_written_into_requested_file() {
+    let input = std::env::temp_dir().join(format!("output-flag-{}.csv", std::process::id()));
+    let output_path = input.with_extension("out.csv");
+    std::fs::write(&input, "type,client,tx,amount\ndeposit,1,1,2\n").unwrap();
+    std::fs::write(
+        &output_path,
+        "stale content that has to be truncated\n".repeat(10),
+    )
+    .unwrap();
+
+    let output = Command::new(env!("CARGO_BIN_EXE_payments-engine"))
+        .arg(&input)
+        .arg("--output")
+        .arg(&output_path)
+        .output()
+        .unwrap();
+    let written = std::fs::read_to_string(&output_path).unwrap();
+    std::fs::remove_file(&input).unwrap();
+    std::fs::remove_file(&output_path).unwrap();
+
+    assert!(output.status.success());
+    assert!(output.stdout.is_empty());
+    assert_eq!(
+        written,
+        "client,available,held,total,locked\n1,2.0000,0.0000,2.0000,false\n"
+    );
+}
+
+#[test]
+fn test_unwritable_output_fails_the_run() {
+    let input = std::env::temp_dir().join(format!("output-failure-{}.csv", std::process::id()));
+    std::fs::write(&input, "type,client,tx,amount\ndeposit,1,1,2\n").unwrap();
+
+    let output = Command::new(env!("CARGO_BIN_EXE_payments-engine"))
+        .arg(&input)
+        .arg("--output")
+        .arg(
+            std::env::temp_dir()
+                .join("missing-directory")
+                .join("out.csv"),
+        )
+        .output()
+        .unwrap();
+    std::fs::remove_file(&input).unwrap();
+
+    assert!(!output.status.success());
+}
+
+#[test]
+fn test_strict_mode_aborts_on_first_failed_transaction() {
+    let input = std::env::temp_dir().join(format!("strict-{}.csv", std::process::id()));
+    std::fs::write(
+        &input,
+        "type,client,tx,amount\ndeposit,1,1,2\nwithdrawal,1,2,5\ndeposit,1,3,1\n",
+    )
+    .unwrap();
+
+    let lenient = Command::new(env!("CARGO_BIN_EXE_payments-engine"))
+        .arg(&input)
+        .output()
+        .unwrap();
+    let strict = Command::new(env!("CARGO_BIN_EXE_payments-engine"))
+        .arg(&input)
+        .arg("--strict")
+        .output()
+        .unwrap();
+    std::fs::remove_file(&input).unwrap();
+
+    assert!(lenient.status.success());
+    assert!(!strict.status.success());
+    assert!(strict.stdout.is_empty());
+    assert!(String::from_utf8(strict.stderr)
+        .unwrap()
+        .contains("Transaction 2 in"));
+}
+
+#[test]
+fn test_headerless_input_is_mapped_by_given_columns() {
+    let output = run_with_stdin(
+        &["-", "--no-headers", "--columns", "client,type,amount,tx"],
+        "1, deposit, 2.5, 1\n1, withdrawal, 1, 2\n",
+    );
+
+    assert!(output.status.success());
+    assert_eq!(
+        String::from_utf8(output.stdout).unwrap(),
+        "client,available,held,total,locked\n1,1.5000,0.0000,1.5000,false\n"
+    );
+}
+
+#[test]
+fn test_missing_column_is_reported_before_processing() {
+    let output = run_with_stdin(
+        &["-", "--no-headers", "--columns", "client,type,amount"],
+        "1, deposit, 2.5\n",
+    );
+
+    assert!(!output.status.success());
+    assert!(output.stdout.is_empty());
+    assert!(String::from_utf8(output.stderr)
+        .unwrap()
+        .contains("the `tx` column is missing"));
+}
+
+#[test]
+fn test_strict_mode_rejects_reference_to_unknown_transaction() {
+    let input = "type,client,tx,amount\ndeposit,1,1,2\ndeposit,2,2,2\ndispute,1,2,\ndispute,1,7,\n";
+
+    let lenient = run_with_stdin(&["-"], input);
+    let strict = run_with_stdin(&["-", "--strict"], input);
+
+    assert!(lenient.status.success());
+    assert!(!strict.status.success());
+    assert!(String::from_utf8(strict.stderr)
+        .unwrap()
+        .contains("Transaction 7 in - failed: Transaction ID 7 does not exist!"));
+}
+
+#[test]
+fn test_check_mode_produces_no_output_and_fails_on_errors() {
+    let valid = "type,client,tx,amount\ndeposit,1,1,2\nwithdrawal,1,2,1\n";
+    let invalid = "type,client,tx,amount\ndeposit,1,1,2\nwithdrawal,1,2,5\nbogus,1,3,1\n";
+
+    let passed = run_with_stdin(&["-", "--check"], valid);
+    let failed = run_with_stdin(&["-", "--check"], invalid);
+
+    assert!(passed.status.success());
+    assert!(passed.stdout.is_empty());
+    assert!(String::from_utf8(passed.stderr)
+        .unwrap()
+        .contains("Checked 2 record(s): 2 valid, 0 invalid record(s), 0 failed transaction(s)"));
+    assert!(!failed.status.success());
+    assert!(failed.stdout.is_empty());
+    assert!(String::from_utf8(failed.stderr)
+        .unwrap()
+        .contains("Checked 3 record(s): 1 valid, 1 invalid record(s), 1 failed transaction(s)"));
+}
+
+#[test]
+fn test_check_mode_fails_on_disputes_that_can_never_apply() {
+    let input = "type,client,tx,amount\ndeposit,1,1,2\ndispute,1,9,\nresolve,1,1,\n\
+                 withdrawal,1,2,5\ndispute,1,2,\ndispute,1,1,\ndispute,1,1,\n";
+
+    let output = run_with_stdin(&["-", "--check"], input);
+
+    assert!(!output.status.success());
+    let stderr = String::from_utf8(output.stderr).unwrap();
+    assert!(stderr
+        .contains("Failed check at line 3: ignored, the referenced transaction doesn't exist"));
+    assert!(stderr.contains(
+        "Failed check at line 4: ignored, the referenced transaction isn't under dispute"
+    ));
+    assert!(stderr
+        .contains("Failed check at line 6: ignored, the referenced transaction can't be disputed"));
+    //disputing an already disputed transaction might be a retry, it isn't a failure
+    assert!(stderr
+        .contains("Checked 7 record(s): 3 valid, 0 invalid record(s), 4 failed transaction(s)"));
+}
+
+#[test]
+fn test_discarded_records_are_reported_with_their_line_numbers() {
+    let input = "type,client,tx,amount\ndeposit,1,1,2\nbogus,1,2,1\nwithdrawal,1,3,5\n";
+
+    let output = run_with_stdin(&["-"], input);
+
+    let stderr = String::from_utf8(output.stderr).unwrap();
+    assert!(stderr.contains("Invalid record at line 3:"));
+    assert!(stderr.contains(
+        "Input parsing error at line 4: Not enough funds available for account 1! (INSUFFICIENT_FUNDS)"
+    ));
+    assert!(stderr.contains("first at line(s) 3, 4"));
+}
+
+#[test]
+fn test_failed_transactions_are_reported_with_their_message_and_code() {
+    let input = "type,client,tx,amount
+deposit,1,1,5
+withdrawal,1,2,1
+dispute,1,2,
+";
+
+    let output = run_with_stdin(&["-", "--deposit-disputes-only"], input);
+
+    assert!(String::from_utf8(output.stderr).unwrap().contains(
+        "Input parsing error at line 4: Transaction ID 2 refers to a withdrawal operation, \
+         only deposits might be disputed! (DISPUTE_TYPE_MISMATCH)"
+    ));
+}
+
+#[test]
+fn test_amounts_not_fitting_the_operation_type_are_rejected() {
+    let input = "type,client,tx,amount
+deposit,1,1,5
+deposit,1,2,
+dispute,1,1,3
+resolve,1,1,
+";
+
+    let output = run_with_stdin(&["-"], input);
+
+    assert!(output.status.success());
+    //neither the deposit nor the dispute were applied
+    assert_eq!(
+        String::from_utf8(output.stdout).unwrap(),
+        "client,available,held,total,locked\n1,5.0000,0.0000,5.0000,false\n"
+    );
+    let stderr = String::from_utf8(output.stderr).unwrap();
+    assert!(stderr.contains(
+        "Input parsing error at line 3: Transaction ID 2 has no amount! (MISSING_AMOUNT)"
+    ));
+    assert!(stderr.contains(
+        "Input parsing error at line 4: Transaction ID 1 carries an unexpected amount 3! (UNEXPECTED_AMOUNT)"
+    ));
+    assert!(stderr.contains("first at line(s) 3, 4"));
+}
+
+#[test]
+fn test_metrics_file_counts_records_per_client() {
+    let path = std::env::temp_dir().join(format!("metrics-{}.csv", std::process::id()));
+    let input = "type,client,tx,amount\ndeposit,2,1,2\ndeposit,1,2,2\nwithdrawal,1,3,5\ndispute,1,2,\nresolve,1,9,\nwithdrawal,2,4,1\ndispute,2,4,\n";
+
+    let output = run_with_stdin(&["-", "--metrics", path.to_str().unwrap()], input);
+    let metrics = std::fs::read_to_string(&path).unwrap();
+    std::fs::remove_file(&path).unwrap();
+
+    assert!(output.status.success());
+    assert_eq!(
+        metrics,
+        "client,deposits,withdrawals,disputes,resolves,chargebacks,ignored,rejected,unheld_withdrawal_disputes\n\
+         1,1,0,1,0,0,1,1,0\n\
+         2,1,1,1,0,0,0,0,1\n"
+    );
+    assert!(String::from_utf8(output.stderr).unwrap().contains(
+        "Warning: 1 dispute(s) of withdrawal(s) held no funds, as the withdrawal dispute mode is `none`"
+    ));
+}
+
+#[test]
+fn test_custom_delimiter_and_quote() {
+    let input = "type;client;tx;amount\ndeposit;1;1;'2.5'\nwithdrawal;1;2;1\n";
+
+    let output = run_with_stdin(&["-", "--delimiter", ";", "--quote", "'"], input);
+    let invalid = run_with_stdin(&["-", "--delimiter", "|"], input);
+
+    assert!(output.status.success());
+    assert_eq!(
+        String::from_utf8(output.stdout).unwrap(),
+        "client,available,held,total,locked\n1,1.5000,0.0000,1.5000,false\n"
+    );
+    assert!(!invalid.status.success());
+    assert!(String::from_utf8(invalid.stderr)
+        .unwrap()
+        .contains("the delimiter has to be one of"));
+}
+
+#[test]
+fn test_error_report_lists_discarded_records() {
+    let path = std::env::temp_dir().join(format!("error-report-cli-{}.csv", std::process::id()));
+    let input = "type,client,tx,amount\ndeposit,1,1,2\nbogus,1,2,1\nwithdrawal,1,3,5\n";
+
+    let output = run_with_stdin(&["-", "--error-report", path.to_str().unwrap()], input);
+    let report = std::fs::read_to_string(&path).unwrap();
+    std::fs::remove_file(&path).unwrap();
+
+    assert!(output.status.success());
+    let lines: Vec<&str> = report.lines().collect();
+    assert_eq!(lines.len(), 3);
+    assert_eq!(lines[0], "input,line,tx,reason");
+    assert!(lines[1].starts_with("-,3,,"));
+    assert_eq!(lines[2], "-,4,3,Not enough funds available for account 1!");
+}
+
+#[test]
+fn test_error_report_lists_silently_ignored_records() {
+    let path =
+        std::env::temp_dir().join(format!("error-report-ignored-{}.csv", std::process::id()));
+    let input =
+        "type,client,tx,amount\ndeposit,1,1,2\ndispute,1,9,\nresolve,1,1,\ndeposit,1,50,1\n";
+
+    let output = run_with_stdin(
+        &[
+            "-",
+            "--until-tx",
+            "10",
+            "--error-report",
+            path.to_str().unwrap(),
+        ],
+        input,
+    );
+    let report = std::fs::read_to_string(&path).unwrap();
+    std::fs::remove_file(&path).unwrap();
+
+    assert!(output.status.success());
+    assert_eq!(
+        report,
+        "input,line,tx,reason\n\
+         -,3,9,\"ignored, the referenced transaction doesn't exist\"\n\
+         -,4,1,\"ignored, the referenced transaction isn't under dispute\"\n\
+         -,5,50,\"ignored, the tx id is outside of the processed range\"\n"
+    );
+}
+
+#[test]
+fn test_error_report_is_written_when_the_processing_stops_early() {
+    let path = std::env::temp_dir().join(format!("error-report-early-{}.csv", std::process::id()));
+    let input = "type,client,tx,amount\ndeposit,1,1,2\nbogus,1,2,1\nwithdrawal,1,3,5\n";
+
+    for args in [&["--check"][..], &["--strict"][..]] {
+        let output = run_with_stdin(
+            &[args, &["-", "--error-report", path.to_str().unwrap()]].concat(),
+            input,
+        );
+        let report = std::fs::read_to_string(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+
+        assert!(!output.status.success());
+        assert!(report.starts_with("input,line,tx,reason\n-,3,,"));
+    }
+}
+
+#[test]
+fn test_limit_processes_only_the_first_records() {
+    let input = "type,client,tx,amount\ndeposit,1,1,2\nbogus,1,2,1\ndeposit,2,3,1\ndeposit,3,4,1\n";
+
+    let output = run_with_stdin(&["-", "--limit", "2"], input);
+
+    assert!(output.status.success());
+    assert_eq!(
+        String::from_utf8(output.stdout).unwrap(),
+        "client,available,held,total,locked\n1,2.0000,0.0000,2.0000,false\n2,1.0000,0.0000,1.0000,false\n"
+    );
+    assert!(String::from_utf8(output.stderr)
+        .unwrap()
+        .contains("Discarded 1 record(s) from -: 1 invalid record(s)"));
+}
+
+#[test]
+fn test_enforce_order_rejects_records_timestamped_in_the_past() {
+    let input = "type,client,tx,amount,timestamp\ndeposit,1,1,2,2024-01-01T10:00:00Z\ndeposit,1,2,1,2024-01-01T09:00:00Z\ndeposit,1,3,1,\n";
+
+    let output = run_with_stdin(&["-", "--enforce-order"], input);
+    assert!(output.status.success());
+    assert_eq!(
+        String::from_utf8(output.stdout).unwrap(),
+        "client,available,held,total,locked\n1,3.0000,0.0000,3.0000,false\n"
+    );
+
+    let output = run_with_stdin(&["-"], input);
+    assert!(output.status.success());
+    assert_eq!(
+        String::from_utf8(output.stdout).unwrap(),
+        "client,available,held,total,locked\n1,4.0000,0.0000,4.0000,false\n"
+    );
+}
+
+#[test]
+fn test_fail_on_discard_fails_after_writing_the_output() {
+    let input = "type,client,tx,amount\ndeposit,1,1,2\nbogus,1,2,1\nwithdrawal,1,3,5\n";
+
+    let output = run_with_stdin(&["-", "--fail-on-discard"], input);
+    assert!(!output.status.success());
+    assert_eq!(
+        String::from_utf8(output.stdout).unwrap(),
+        "client,available,held,total,locked\n1,2.0000,0.0000,2.0000,false\n"
+    );
+    assert!(String::from_utf8(output.stderr)
+        .unwrap()
+        .contains("Discarded 2 record(s) in total: 1 invalid record(s), 1 failed transaction(s)"));
+
+    let output = run_with_stdin(&["-"], input);
+    assert!(output.status.success());
+    let output = run_with_stdin(
+        &["-", "--fail-on-discard"],
+        "type,client,tx,amount\ndeposit,1,1,2\n",
+    );
+    assert!(output.status.success());
+}
+
+#[test]
+fn test_accounts_failing_to_serialize_are_reported() {
+    //the total of client 1 overflows, 5e28 is both held and available
+    let input = "type,client,tx,amount\ndeposit,1,1,50000000000\ndispute,1,1,\ndeposit,1,2,50000000000\ndeposit,2,3,1\n";
+    let scale = "1000000000000000000";
+
+    let output = run_with_stdin(&["-", "--scale-amounts", scale], input);
+    assert!(output.status.success());
+    assert_eq!(
+        String::from_utf8(output.stdout).unwrap(),
+        "client,available,held,total,locked\n2,1000000000000000000.0000,0.0000,1000000000000000000.0000,false\n"
+    );
+    assert!(String::from_utf8(output.stderr)
+        .unwrap()
+        .contains("Skipped 1 account(s) failing to serialize, of client(s) 1"));
+
+    let output = run_with_stdin(&["-", "--scale-amounts", scale, "--strict"], input);
+    assert!(!output.status.success());
+    assert!(String::from_utf8(output.stderr)
+        .unwrap()
+        .contains("Account of client 1 failed to serialize"));
+}
+
+#[test]
+fn test_only_flagged_outputs_accounts_with_held_funds_or_locked() {
+    let input = "type,client,tx,amount\ndeposit,1,1,2\ndeposit,2,2,3\ndispute,2,2,\ndeposit,3,3,4\ndispute,3,3,\nchargeback,3,3,\n";
+
+    let output = run_with_stdin(&["-", "--only-flagged"], input);
+    assert!(output.status.success());
+    assert_eq!(
+        String::from_utf8(output.stdout).unwrap(),
+        "client,available,held,total,locked\n2,0.0000,3.0000,3.0000,false\n3,0.0000,0.0000,0.0000,true\n"
+    );
+
+    let output = run_with_stdin(
+        &["-", "--only-flagged"],
+        "type,client,tx,amount\ndeposit,1,1,2\n",
+    );
+    assert!(output.status.success());
+    assert_eq!(
+        String::from_utf8(output.stdout).unwrap(),
+        "client,available,held,total,locked\n"
+    );
+}
+
+#[test]
+fn test_mistyped_header_fails_before_processing() {
+    let output = run_with_stdin(&["-"], "type,client,tx,ammount\ndeposit,1,1,2\n");
+
+    assert!(!output.status.success());
+    assert!(output.stdout.is_empty());
+    assert!(String::from_utf8(output.stderr)
+        .unwrap()
+        .contains("unexpected column(s): [`ammount`], missing column(s): [`amount`]"));
+}
+
+#[test]
+fn test_fraud_columns_are_added_on_request() {
+    let input = "type,client,tx,amount\ndeposit,1,1,2\ndeposit,1,2,3\ndispute,1,1,\nresolve,1,1,\ndispute,1,2,\nchargeback,1,2,\n";
+
+    let output = run_with_stdin(&["-", "--with-fraud-columns"], input);
+    assert!(output.status.success());
+    assert_eq!(
+        String::from_utf8(output.stdout).unwrap(),
+        "client,available,held,total,locked,dispute_count,chargeback_count\n1,2.0000,0.0000,2.0000,true,2,1\n"
+    );
+
+    let output = run_with_stdin(&["-"], input);
+    assert_eq!(
+        String::from_utf8(output.stdout).unwrap(),
+        "client,available,held,total,locked\n1,2.0000,0.0000,2.0000,true\n"
+    );
+}
+
+#[test]
+fn test_max_balance_rejects_deposits_above_the_cap() {
+    let input = "type,client,tx,amount\ndeposit,1,1,6\ndeposit,1,2,4\ndeposit,1,3,0.0001\ndeposit,2,4,10.0001\n";
+
+    let output = run_with_stdin(&["-", "--max-balance", "10"], input);
+    assert!(output.status.success());
+    assert_eq!(
+        String::from_utf8(output.stdout).unwrap(),
+        "client,available,held,total,locked\n1,10.0000,0.0000,10.0000,false\n2,0.0000,0.0000,0.0000,false\n"
+    );
+    assert!(String::from_utf8(output.stderr)
+        .unwrap()
+        .contains("Discarded 2 record(s) from -: 0 invalid record(s), 2 failed transaction(s)"));
+}
+
+#[test]
+fn test_on_overflow_saturate_clamps_instead_of_rejecting() {
+    let input = "type,client,tx,amount\ndeposit,1,1,50000000000000000000000000000.0\ndeposit,1,2,50000000000000000000000000000.0\n";
+
+    let rejected = run_with_stdin(&["-"], input);
+    assert!(rejected.status.success());
+    assert!(String::from_utf8(rejected.stderr)
+        .unwrap()
+        .contains("Discarded 1 record(s) from -: 0 invalid record(s), 1 failed transaction(s)"));
+
+    let saturated = run_with_stdin(&["-", "--on-overflow", "saturate"], input);
+    assert!(saturated.status.success());
+    assert_eq!(
+        String::from_utf8(saturated.stdout).unwrap(),
+        "client,available,held,total,locked\n1,79228162514264337593543950335.0000,0.0000,79228162514264337593543950335.0000,false\n"
+    );
+    assert!(String::from_utf8(saturated.stderr).unwrap().is_empty());
+}
+
+#[test]
+fn test_tx_range_skips_deposits_and_withdrawals_outside_of_it() {
+    let input = "type,client,tx,amount\ndeposit,1,1,1.0\ndeposit,1,2,2.0\nwithdrawal,1,3,0.5\ndeposit,1,4,4.0\ndispute,1,2,\n";
+
+    let output = run_with_stdin(&["-", "--since-tx", "2", "--until-tx", "3"], input);
+    assert!(output.status.success());
+    assert_eq!(
+        String::from_utf8(output.stdout).unwrap(),
+        "client,available,held,total,locked\n1,-0.5000,2.0000,1.5000,false\n"
+    );
+
+    let inverted = run_with_stdin(&["-", "--since-tx", "3", "--until-tx", "2"], input);
+    assert!(!inverted.status.success());
+    assert!(String::from_utf8(inverted.stderr)
+        .unwrap()
+        .contains("--since-tx 3 is above --until-tx 2"));
+}
+
+#[test]
+fn test_subcommands_process_validate_and_replay() {
+    let input = "type,client,tx,amount\ndeposit,1,1,1.0\ndeposit,1,2,2.0\ndeposit,1,3,4.0\n";
+    let snapshot =
+        std::env::temp_dir().join(format!("replay-snapshot-{}.json", std::process::id()));
+    let snapshot = snapshot.to_str().unwrap();
+    let bare = run_with_stdin(&["-"], input);
+    let processed = run_with_stdin(&["process", "-"], input);
+    let validated = run_with_stdin(&["validate", "-"], input);
+    //a run stopped right after the checkpoint, and the replay of the whole input on top of it
+    let interrupted = run_with_stdin(
+        &["process", "--save-snapshot", snapshot, "--limit", "2", "-"],
+        input,
+    );
+    let replayed = run_with_stdin(
+        &["replay", "--from", "2", "--snapshot", snapshot, "-"],
+        input,
+    );
+    std::fs::remove_file(snapshot).unwrap();
+
+    assert!(processed.status.success());
+    assert_eq!(processed.stdout, bare.stdout);
+    assert!(validated.status.success());
+    assert!(validated.stdout.is_empty());
+    assert!(interrupted.status.success());
+    assert!(replayed.status.success());
+    assert_eq!(replayed.stdout, bare.stdout);
+    assert_eq!(
+        String::from_utf8(replayed.stdout).unwrap(),
+        "client,available,held,total,locked\n1,7.0000,0.0000,7.0000,false\n"
+    );
+
+    let invalid = run_with_stdin(
+        &["validate", "-"],
+        "type,client,tx,amount\nwithdrawal,1,1,1.0\n",
+    );
+    assert!(!invalid.status.success());
+    let missing_from = run_with_stdin(&["replay", "-"], input);
+    assert!(!missing_from.status.success());
+    let missing_snapshot = run_with_stdin(&["replay", "--from", "2", "-"], input);
+    assert!(!missing_snapshot.status.success());
+    assert!(String::from_utf8(missing_snapshot.stderr)
+        .unwrap()
+        .contains("replay needs the --snapshot"));
+}
+
+#[test]
+fn test_since_tx_disputes_a_deposit_restored_from_a_snapshot() {
+    let snapshot = std::env::temp_dir().join(format!("since-snapshot-{}.json", std::process::id()));
+    let snapshot = snapshot.to_str().unwrap();
+    let saved = run_with_stdin(
+        &["--save-snapshot", snapshot, "-"],
+        "type,client,tx,amount\ndeposit,1,1,10\n",
+    );
+    let output = run_with_stdin(
+        &["--snapshot", snapshot, "--since-tx", "5", "-"],
+        "type,client,tx,amount\ndeposit,1,4,100\ndeposit,1,5,3\ndispute,1,1,\n",
+    );
+    std::fs::remove_file(snapshot).unwrap();
+
+    assert!(saved.status.success());
+    assert!(output.status.success());
+    assert_eq!(
+        String::from_utf8(output.stdout).unwrap(),
+        "client,available,held,total,locked\n1,3.0000,10.0000,13.0000,false\n"
+    );
+}
+
+#[test]
+fn test_currency_column_keeps_an_account_per_currency() {
+    let output = run_with_stdin(
+        &["-"],
+        "type,client,tx,amount,currency\ndeposit,1,1,10,USD\ndeposit,1,2,2,BTC\ndispute,1,2,,BTC\ndispute,1,1,,BTC\n",
+    );
+
+    assert!(output.status.success());
+    assert_eq!(
+        String::from_utf8(output.stdout).unwrap(),
+        "client,currency,available,held,total,locked\n\
+         1,BTC,0.0000,2.0000,2.0000,false\n\
+         1,USD,10.0000,0.0000,10.0000,false\n"
+    );
+}
+
+#[test]
+fn test_parallel_parse_matches_the_sequential_output() {
+    //spans several parsing batches, with invalid records and disputes referring to earlier ones
+    let mut input = String::from("type,client,tx,amount\n");
+    for tx in 1..=10_000u32 {
+        let client = tx % 97;
+        match tx % 10 {
+            0 => input.push_str(&format!("dispute,{},{},\n", client, tx - 9)),
+            5 => input.push_str(&format!("chargeback,{},{},\n", client, tx - 5)),
+            7 => input.push_str(&format!("bogus,{},{},1.0\n", client, tx)),
+            3 => input.push_str(&format!("withdrawal,{},{},{}.25\n", client, tx, tx % 7)),
+            _ => input.push_str(&format!("deposit,{},{},{}.5\n", client, tx, tx % 13)),
+        }
+    }
+
+    //read from a file, the logs of the discarded records would fill the stderr pipe while the
+    //standard input is still being written
+    let path = std::env::temp_dir().join(format!("parallel-parse-{}.csv", std::process::id()));
+    std::fs::write(&path, input).unwrap();
+    let run = |args: &[&str]| {
+        Command::new(env!("CARGO_BIN_EXE_payments-engine"))
+            .arg(&path)
+            .args(args)
+            .output()
+            .unwrap()
+    };
+    let sequential = run(&[]);
+    let parallel = run(&["--parallel-parse"]);
+    std::fs::remove_file(&path).unwrap();
+
+    assert!(sequential.status.success());
+    assert!(parallel.status.success());
+    assert_eq!(sequential.stdout, parallel.stdout);
+    assert_eq!(sequential.stderr, parallel.stderr);
+}
+
+#[test]
+fn test_pretty_prints_an_account_per_line() {
+    let output = run_with_stdin(
+        &["-", "--pretty"],
+        "type,client,tx,amount\ndeposit,2,1,1\ndeposit,1,2,12.34\ndispute,1,2,\n",
+    );
+
+    assert!(output.status.success());
+    assert_eq!(
+        String::from_utf8(output.stdout).unwrap(),
+        "client=1 available=0.0000 held=12.3400 total=12.3400 locked=false\n\
+         client=2 available=1.0000 held=0.0000 total=1.0000 locked=false\n"
+    );
+}
+
+#[test]
+fn test_progress_is_reported_to_stderr_only() {
+    let content = "type,client,tx,amount\ndeposit,1,1,1.0\ndeposit,1,2,2.0\n";
+    let path = std::env::temp_dir().join(format!("progress-{}.csv", std::process::id()));
+    std::fs::write(&path, content).unwrap();
+    let output = Command::new(env!("CARGO_BIN_EXE_payments-engine"))
+        .arg(&path)
+        .arg("--progress")
+        .output()
+        .unwrap();
+    std::fs::remove_file(&path).unwrap();
+
+    assert!(output.status.success());
+    assert_eq!(
+        String::from_utf8(output.stdout).unwrap(),
+        "client,available,held,total,locked\n1,3.0000,0.0000,3.0000,false\n"
+    );
+    assert!(String::from_utf8(output.stderr).unwrap().contains(&format!(
+        "Progress of {}: 2 record(s), {} byte(s) read (100.0%)",
+        path.display(),
+        content.len()
+    )));
+}
+
+// serves a single request with the given status and body, on a free local port
+#[cfg(feature = "http")]
+fn serve_once(status: &'static str, body: &'static str) -> String {
+    use std::io::{BufRead, BufReader};
+    let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+    let url = format!("http://{}/transactions.csv", listener.local_addr().unwrap());
+    std::thread::spawn(move || {
+        let (stream, _) = listener.accept().unwrap();
+        let mut request = BufReader::new(&stream);
+        let mut line = String::new();
+        while request.read_line(&mut line).unwrap() > 2 {
+            line.clear();
+        }
+        write!(
+            &stream,
+            "HTTP/1.1 {}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+            status,
+            body.len(),
+            body
+        )
+        .unwrap();
+    });
+    url
+}
+
+#[cfg(feature = "http")]
+#[test]
+fn test_input_is_streamed_from_an_http_url() {
+    let url = serve_once(
+        "200 OK",
+        "type,client,tx,amount\ndeposit,1,1,1.0\ndeposit,1,2,2.0\nwithdrawal,1,3,0.5\n",
+    );
+    let output = Command::new(env!("CARGO_BIN_EXE_payments-engine"))
+        .arg(&url)
+        .output()
+        .unwrap();
+
+    assert!(output.status.success());
+    assert_eq!(
+        String::from_utf8(output.stdout).unwrap(),
+        "client,available,held,total,locked\n1,2.5000,0.0000,2.5000,false\n"
+    );
+
+    let missing = serve_once("404 Not Found", "");
+    let output = Command::new(env!("CARGO_BIN_EXE_payments-engine"))
+        .arg(&missing)
+        .output()
+        .unwrap();
+    assert!(!output.status.success());
+    assert!(String::from_utf8(output.stderr).unwrap().contains(&missing));
+}
+
+#[cfg(not(feature = "http"))]
+#[test]
+fn test_url_input_requires_the_http_feature() {
+    let output = Command::new(env!("CARGO_BIN_EXE_payments-engine"))
+        .arg("https://example.com/transactions.csv")
+        .output()
+        .unwrap();
+
+    assert!(!output.status.success());
+    assert!(String::from_utf8(output.stderr)
+        .unwrap()
+        .contains("reading over HTTP(S) requires the `http` feature"));
+}
+
+#[test]
+fn test_deferred_records_never_matched_are_dropped_at_the_end_of_the_input() {
+    let input = "type,client,tx,amount\ndeposit,1,1,5\ndispute,1,9,\ndispute,1,1,\n";
+    let report = std::env::temp_dir().join(format!("deferred-report-{}.csv", std::process::id()));
+    let output = run_with_stdin(
+        &[
+            "--buffer-out-of-order",
+            "10",
+            "--report-ignored",
+            "--error-report",
+            report.to_str().unwrap(),
+            "-",
+        ],
+        input,
+    );
+    let content = std::fs::read_to_string(&report).unwrap();
+    std::fs::remove_file(&report).unwrap();
+
+    assert!(output.status.success());
+    assert_eq!(
+        String::from_utf8(output.stdout).unwrap(),
+        "client,available,held,total,locked\n1,0.0000,5.0000,5.0000,false\n"
+    );
+    assert!(String::from_utf8(output.stderr)
+        .unwrap()
+        .contains("Input parsing error at line 3 of -: Transaction ID 9 does not exist! (UNKNOWN_TRANSACTION)"));
+    assert_eq!(
+        content,
+        "input,line,tx,reason\n-,3,9,Transaction ID 9 does not exist!\n"
+    );
+
+    let output = run_with_stdin(&["--buffer-out-of-order", "10", "--strict", "-"], input);
+    assert!(!output.status.success());
+    assert!(String::from_utf8(output.stderr)
+        .unwrap()
+        .contains("Transaction 9 in - failed"));
+}
+
+#[test]
+fn test_reference_to_an_unknown_transaction_is_logged_by_default() {
+    let output = run_with_stdin(
+        &["-"],
+        "type,client,tx,amount\ndeposit,1,1,2\ndispute,1,9,\nresolve,1,1,\n",
+    );
+
+    assert!(output.status.success());
+    let stderr = String::from_utf8(output.stderr).unwrap();
+    assert_eq!(
+        stderr,
+        "Ignored record at line 3: Transaction ID 9 does not exist! (UNKNOWN_TRANSACTION)\n"
+    );
+}
+
+#[test]
+fn test_killed_and_resumed_processing_handles_each_record_exactly_once() {
+    let input = std::env::temp_dir().join(format!("resumed-{}.csv", std::process::id()));
+    //the offset file is named after the input
+    let offset = format!("{}.offset", input.display());
+    std::fs::write(
+        &input,
+        "type,client,tx,amount\ndeposit,1,1,5\ndeposit,1,3,3\ndeposit,1,4,1\n\
+         withdrawal,1,6,100\nbogus,1,2,1\ndeposit,2,5,7\n",
+    )
+    .unwrap();
+    let run = |args: &[&str]| {
+        Command::new(env!("CARGO_BIN_EXE_payments-engine"))
+            .arg(&input)
+            .args(args)
+            .output()
+            .unwrap()
+    };
+
+    //the first run stops at the failed withdrawal, as if it got killed, one record past the
+    //last persisted offset
+    let killed = run(&["--strict", "--persist-offset", "--offset-interval", "2"]);
+    let persisted = std::fs::read_to_string(&offset).unwrap();
+    let resumed = run(&["--resume-offset"]);
+    let processed = std::fs::read_to_string(&offset).unwrap();
+    let states = [2, 6].map(|n| std::path::PathBuf::from(format!("{}.{}.snapshot", offset, n)));
+    let kept = states
+        .iter()
+        .map(|state| state.exists())
+        .collect::<Vec<_>>();
+    for path in [&input, &std::path::PathBuf::from(&offset), &states[1]] {
+        std::fs::remove_file(path).unwrap();
+    }
+
+    assert!(!killed.status.success());
+    assert_eq!(persisted, "2");
+    assert!(resumed.status.success());
+    //the records of the first run are accounted for once, through the state it persisted
+    assert_eq!(
+        String::from_utf8(resumed.stdout).unwrap(),
+        "client,available,held,total,locked\n1,9.0000,0.0000,9.0000,false\n2,7.0000,0.0000,7.0000,false\n"
+    );
+    assert_eq!(processed, "6");
+    //only the state of the last offset is kept
+    assert_eq!(kept, vec![false, true]);
+}
+
+#[cfg(feature = "gzip")]
+#[test]
+fn test_gzip_compressed_output_decompresses_to_the_plain_one() {
+    use std::io::Read;
+    let input = "type,client,tx,amount\ndeposit,1,1,1.5\ndeposit,2,2,3\n";
+
+    let plain = run_with_stdin(&["-"], input);
+    let compressed = run_with_stdin(&["-", "--compress", "gzip"], input);
+
+    assert!(compressed.status.success());
+    assert_ne!(compressed.stdout, plain.stdout);
+    let mut decompressed = Vec::new();
+    flate2::read::GzDecoder::new(compressed.stdout.as_slice())
+        .read_to_end(&mut decompressed)
+        .unwrap();
+    assert_eq!(decompressed, plain.stdout);
+}
+
+#[cfg(feature = "zstd")]
+#[test]
+fn test_zstd_compressed_output_decompresses_to_the_plain_one() {
+    let input = "type,client,tx,amount\ndeposit,1,1,1.5\ndeposit,2,2,3\n";
+
+    let plain = run_with_stdin(&["-"], input);
+    let compressed = run_with_stdin(&["-", "--compress", "zstd"], input);
+
+    assert!(compressed.status.success());
+    assert_eq!(
+        zstd::decode_all(compressed.stdout.as_slice()).unwrap(),
+        plain.stdout
+    );
+}